@@ -1,4 +1,28 @@
 import('./pkg')
   .then(wasm => {
-    // nop
-});
\ No newline at end of file
+    const save = (name, href) => {
+      const link = document.createElement('a');
+      link.download = name;
+      link.href = href;
+      link.click();
+    };
+
+    // Animations come out as a numbered PNG sequence, one download per
+    // frame, followed by the whole loop as a Y4M video and a GIF.
+    const frames = wasm.frame_count();
+    if (frames === 0) {
+      return;
+    }
+    const canvas = document.getElementById('canvas');
+    const fps = 24;
+    const y4m = wasm.VideoWriter.y4m(fps);
+    const gif = wasm.VideoWriter.gif(fps);
+    for (let frame = 0; frame < frames; frame++) {
+      const rgb = wasm.draw_frame(frame);
+      save(`frame_${String(frame).padStart(4, '0')}.png`, canvas.toDataURL('image/png'));
+      y4m.add_frame(rgb);
+      gif.add_frame(rgb);
+    }
+    save('animation.y4m', URL.createObjectURL(new Blob([y4m.finish()])));
+    save('animation.gif', URL.createObjectURL(new Blob([gif.finish()], { type: 'image/gif' })));
+});
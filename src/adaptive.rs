@@ -0,0 +1,117 @@
+// Adaptive sampling: after a first pass over every pixel, more samples go
+// only to the pixels whose estimated error is still above a threshold.
+
+use crate::framebuffer::Framebuffer;
+use crate::utils::luminance;
+
+// Standard error of the mean of a pixel's samples, as it shows on screen.
+// Colors are drawn with gamma 2, so an error d in the luminance L moves the
+// drawn value by about d / (2 sqrt(L)).
+pub fn error(framebuffer: &Framebuffer, x: u32, y: u32) -> f64 {
+    let samples = framebuffer.samples(x, y);
+    if samples < 2 {
+        return f64::INFINITY;
+    }
+    let standard_error = (luminance(&framebuffer.variance(x, y)) / samples as f64).sqrt();
+    standard_error / (2. * luminance(&framebuffer.color(x, y)).max(0.).sqrt() + 0.01)
+}
+
+// Pixels with an error above `threshold` and fewer than `max_samples`
+// samples, worst first. A pixel counts as noisy if any of its neighbours
+// is, since a few samples can all agree by chance.
+pub fn noisy_pixels(
+    framebuffer: &Framebuffer,
+    threshold: f64,
+    max_samples: u32,
+) -> Vec<(u32, u32)> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut errors = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            errors.push(error(framebuffer, x, y));
+        }
+    }
+
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if framebuffer.samples(x, y) >= max_samples {
+                continue;
+            }
+            let mut worst = 0_f64;
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    worst = worst.max(errors[(ny * width + nx) as usize]);
+                }
+            }
+            if worst > threshold {
+                pixels.push((worst, x, y));
+            }
+        }
+    }
+    pixels.sort_by(|a, b| b.0.total_cmp(&a.0));
+    pixels.into_iter().map(|(_, x, y)| (x, y)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Checkpoint;
+    use crate::{resume_scene, Color, Mode, Settings, FIRST_PASS_SAMPLES, MAX_SAMPLES_SCALE};
+
+    #[test]
+    fn constant_pixels_are_not_noisy() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                for _ in 0..4 {
+                    framebuffer.add_sample(x, y, Color::new(0.2, 0.5, 0.7));
+                }
+            }
+        }
+        assert!(noisy_pixels(&framebuffer, 1e-9, 64).is_empty());
+
+        // one noisy pixel makes its neighbours noisy too, and no more samples
+        // go to pixels that have enough
+        framebuffer.add_sample(0, 0, Color::repeat(10.));
+        assert_eq!(
+            noisy_pixels(&framebuffer, 0.01, 64),
+            [(0, 0), (1, 0), (0, 1), (1, 1)]
+        );
+        assert!(noisy_pixels(&framebuffer, 0.01, 4).is_empty());
+    }
+
+    // An unreachable noise target on a small tile stops once every pixel has
+    // the most samples, which each pass doubles at most
+    #[test]
+    fn noise_target_stops_at_the_most_samples() {
+        let samples_per_pixel = 2;
+        let max_samples = MAX_SAMPLES_SCALE * samples_per_pixel;
+        let settings = Settings {
+            samples_per_pixel,
+            noise_threshold: 0.,
+            mode: Mode::Noise(1e-9),
+        };
+        let mut tile = Checkpoint::tile(settings, 3, 0, (560, 380), (6, 4));
+        let mut most = 0;
+        resume_scene("smoke", &mut tile, &mut |checkpoint, _| {
+            let framebuffer = &checkpoint.framebuffer;
+            let passes = checkpoint.pass;
+            let limit = if passes <= FIRST_PASS_SAMPLES {
+                passes
+            } else {
+                FIRST_PASS_SAMPLES << (passes - FIRST_PASS_SAMPLES)
+            };
+            for y in 0..framebuffer.height {
+                for x in 0..framebuffer.width {
+                    let samples = framebuffer.samples(x, y);
+                    assert!(samples <= limit.min(max_samples), "pass {}", passes);
+                    most = most.max(samples);
+                }
+            }
+        })
+        .unwrap();
+        assert_eq!(most, max_samples);
+        assert!(noisy_pixels(&tile.framebuffer, 1e-9, max_samples).is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+// Keyframed camera and object motion, sampled once per frame of an animation.
+
+use nalgebra::{Quaternion, Unit, UnitQuaternion, Vector3, Vector4};
+
+use crate::camera::PerspectiveCamera;
+use crate::instance::Pose;
+use crate::utils::deg_to_rad;
+
+// How a track moves between two keys
+#[derive(Clone, Copy)]
+pub enum Interpolation {
+    Linear,
+    // smooth curve through the keys, shaped by their neighbours
+    CatmullRom,
+    // cubic curve shaped by each key's handles; keys added without handles
+    // ease in and out
+    Bezier,
+}
+
+// Values a track can interpolate. Interpolations are weighted sums of up to
+// four values, with weights adding up to one.
+pub trait Animatable: Copy {
+    fn blend(values: [Self; 4], weights: [f64; 4]) -> Self;
+}
+
+impl Animatable for f64 {
+    fn blend(values: [Self; 4], weights: [f64; 4]) -> Self {
+        values.iter().zip(weights).map(|(v, w)| v * w).sum()
+    }
+}
+
+impl Animatable for Vector3<f64> {
+    fn blend(values: [Self; 4], weights: [f64; 4]) -> Self {
+        values.iter().zip(weights).map(|(v, w)| v * w).sum()
+    }
+}
+
+impl Animatable for Pose {
+    // Rotations are averaged as quaternions, which stays close to slerp as
+    // long as neighbouring keys turn by less than 90 degrees.
+    fn blend(values: [Self; 4], weights: [f64; 4]) -> Self {
+        let reference = values[1].rotation.coords;
+        let mut rotation = Vector4::zeros();
+        for (pose, w) in values.iter().zip(weights) {
+            let q = pose.rotation.coords;
+            rotation += if q.dot(&reference) < 0. { -q } else { q } * w;
+        }
+        Pose::new(
+            Vector3::blend(values.map(|pose| pose.translation), weights),
+            UnitQuaternion::from_quaternion(Quaternion::from(rotation)),
+            Vector3::blend(values.map(|pose| pose.scale), weights),
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+    // Bezier control values before and after the key
+    pub handles: (T, T),
+}
+
+// Keys of one animated value, held constant before the first and after the
+// last one.
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Animatable,
+{
+    pub fn new(interpolation: Interpolation) -> Self {
+        Track {
+            keys: Vec::new(),
+            interpolation,
+        }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Track::new(Interpolation::Linear).with_key(0., value)
+    }
+
+    pub fn with_key(self, time: f64, value: T) -> Self {
+        self.with_bezier_key(time, value, value, value)
+    }
+
+    // Key with the Bezier control values before and after it; the other
+    // interpolations ignore them.
+    pub fn with_bezier_key(mut self, time: f64, value: T, handle_in: T, handle_out: T) -> Self {
+        let index = self.keys.partition_point(|key| key.time <= time);
+        self.keys.insert(
+            index,
+            Keyframe {
+                time,
+                value,
+                handles: (handle_in, handle_out),
+            },
+        );
+        self
+    }
+
+    // Panics if the track has no keys.
+    pub fn sample(&self, time: f64) -> T {
+        let keys = &self.keys;
+        assert!(!keys.is_empty(), "Track needs at least one key");
+        let i = keys.partition_point(|key| key.time <= time);
+        if i == 0 {
+            return keys[0].value;
+        }
+        if i == keys.len() {
+            return keys[i - 1].value;
+        }
+
+        let (k0, k1) = (&keys[i - 1], &keys[i]);
+        let s = (time - k0.time) / (k1.time - k0.time);
+        match self.interpolation {
+            Interpolation::Linear => T::blend(
+                [k0.value, k1.value, k1.value, k1.value],
+                [1. - s, s, 0., 0.],
+            ),
+            // Uniform spline, with the end keys repeated.
+            // See https://en.wikipedia.org/wiki/Cubic_Hermite_spline#Catmull%E2%80%93Rom_spline
+            Interpolation::CatmullRom => {
+                let before = keys[i.saturating_sub(2)].value;
+                let after = keys[(i + 1).min(keys.len() - 1)].value;
+                let (s2, s3) = (s * s, s * s * s);
+                T::blend(
+                    [before, k0.value, k1.value, after],
+                    [
+                        (-s + 2. * s2 - s3) / 2.,
+                        (2. - 5. * s2 + 3. * s3) / 2.,
+                        (s + 4. * s2 - 3. * s3) / 2.,
+                        (-s2 + s3) / 2.,
+                    ],
+                )
+            }
+            Interpolation::Bezier => {
+                let r = 1. - s;
+                T::blend(
+                    [k0.value, k0.handles.1, k1.handles.0, k1.value],
+                    [r * r * r, 3. * s * r * r, 3. * s * s * r, s * s * s],
+                )
+            }
+        }
+    }
+}
+
+// Keyframed parameters of a PerspectiveCamera.
+pub struct CameraAnimation {
+    lookfrom: Track<Vector3<f64>>,
+    lookat: Track<Vector3<f64>>,
+    vfov: Track<f64>,
+    focus_dist: Track<f64>,
+    // degrees around vup through lookat, turning lookfrom
+    orbit: Track<f64>,
+    vup: Vector3<f64>,
+    aspect_ratio: f64,
+    aperture: f64,
+}
+
+impl CameraAnimation {
+    // A camera that stands still until tracks are set
+    pub fn new(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> Self {
+        CameraAnimation {
+            lookfrom: Track::constant(lookfrom),
+            lookat: Track::constant(lookat),
+            vfov: Track::constant(vfov),
+            focus_dist: Track::constant(focus_dist),
+            orbit: Track::constant(0.),
+            vup,
+            aspect_ratio,
+            aperture,
+        }
+    }
+
+    // Circles once around lookat from time 0 to 1, focused on it; render
+    // frames at times i / frames for a seamless loop.
+    pub fn turntable(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+    ) -> Self {
+        CameraAnimation::new(
+            lookfrom,
+            lookat,
+            vup,
+            vfov,
+            aspect_ratio,
+            aperture,
+            (lookfrom - lookat).norm(),
+        )
+        .with_orbit(
+            Track::new(Interpolation::Linear)
+                .with_key(0., 0.)
+                .with_key(1., 360.),
+        )
+    }
+
+    pub fn with_lookfrom(mut self, track: Track<Vector3<f64>>) -> Self {
+        self.lookfrom = track;
+        self
+    }
+
+    pub fn with_lookat(mut self, track: Track<Vector3<f64>>) -> Self {
+        self.lookat = track;
+        self
+    }
+
+    pub fn with_vfov(mut self, track: Track<f64>) -> Self {
+        self.vfov = track;
+        self
+    }
+
+    pub fn with_focus_dist(mut self, track: Track<f64>) -> Self {
+        self.focus_dist = track;
+        self
+    }
+
+    pub fn with_orbit(mut self, track: Track<f64>) -> Self {
+        self.orbit = track;
+        self
+    }
+
+    pub fn camera(&self, time: f64) -> PerspectiveCamera {
+        let lookat = self.lookat.sample(time);
+        let orbit = UnitQuaternion::from_axis_angle(
+            &Unit::new_normalize(self.vup),
+            deg_to_rad(self.orbit.sample(time)),
+        );
+        let lookfrom = lookat + orbit * (self.lookfrom.sample(time) - lookat);
+        PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            self.vup,
+            self.vfov.sample(time),
+            self.aspect_ratio,
+            self.aperture,
+            self.focus_dist.sample(time),
+        )
+    }
+}
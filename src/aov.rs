@@ -0,0 +1,225 @@
+// Auxiliary output buffers (AOVs) describing what the camera rays hit first,
+// for compositing and to guide denoisers.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::checkpoint::{
+    read_f64s, read_u32s, read_vectors, write_f64s, write_u32s, write_vectors,
+};
+use crate::hit::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::Color;
+
+pub struct Aovs {
+    width: u32,
+    albedo: Vec<Color>,
+    normal: Vec<Vector3<f64>>,
+    samples: Vec<u32>,
+    // depth and position only average the samples that hit something
+    depth: Vec<f64>,
+    position: Vec<Vector3<f64>>,
+    hits: Vec<u32>,
+    // ids of the first sample of each pixel, None for the background
+    object_id: Vec<Option<usize>>,
+    material_id: Vec<Option<usize>>,
+    // the material ids, numbered in the order the scene lists them
+    materials: HashMap<*const (), usize>,
+}
+
+impl Aovs {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Aovs {
+            width,
+            albedo: vec![Color::zeros(); len],
+            normal: vec![Vector3::zeros(); len],
+            samples: vec![0; len],
+            depth: vec![0.; len],
+            position: vec![Vector3::zeros(); len],
+            hits: vec![0; len],
+            object_id: vec![None; len],
+            material_id: vec![None; len],
+            materials: HashMap::new(),
+        }
+    }
+
+    // Numbers the materials of `world`. The scene lists them in the same
+    // order wherever and whenever it's built, so tiles and resumed renders
+    // agree on the ids.
+    pub(crate) fn number_materials<T: Hittable>(&mut self, world: &T) {
+        let mut materials = Vec::new();
+        world.materials(&mut materials);
+        self.materials.clear();
+        for material in &materials {
+            let count = self.materials.len();
+            self.materials
+                .entry(Rc::as_ptr(material) as *const ())
+                .or_insert(count);
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // Records the first hit of a camera ray, or the background color it sees.
+    pub fn add_sample(
+        &mut self,
+        x: u32,
+        y: u32,
+        ray: &Ray,
+        hit_record: Option<&HitRecord>,
+        background: Color,
+    ) {
+        let i = self.index(x, y);
+        let first = self.samples[i] == 0;
+        self.samples[i] += 1;
+        let hit_record = match hit_record {
+            Some(hit_record) => hit_record,
+            None => {
+                self.albedo[i] += background;
+                return;
+            }
+        };
+
+        self.albedo[i] += hit_record.material.albedo(hit_record);
+        self.normal[i] += hit_record.normal;
+        self.depth[i] += hit_record.t * ray.direction.norm();
+        self.position[i] += hit_record.p;
+        self.hits[i] += 1;
+        if first {
+            let material = Rc::as_ptr(&hit_record.material) as *const ();
+            self.object_id[i] = Some(hit_record.object_id);
+            self.material_id[i] = self.materials.get(&material).copied();
+        }
+    }
+
+    pub fn albedo(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        self.albedo[i] / self.samples[i].max(1) as f64
+    }
+
+    // Average shading normal, facing the camera; shorter than 1 along edges
+    pub fn normal(&self, x: u32, y: u32) -> Vector3<f64> {
+        let i = self.index(x, y);
+        self.normal[i] / self.samples[i].max(1) as f64
+    }
+
+    // Distance from the camera, infinite for the background
+    pub fn depth(&self, x: u32, y: u32) -> f64 {
+        let i = self.index(x, y);
+        match self.hits[i] {
+            0 => f64::INFINITY,
+            n => self.depth[i] / n as f64,
+        }
+    }
+
+    pub fn position(&self, x: u32, y: u32) -> Vector3<f64> {
+        let i = self.index(x, y);
+        self.position[i] / self.hits[i].max(1) as f64
+    }
+
+    pub fn object_id(&self, x: u32, y: u32) -> Option<usize> {
+        self.object_id[self.index(x, y)]
+    }
+
+    pub fn material_id(&self, x: u32, y: u32) -> Option<usize> {
+        self.material_id[self.index(x, y)]
+    }
+
+    // Copies the buffers of a tile starting at (x, y)
+    pub(crate) fn insert(&mut self, tile: &Aovs, x: u32, y: u32) {
+        let tile_height = tile.samples.len() as u32 / tile.width;
+        for ty in 0..tile_height {
+            for tx in 0..tile.width {
+                let (i, j) = (self.index(x + tx, y + ty), tile.index(tx, ty));
+                self.albedo[i] = tile.albedo[j];
+                self.normal[i] = tile.normal[j];
+                self.samples[i] = tile.samples[j];
+                self.depth[i] = tile.depth[j];
+                self.position[i] = tile.position[j];
+                self.hits[i] = tile.hits[j];
+                self.object_id[i] = tile.object_id[j];
+                self.material_id[i] = tile.material_id[j];
+            }
+        }
+    }
+
+    // The sums, for checkpoints
+    pub(crate) fn write_state<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let ids = |ids: &[Option<usize>]| -> Vec<u32> {
+            ids.iter()
+                .map(|id| id.map_or(u32::MAX, |id| id as u32))
+                .collect()
+        };
+        write_vectors(out, &self.albedo)?;
+        write_vectors(out, &self.normal)?;
+        write_u32s(out, &self.samples)?;
+        write_f64s(out, &self.depth)?;
+        write_vectors(out, &self.position)?;
+        write_u32s(out, &self.hits)?;
+        write_u32s(out, &ids(&self.object_id))?;
+        write_u32s(out, &ids(&self.material_id))
+    }
+
+    pub(crate) fn read_state<R: Read>(input: &mut R, width: u32, height: u32) -> io::Result<Self> {
+        let len = (width * height) as usize;
+        let ids = |ids: Vec<u32>| -> Vec<Option<usize>> {
+            ids.into_iter()
+                .map(|id| (id != u32::MAX).then_some(id as usize))
+                .collect()
+        };
+        Ok(Aovs {
+            width,
+            albedo: read_vectors(input, len)?,
+            normal: read_vectors(input, len)?,
+            samples: read_u32s(input, len)?,
+            depth: read_f64s(input, len)?,
+            position: read_vectors(input, len)?,
+            hits: read_u32s(input, len)?,
+            object_id: ids(read_u32s(input, len)?),
+            material_id: ids(read_u32s(input, len)?),
+            materials: HashMap::new(),
+        })
+    }
+}
+
+// A distinct color for every id, black for the background, to look at the
+// id buffers as images.
+pub fn id_color(id: Option<usize>) -> [u8; 3] {
+    match id {
+        None => [0, 0, 0],
+        Some(id) => {
+            // integer hash by Chris Wellons
+            let mut h = id as u32 ^ 0x9e37_79b9;
+            h = (h ^ (h >> 16)).wrapping_mul(0x7feb_352d);
+            h = (h ^ (h >> 15)).wrapping_mul(0x846c_a68b);
+            h ^= h >> 16;
+            [
+                (h >> 16) as u8 | 0x20,
+                (h >> 8) as u8 | 0x20,
+                h as u8 | 0x20,
+            ]
+        }
+    }
+}
+
+// Black through purple, orange and yellow to white for values in [0, 1], to
+// look at counts like the samples per pixel as heatmaps.
+pub fn heat_color(value: f64) -> [u8; 3] {
+    const STOPS: [[f64; 3]; 5] = [
+        [0., 0., 0.],
+        [60., 20., 130.],
+        [210., 50., 90.],
+        [250., 160., 20.],
+        [255., 255., 255.],
+    ];
+    let t = value.clamp(0., 1.) * (STOPS.len() - 1) as f64;
+    let i = (t as usize).min(STOPS.len() - 2);
+    let s = t - i as f64;
+    std::array::from_fn(|c| (STOPS[i][c] * (1. - s) + STOPS[i + 1][c] * s).round() as u8)
+}
@@ -0,0 +1,358 @@
+use std::rc::Rc;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::*;
+
+const DEFAULT_SUBDIVISIONS: usize = 8;
+const MAX_ITERATIONS: u32 = 10;
+
+// Cubic Bernstein polynomials and their derivatives at t
+fn bernstein(t: f64) -> ([f64; 4], [f64; 4]) {
+    let s = 1. - t;
+    (
+        [s * s * s, 3. * t * s * s, 3. * t * t * s, t * t * t],
+        [
+            -3. * s * s,
+            3. * s * s - 6. * t * s,
+            6. * t * s - 3. * t * t,
+            3. * t * t,
+        ],
+    )
+}
+
+// Control points of the part of a cubic Bezier curve between a and b,
+// from the blossoms f(a,a,a), f(a,a,b), f(a,b,b) and f(b,b,b).
+fn sub_curve(c: [Vector3<f64>; 4], a: f64, b: f64) -> [Vector3<f64>; 4] {
+    let blossom = |t: [f64; 3]| {
+        let mut p = c;
+        for (level, &t) in t.iter().enumerate() {
+            for i in 0..3 - level {
+                p[i] = p[i] * (1. - t) + p[i + 1] * t;
+            }
+        }
+        p[0]
+    };
+    [
+        blossom([a, a, a]),
+        blossom([a, a, b]),
+        blossom([a, b, b]),
+        blossom([b, b, b]),
+    ]
+}
+
+// Part of the patch over [u0, u1] x [v0, v1]. A patch lies within the
+// convex hull of its control points, so their box bounds it.
+struct SubPatch {
+    u0: f64,
+    u1: f64,
+    v0: f64,
+    v1: f64,
+    aabb: Aabb,
+}
+
+// Bicubic Bezier patch, intersected directly by Newton iteration.
+// control[i][j] is the control point i along u and j along v.
+// See Martin et al., "Practical Ray Tracing of Trimmed NURBS Surfaces"
+pub struct BezierPatch {
+    control: [[Vector3<f64>; 4]; 4],
+    material: Rc<dyn Material>,
+    // starting guesses for the iteration, each with its bounds
+    subpatches: Vec<SubPatch>,
+    aabb: Aabb,
+    // how close to the ray counts as converged, relative to the patch size
+    tolerance: f64,
+}
+
+impl BezierPatch {
+    pub fn new(control: [[Vector3<f64>; 4]; 4], material: Rc<dyn Material>) -> Self {
+        let mut patch = BezierPatch {
+            control,
+            material,
+            subpatches: Vec::new(),
+            aabb: Aabb::new(Vector3::zeros(), Vector3::zeros()),
+            tolerance: 0.,
+        };
+        patch.subdivide(DEFAULT_SUBDIVISIONS);
+        patch
+    }
+
+    // Splits the patch n x n ways to find starting points. Strongly curved
+    // patches need more subdivisions, or Newton may miss parts of them.
+    pub fn with_subdivisions(mut self, n: usize) -> Self {
+        self.subdivide(n.max(1));
+        self
+    }
+
+    fn subdivide(&mut self, n: usize) {
+        self.subpatches.clear();
+        for a in 0..n {
+            for b in 0..n {
+                let (u0, u1) = (a as f64 / n as f64, (a + 1) as f64 / n as f64);
+                let (v0, v1) = (b as f64 / n as f64, (b + 1) as f64 / n as f64);
+                // split the rows along v, then the resulting columns along u
+                let rows = self.control.map(|row| sub_curve(row, v0, v1));
+                let mut aabb: Option<Aabb> = None;
+                for j in 0..4 {
+                    let column = sub_curve(rows.map(|row| row[j]), u0, u1);
+                    for p in column {
+                        let point = Aabb::new(p, p);
+                        aabb = Some(aabb.map_or(point, |aabb| aabb.surrounding(&point)));
+                    }
+                }
+                self.subpatches.push(SubPatch {
+                    u0,
+                    u1,
+                    v0,
+                    v1,
+                    aabb: aabb.unwrap().padded(0.0001),
+                });
+            }
+        }
+        self.aabb = self
+            .subpatches
+            .iter()
+            .map(|sub| sub.aabb)
+            .reduce(|a, b| a.surrounding(&b))
+            .unwrap();
+        self.tolerance = 1e-7 * (self.aabb.max - self.aabb.min).norm();
+    }
+
+    // Point and partial derivatives along u and v
+    pub fn evaluate(&self, u: f64, v: f64) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+        let (bu, dbu) = bernstein(u);
+        let (bv, dbv) = bernstein(v);
+        let mut p = Vector3::zeros();
+        let mut pu = Vector3::zeros();
+        let mut pv = Vector3::zeros();
+        for i in 0..4 {
+            for j in 0..4 {
+                let c = self.control[i][j];
+                p += bu[i] * bv[j] * c;
+                pu += dbu[i] * bv[j] * c;
+                pv += bu[i] * dbv[j] * c;
+            }
+        }
+        (p, pu, pv)
+    }
+
+    // Analytic normal, nudged inwards where the patch degenerates to a point
+    // (like the top of the teapot lid).
+    fn normal(&self, u: f64, v: f64) -> Vector3<f64> {
+        let (_, pu, pv) = self.evaluate(u, v);
+        let n = pu.cross(&pv);
+        if n.norm() > 1e-12 {
+            return n.normalize();
+        }
+        let nudge = |x: f64| x + (0.5 - x) * 1e-4;
+        let (_, pu, pv) = self.evaluate(nudge(u), nudge(v));
+        pu.cross(&pv).normalize()
+    }
+
+    // Newton iteration from the middle of `sub` on the distance of the patch
+    // to the two planes crossing along the ray. Returns t, u and v.
+    fn newton(
+        &self,
+        ray: &Ray,
+        planes: &[(Vector3<f64>, f64); 2],
+        sub: &SubPatch,
+    ) -> Option<(f64, f64, f64)> {
+        let (mut u, mut v) = ((sub.u0 + sub.u1) / 2., (sub.v0 + sub.v1) / 2.);
+        for _ in 0..MAX_ITERATIONS {
+            let (p, pu, pv) = self.evaluate(u, v);
+            let [(n1, d1), (n2, d2)] = planes;
+            let (f1, f2) = (n1.dot(&p) + d1, n2.dot(&p) + d2);
+            if f1.abs() < self.tolerance && f2.abs() < self.tolerance {
+                // any converged point on the patch is a real hit, even if
+                // it isn't in the subpatch the iteration started from
+                if !(0. ..=1.).contains(&u) || !(0. ..=1.).contains(&v) {
+                    return None;
+                }
+                let t = (p - ray.origin).dot(&ray.direction) / ray.direction.norm_squared();
+                return Some((t, u, v));
+            }
+            let (a, b, c, d) = (n1.dot(&pu), n1.dot(&pv), n2.dot(&pu), n2.dot(&pv));
+            let det = a * d - b * c;
+            if det.abs() < 1e-14 {
+                return None;
+            }
+            u -= (d * f1 - b * f2) / det;
+            v -= (a * f2 - c * f1) / det;
+            // diverging far away from the patch
+            if !(-1. ..=2.).contains(&u) || !(-1. ..=2.).contains(&v) {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Patches in the classic teapot format: the patch count, 16 one-based
+    // control point indices per patch, the vertex count, then x y z per
+    // vertex, separated by commas or whitespace.
+    pub fn parse(text: &str, material: Rc<dyn Material>) -> Result<Vec<BezierPatch>, String> {
+        let mut tokens = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty());
+        let mut next = |name: &str| {
+            tokens
+                .next()
+                .ok_or(format!("unexpected end of patch file, expected {}", name))
+        };
+        let count = |token: &str| {
+            token
+                .parse::<usize>()
+                .map_err(|_| format!("invalid count: {}", token))
+        };
+
+        // the counts aren't trusted to size anything, the tokens run out first
+        let patch_count = count(next("patch count")?)?;
+        let mut indices = Vec::new();
+        for _ in 0..patch_count {
+            let mut patch = [0; 16];
+            for index in patch.iter_mut() {
+                *index = count(next("control point index")?)?;
+            }
+            indices.push(patch);
+        }
+
+        let vertex_count = count(next("vertex count")?)?;
+        let mut vertices = Vec::new();
+        for _ in 0..vertex_count {
+            let mut vertex = Vector3::zeros();
+            for i in 0..3 {
+                let token = next("coordinate")?;
+                vertex[i] = token
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid coordinate: {}", token))?;
+            }
+            vertices.push(vertex);
+        }
+
+        indices
+            .into_iter()
+            .map(|patch| {
+                let mut control = [[Vector3::zeros(); 4]; 4];
+                for (k, &index) in patch.iter().enumerate() {
+                    if index == 0 || index > vertices.len() {
+                        return Err(format!("control point index out of range: {}", index));
+                    }
+                    control[k / 4][k % 4] = vertices[index - 1];
+                }
+                Ok(BezierPatch::new(control, Rc::clone(&material)))
+            })
+            .collect()
+    }
+
+    // Surface of revolution around the Y axis of a cubic Bezier profile given
+    // as (radius, height) points, as four quarter patches. The circle is the
+    // usual cubic approximation, off by less than 0.03% of the radius.
+    pub fn revolve(profile: [Vector2<f64>; 4], material: Rc<dyn Material>) -> Vec<BezierPatch> {
+        // tangent length of a quarter circle
+        let k = 4. / 3. * (2_f64.sqrt() - 1.);
+        let directions = [
+            Vector2::new(1., 0.),
+            Vector2::new(0., 1.),
+            Vector2::new(-1., 0.),
+            Vector2::new(0., -1.),
+        ];
+        (0..4)
+            .map(|q| {
+                let (d0, d1) = (directions[q], directions[(q + 1) % 4]);
+                let arc = [d0, d0 + k * d1, d1 + k * d0, d1];
+                let control = profile.map(|p| arc.map(|c| Vector3::new(p.x * c.x, p.y, p.x * c.y)));
+                BezierPatch::new(control, Rc::clone(&material))
+            })
+            .collect()
+    }
+}
+
+impl Hittable for BezierPatch {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        self.aabb.interval(ray, t_min, t_max)?;
+        // the ray as the line where two planes n . p + d = 0 meet
+        let (n1, n2) = orthonormal_basis(&ray.direction.normalize());
+        let planes = [(n1, -n1.dot(&ray.origin)), (n2, -n2.dot(&ray.origin))];
+
+        let mut closest: Option<(f64, f64, f64)> = None;
+        for sub in &self.subpatches {
+            let t_far = closest.map_or(t_max, |(t, _, _)| t);
+            if sub.aabb.interval(ray, t_min, t_far).is_none() {
+                continue;
+            }
+            if let Some((t, u, v)) = self.newton(ray, &planes, sub) {
+                if (t_min..=t_far).contains(&t) {
+                    closest = Some((t, u, v));
+                }
+            }
+        }
+
+        let (t, u, v) = closest?;
+        Some(HitRecord::new(
+            ray,
+            t,
+            &self.normal(u, v),
+            (u, v),
+            Rc::clone(&self.material),
+        ))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.aabb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::Color;
+
+    fn parse(text: &str) -> Result<Vec<BezierPatch>, String> {
+        BezierPatch::parse(text, Rc::new(Lambertian::new(Color::repeat(0.5))))
+    }
+
+    // One flat patch over 16 vertices on a 4x4 grid, and what to append
+    fn patch_file(extra: &str) -> String {
+        let indices: Vec<String> = (1..=16).map(|i| i.to_string()).collect();
+        let vertices: Vec<String> = (0..16)
+            .map(|i| format!("{}, 0, {}", i % 4, i / 4))
+            .collect();
+        format!(
+            "1\n{}\n16\n{}\n{}",
+            indices.join(", "),
+            vertices.join("\n"),
+            extra
+        )
+    }
+
+    #[test]
+    fn parse_patches() {
+        let patches = parse(&patch_file("")).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].control[3][1], Vector3::new(1., 0., 3.));
+        let aabb = patches[0].bounding_box().unwrap();
+        assert!(aabb.min.x <= 0. && aabb.max.z >= 3.);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let file = patch_file("");
+        // cut off in the middle of the vertices
+        assert!(parse(&file[..file.len() - 10]).is_err());
+        assert!(parse("").is_err());
+        assert!(parse(&file.replacen("16\n16\n", "17\n16\n", 1)).is_err());
+        assert!(parse(&file.replacen("1,", "0,", 1)).is_err());
+        assert!(parse(&file.replacen("0, 0", "zero, 0", 1)).is_err());
+        // counts far beyond the file
+        assert!(parse("100000000000000000 1 2 3").is_err());
+        assert!(parse(&file.replacen("\n16\n", "\n100000000000000000\n", 1)).is_err());
+    }
+}
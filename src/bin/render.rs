@@ -0,0 +1,378 @@
+// Renders the scene or the animation chosen in src/lib.rs outside the
+// browser, into every file named on the command line:
+//
+//     cargo run --release --bin render -- [--frames N] [--fps N] [--samples N] [--noise-threshold X]
+//         [--time SECONDS | --noise-target X | --sample-budget N] [--denoise] [--aovs PREFIX]
+//         [--checkpoint FILE] [--checkpoint-interval SECONDS] [--resume FILE]
+//         [--seed N] [--coordinator ADDRESS] [--workers N]
+//         out.y4m out.gif out.ppm out.png out.pfm
+//     cargo run --release --bin render -- --worker ADDRESS
+//
+// The format follows the extension; .ppm, .png and .pfm get the still, or one
+// numbered file per frame of an animation. --samples and --noise-threshold
+// override SAMPLES_PER_PIXEL and NOISE_THRESHOLD. --time, --noise-target and
+// --sample-budget stop every frame after that many seconds, once no pixel
+// is noisier, or after that many samples in all, instead. --denoise filters the
+// images with the auxiliary buffers as guides. --aovs also writes the
+// auxiliary buffers next to them: PREFIX_albedo.pfm, PREFIX_normal.pfm,
+// PREFIX_depth.pfm, PREFIX_position.pfm, PREFIX_variance.pfm,
+// PREFIX_object_id.ppm, PREFIX_material_id.ppm and PREFIX_samples.ppm, a
+// heatmap of the samples taken in every pixel.
+//
+// --checkpoint saves the still render every 60 seconds, or as often as
+// --checkpoint-interval says, and when it is done. --resume adds samples to
+// a saved render, with its settings unless the command line changes them:
+// with the same budget it finishes an interrupted render, with a bigger one
+// it refines a finished one. --seed makes the still come out the same on
+// every run.
+//
+// --coordinator ADDRESS renders the still in tiles on the workers that
+// connect to ADDRESS, like 0.0.0.0:7878 for the whole LAN, with `render
+// --worker HOST:7878` on every machine. --workers N also starts N worker
+// processes on this one, listening on a free local port when --coordinator
+// isn't given.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::process::{self, Command};
+use std::time::Instant;
+
+use raytracing::aov::{heat_color, id_color};
+use raytracing::checkpoint::Checkpoint;
+use raytracing::denoise::Denoiser;
+use raytracing::distributed::{coordinate, work};
+use raytracing::framebuffer::{rgb8, to_rgb8, write_pfm, write_ppm, Framebuffer};
+use raytracing::gif::GifWriter;
+use raytracing::png::write_png;
+use raytracing::video::Y4mWriter;
+use raytracing::Color;
+use raytracing::{frame_count, framebuffer_size, render_frame, resume_still, Mode, Settings};
+
+const USAGE: &str = "usage: render [--frames N] [--fps N] [--samples N] [--noise-threshold X] \
+                     [--time SECONDS | --noise-target X | --sample-budget N] \
+                     [--denoise] [--aovs PREFIX] [--checkpoint FILE] \
+                     [--checkpoint-interval SECONDS] [--resume FILE] [--seed N] \
+                     [--coordinator ADDRESS] [--workers N] OUTPUT.{y4m,gif,ppm,png,pfm}...\n\
+                     \x20      render --worker ADDRESS";
+
+enum Output {
+    Y4m(Y4mWriter<BufWriter<File>>),
+    Gif(GifWriter<BufWriter<File>>),
+    // paths without their extension
+    Ppm(String),
+    Png(String),
+    Pfm(String),
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut frames = frame_count();
+    let mut fps = 24;
+    // settings from the command line, over those of a resumed render
+    let mut samples_per_pixel = None;
+    let mut noise_threshold = None;
+    let mut mode = None;
+    let mut denoise = false;
+    let mut aovs = None;
+    let mut checkpoint = None;
+    let mut checkpoint_interval = 60.;
+    let mut resume = None;
+    let mut seed = None;
+    let mut coordinator = None;
+    let mut workers = 0;
+    let mut worker = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_default();
+        let number = |value: String| {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("{} needs a number\n{}", arg, USAGE))
+        };
+        let positive = |value: String| {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|&v| v > 0.)
+                .ok_or(format!("{} needs a positive number\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--frames" => frames = number(value())?,
+            "--fps" => fps = number(value())?.max(1),
+            "--samples" => samples_per_pixel = Some(number(value())?.max(1)),
+            "--noise-threshold" => noise_threshold = Some(positive(value())?),
+            "--time" => mode = Some(Mode::Time(positive(value())?)),
+            "--noise-target" => mode = Some(Mode::Noise(positive(value())?)),
+            "--sample-budget" => {
+                mode = Some(Mode::SampleBudget(
+                    value()
+                        .parse()
+                        .map_err(|_| format!("{} needs a number\n{}", arg, USAGE))?,
+                ))
+            }
+            "--denoise" => denoise = true,
+            "--aovs" => aovs = Some(args.next().ok_or(USAGE.to_string())?),
+            "--checkpoint" => checkpoint = Some(args.next().ok_or(USAGE.to_string())?),
+            "--checkpoint-interval" => checkpoint_interval = positive(value())?,
+            "--resume" => resume = Some(args.next().ok_or(USAGE.to_string())?),
+            "--seed" => {
+                seed = Some(
+                    value()
+                        .parse::<u64>()
+                        .map_err(|_| format!("{} needs a number\n{}", arg, USAGE))?,
+                )
+            }
+            "--coordinator" => coordinator = Some(args.next().ok_or(USAGE.to_string())?),
+            "--workers" => workers = number(value())?,
+            "--worker" => worker = Some(args.next().ok_or(USAGE.to_string())?),
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => paths.push(arg),
+        }
+    }
+    if let Some(address) = worker {
+        return work(&address).map_err(|e| format!("{}: {}", address, e));
+    }
+    if paths.is_empty() && aovs.is_none() && checkpoint.is_none() {
+        return Err(USAGE.to_string());
+    }
+    if frames > 0 && (checkpoint.is_some() || resume.is_some()) {
+        return Err("checkpoints are only for the still, not animations".to_string());
+    }
+    let distributed = coordinator.is_some() || workers > 0;
+    if distributed && (frames > 0 || checkpoint.is_some() || resume.is_some()) {
+        return Err("only the still renders distributed, without checkpoints".to_string());
+    }
+    if distributed && matches!(mode, Some(Mode::Time(_))) {
+        return Err("distributed renders can't stop after a time limit".to_string());
+    }
+    let with_overrides = |mut settings: Settings| {
+        if let Some(samples_per_pixel) = samples_per_pixel {
+            settings.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(noise_threshold) = noise_threshold {
+            settings.noise_threshold = noise_threshold;
+        }
+        if let Some(mode) = mode {
+            settings.mode = mode;
+        }
+        settings
+    };
+
+    let (width, height) = framebuffer_size();
+    let create = |path: &str| {
+        File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("{}: {}", path, e))
+    };
+    let mut outputs = Vec::new();
+    for path in &paths {
+        let output = match path.rsplit_once('.') {
+            Some((_, "y4m")) => Output::Y4m(
+                Y4mWriter::new(create(path)?, width, height, fps).map_err(|e| e.to_string())?,
+            ),
+            Some((_, "gif")) => Output::Gif(
+                GifWriter::new(create(path)?, width, height, fps).map_err(|e| e.to_string())?,
+            ),
+            Some((stem, "ppm")) => Output::Ppm(stem.to_string()),
+            Some((stem, "png")) => Output::Png(stem.to_string()),
+            Some((stem, "pfm")) => Output::Pfm(stem.to_string()),
+            _ => return Err(format!("{}: unknown format\n{}", path, USAGE)),
+        };
+        outputs.push(output);
+    }
+
+    // 0 frames means the still
+    for frame in 0..frames.max(1) {
+        let framebuffer = if frames > 0 {
+            render_frame(frame, frames, &with_overrides(Settings::default()))
+        } else if distributed {
+            render_distributed(
+                coordinator.as_deref(),
+                workers,
+                with_overrides(Settings::default()),
+                seed.unwrap_or_else(rand::random),
+            )?
+        } else {
+            let mut still = match (&resume, seed) {
+                (Some(path), _) => load_checkpoint(path)?,
+                (None, Some(seed)) => {
+                    Checkpoint::new(Settings::default(), width, height).with_seed(seed)
+                }
+                (None, None) => Checkpoint::new(Settings::default(), width, height),
+            };
+            if (still.framebuffer.width, still.framebuffer.height) != (width, height) {
+                return Err(format!(
+                    "the checkpoint is {}x{} but the still is {}x{}",
+                    still.framebuffer.width, still.framebuffer.height, width, height
+                ));
+            }
+            still.settings = with_overrides(still.settings);
+            let mut saved = Instant::now();
+            resume_still(&mut still, &mut |still, _| {
+                if let Some(path) = &checkpoint {
+                    if saved.elapsed().as_secs_f64() >= checkpoint_interval {
+                        // a failed save shouldn't end a long render
+                        if let Err(e) = save_checkpoint(path, still) {
+                            eprintln!("{}", e);
+                        }
+                        saved = Instant::now();
+                    }
+                }
+            });
+            if let Some(path) = &checkpoint {
+                save_checkpoint(path, &still)?;
+            }
+            still.framebuffer
+        };
+        let colors = if denoise {
+            Denoiser::new().denoise(&framebuffer)
+        } else {
+            framebuffer.colors()
+        };
+        let color = |x: u32, y: u32| colors[(y * width + x) as usize];
+        let rgb = rgb8(&colors);
+        for output in &mut outputs {
+            match output {
+                Output::Y4m(writer) => writer.write_frame(&rgb),
+                Output::Gif(writer) => writer.write_frame(&rgb),
+                Output::Ppm(stem) => {
+                    let out = create(&numbered(stem, frame, frames, "ppm"))?;
+                    write_ppm(out, width, height, |x, y| to_rgb8(color(x, y)))
+                }
+                Output::Png(stem) => {
+                    let out = create(&numbered(stem, frame, frames, "png"))?;
+                    write_png(out, width, height, |x, y| to_rgb8(color(x, y)))
+                }
+                Output::Pfm(stem) => {
+                    let out = create(&numbered(stem, frame, frames, "pfm"))?;
+                    write_pfm(out, width, height, color)
+                }
+            }
+            .map_err(|e| e.to_string())?;
+        }
+        if let Some(prefix) = &aovs {
+            write_aovs(&framebuffer, prefix, frame, frames)?;
+        }
+    }
+
+    for output in outputs {
+        match output {
+            Output::Y4m(writer) => writer.finish().map(drop),
+            Output::Gif(writer) => writer.finish().map(drop),
+            Output::Ppm(_) | Output::Png(_) | Output::Pfm(_) => Ok(()),
+        }
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// The still, rendered by `workers` worker processes started here and any
+// others that connect to `address`
+fn render_distributed(
+    address: Option<&str>,
+    workers: u32,
+    settings: Settings,
+    seed: u64,
+) -> Result<Framebuffer, String> {
+    let address = address.unwrap_or("127.0.0.1:0");
+    let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+    let local = listener.local_addr().map_err(|e| e.to_string())?;
+    // workers on this machine connect over the loopback interface
+    let ip = match local.ip() {
+        ip if !ip.is_unspecified() => ip,
+        IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+        IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+    };
+    let local = SocketAddr::new(ip, local.port());
+    eprintln!("waiting for workers on port {}", local.port());
+
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut children = Vec::new();
+    for _ in 0..workers {
+        let child = Command::new(&exe)
+            .arg("--worker")
+            .arg(local.to_string())
+            .spawn()
+            .map_err(|e| format!("{}: {}", exe.display(), e))?;
+        children.push(child);
+    }
+    let framebuffer = coordinate(listener, settings, seed).map_err(|e| e.to_string());
+    for mut child in children {
+        // they stop once they hear that the tiles are done
+        let _ = child.wait();
+    }
+    framebuffer
+}
+
+fn load_checkpoint(path: &str) -> Result<Checkpoint, String> {
+    File::open(path)
+        .and_then(|input| Checkpoint::read(BufReader::new(input)))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+// Replaces the file only once the new checkpoint is complete, so dying
+// while saving doesn't lose the previous one.
+fn save_checkpoint(path: &str, checkpoint: &Checkpoint) -> Result<(), String> {
+    let partial = format!("{}.partial", path);
+    File::create(&partial)
+        .and_then(|out| checkpoint.write(BufWriter::new(out)))
+        .and_then(|()| fs::rename(&partial, path))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+// path.extension, numbered for animations
+fn numbered(stem: &str, frame: u32, frames: u32, extension: &str) -> String {
+    if frames == 0 {
+        format!("{}.{}", stem, extension)
+    } else {
+        format!("{}_{:04}.{}", stem, frame, extension)
+    }
+}
+
+fn write_aovs(
+    framebuffer: &Framebuffer,
+    prefix: &str,
+    frame: u32,
+    frames: u32,
+) -> Result<(), String> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let aovs = &framebuffer.aovs;
+    let gray = |v: f64| Color::new(v, v, v);
+    let mut max_samples = 1;
+    for y in 0..height {
+        for x in 0..width {
+            max_samples = max_samples.max(framebuffer.samples(x, y));
+        }
+    }
+    let pfm = |name: &str, pixel: &dyn Fn(u32, u32) -> Color| {
+        let path = numbered(&format!("{}_{}", prefix, name), frame, frames, "pfm");
+        let out = File::create(&path).map(BufWriter::new);
+        out.and_then(|out| write_pfm(out, width, height, pixel))
+            .map_err(|e| format!("{}: {}", path, e))
+    };
+    let ppm = |name: &str, pixel: &dyn Fn(u32, u32) -> [u8; 3]| {
+        let path = numbered(&format!("{}_{}", prefix, name), frame, frames, "ppm");
+        let out = File::create(&path).map(BufWriter::new);
+        out.and_then(|out| write_ppm(out, width, height, pixel))
+            .map_err(|e| format!("{}: {}", path, e))
+    };
+
+    pfm("albedo", &|x, y| aovs.albedo(x, y))?;
+    pfm("normal", &|x, y| aovs.normal(x, y))?;
+    pfm("depth", &|x, y| gray(aovs.depth(x, y)))?;
+    pfm("position", &|x, y| aovs.position(x, y))?;
+    pfm("variance", &|x, y| framebuffer.variance(x, y))?;
+    ppm("object_id", &|x, y| id_color(aovs.object_id(x, y)))?;
+    ppm("material_id", &|x, y| id_color(aovs.material_id(x, y)))?;
+    ppm("samples", &|x, y| {
+        heat_color(framebuffer.samples(x, y) as f64 / max_samples as f64)
+    })
+}
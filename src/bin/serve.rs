@@ -0,0 +1,608 @@
+// A local HTTP service that renders stills for other programs, like a web
+// dashboard, one job at a time in the order they came:
+//
+//     cargo run --release --bin serve -- [--allow-origin ORIGIN] [ADDRESS]
+//
+// It listens on 127.0.0.1:8000 unless ADDRESS says otherwise. There's no
+// authentication, so web pages may only use it from the one ORIGIN allowed,
+// like http://localhost:3000, and browsers keep every other page out.
+//
+//     POST /jobs             queues a job and answers {"id": N}
+//     GET /jobs              every job, as in GET /jobs/N
+//     GET /jobs/N            {"id", "scene", "state", "progress", "error"}
+//     GET /jobs/N/image      the image of a job that is done
+//     DELETE /jobs/N         forgets a job that isn't rendering
+//     GET /scenes            the names of the built-in scenes
+//
+// Jobs that are done or failed are forgotten an hour later, or once 16
+// newer ones have finished, so fetch their images before then.
+//
+// A job is a JSON object like
+//
+//     {"scene": "image21", "samples": 8, "noise_threshold": 0.01,
+//      "time": 60, "seed": 5, "denoise": true, "format": "png"}
+//
+// where only "scene" is required: the name of one of the scenes in
+// src/lib.rs, or a scene of its own described as in src/description.rs. The
+// others have the meaning of the render flags of the same names, with
+// "noise_target" or "sample_budget" in place of "time"; "format" is "png",
+// the default, or "pfm" for the linear HDR colors. The state goes from
+// "queued" over "rendering" to "done" or "failed", and the progress is the
+// percentage of the render completed.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use raytracing::checkpoint::Checkpoint;
+use raytracing::denoise::Denoiser;
+use raytracing::description;
+use raytracing::framebuffer::{to_rgb8, write_pfm};
+use raytracing::json::Json;
+use raytracing::png::write_png;
+use raytracing::{framebuffer_size, resume_scene, resume_with, Info, Mode, Settings, SCENES};
+
+const USAGE: &str = "usage: serve [--allow-origin ORIGIN] [ADDRESS]";
+// Bigger requests are refused, jobs are a few hundred bytes
+const MAX_BODY: usize = 1 << 20;
+// Longer request lines and headers are refused
+const MAX_HEAD: u64 = 64 << 10;
+// How long a client may take to send its request or read the reply
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Finished jobs are forgotten after an hour, or sooner when there are more
+// of them, as each holds its image
+const KEEP_FINISHED: Duration = Duration::from_secs(60 * 60);
+const MAX_FINISHED: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Png,
+    Pfm,
+}
+
+enum State {
+    Queued,
+    Rendering,
+    // the encoded image
+    Done(Vec<u8>),
+    Failed(String),
+}
+
+struct Job {
+    id: u64,
+    // the name of a built-in scene or a description
+    scene: Json,
+    settings: Settings,
+    seed: Option<u64>,
+    denoise: bool,
+    format: Format,
+    state: State,
+    progress: u32,
+    // when it was done or failed
+    finished: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    // in the order they came, which is the order they render in
+    list: VecDeque<Job>,
+}
+
+impl Jobs {
+    // Forgets the finished jobs kept for long enough, and the oldest ones
+    // beyond MAX_FINISHED. Jobs finish in the order of the list.
+    fn evict(&mut self) {
+        let mut finished = self
+            .list
+            .iter()
+            .filter(|job| job.finished.is_some())
+            .count();
+        self.list.retain(|job| match job.finished {
+            Some(time) if finished > MAX_FINISHED || time.elapsed() > KEEP_FINISHED => {
+                finished -= 1;
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+// The jobs, shared by the connections and the render thread, which waits
+// on `queued` for new ones
+#[derive(Default)]
+struct Service {
+    jobs: Mutex<Jobs>,
+    queued: Condvar,
+    // the web page origin allowed to use the service, none by default
+    allow_origin: Option<String>,
+}
+
+impl Service {
+    fn jobs(&self) -> MutexGuard<'_, Jobs> {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.evict();
+        jobs
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut allow_origin = None;
+    let mut address = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--allow-origin" => allow_origin = Some(args.next().ok_or(USAGE)?),
+            _ if arg.starts_with('-') || address.is_some() => return Err(USAGE.to_string()),
+            _ => address = Some(arg),
+        }
+    }
+    let address = address.unwrap_or("127.0.0.1:8000".to_string());
+    let listener = TcpListener::bind(&address).map_err(|e| format!("{}: {}", address, e))?;
+    eprintln!("serving on http://{}/", address);
+
+    let service = Arc::new(Service {
+        allow_origin,
+        ..Service::default()
+    });
+    {
+        let service = service.clone();
+        thread::spawn(move || render_jobs(&service));
+    }
+    for stream in listener.incoming().flatten() {
+        let service = service.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &service) {
+                eprintln!("{}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Renders the queued jobs one after the other, forever
+fn render_jobs(service: &Service) {
+    loop {
+        let (id, scene, settings, seed, denoise, format) = {
+            let mut jobs = service.jobs();
+            let job = loop {
+                match jobs
+                    .list
+                    .iter_mut()
+                    .find(|job| matches!(job.state, State::Queued))
+                {
+                    Some(job) => break job,
+                    None => jobs = service.queued.wait(jobs).unwrap(),
+                }
+            };
+            job.state = State::Rendering;
+            let job = &*job;
+            (
+                job.id,
+                job.scene.clone(),
+                job.settings,
+                job.seed,
+                job.denoise,
+                job.format,
+            )
+        };
+        match &scene {
+            Json::String(name) => eprintln!("job {}: rendering {}", id, name),
+            _ => eprintln!("job {}: rendering its scene", id),
+        }
+
+        let set_progress = |progress| {
+            let mut jobs = service.jobs();
+            if let Some(job) = jobs.list.iter_mut().find(|job| job.id == id) {
+                job.progress = progress;
+            }
+        };
+        // a panicking scene fails its job, not the service
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            render(&scene, settings, seed, denoise, format, &mut |progress| {
+                set_progress(progress)
+            })
+        }))
+        .unwrap_or_else(|_| Err("the render panicked".to_string()));
+
+        let mut jobs = service.jobs();
+        if let Some(job) = jobs.list.iter_mut().find(|job| job.id == id) {
+            match result {
+                Ok(image) => {
+                    eprintln!("job {}: done", id);
+                    job.progress = 100;
+                    job.state = State::Done(image);
+                }
+                Err(e) => {
+                    eprintln!("job {}: {}", id, e);
+                    job.state = State::Failed(e);
+                }
+            }
+            job.finished = Some(Instant::now());
+        }
+    }
+}
+
+// The encoded image of a job, telling `progress` the percentage completed
+// after every pass
+fn render(
+    scene: &Json,
+    settings: Settings,
+    seed: Option<u64>,
+    denoise: bool,
+    format: Format,
+    progress: &mut dyn FnMut(u32),
+) -> Result<Vec<u8>, String> {
+    let (width, height) = framebuffer_size();
+    let mut still = Checkpoint::new(settings, width, height);
+    if let Some(seed) = seed {
+        still = still.with_seed(seed);
+    }
+    let mut save = |_: &Checkpoint, info: &Info| progress(info.progress());
+    match scene {
+        Json::String(name) => resume_scene(name, &mut still, &mut save)?,
+        description => resume_with(&description::build(description)?, &mut still, &mut save),
+    }
+
+    let colors = if denoise {
+        Denoiser::new().denoise(&still.framebuffer)
+    } else {
+        still.framebuffer.colors()
+    };
+    let color = |x: u32, y: u32| colors[(y * width + x) as usize];
+    let mut image = Vec::new();
+    match format {
+        Format::Png => write_png(&mut image, width, height, |x, y| to_rgb8(color(x, y))),
+        Format::Pfm => write_pfm(&mut image, width, height, color),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(image)
+}
+
+// Answers one request. Every connection carries just one.
+fn handle(stream: TcpStream, service: &Service) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = Reply {
+        out: stream,
+        allow_origin: service.allow_origin.as_deref(),
+    };
+
+    let mut head = (&mut input).take(MAX_HEAD);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return out.error(400, "Bad Request", "malformed request"),
+    };
+    let mut content_length = 0;
+    let mut origin = None;
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(usize::MAX);
+            } else if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            }
+        }
+    }
+    if head.limit() == 0 {
+        return out.error(
+            431,
+            "Request Header Fields Too Large",
+            "the headers are too big",
+        );
+    }
+    // Browsers send the origin of the page with their cross-origin requests,
+    // and some of those, like a form posting a job, need no preflight
+    if origin.is_some() && origin.as_deref() != out.allow_origin {
+        return out.error(403, "Forbidden", "the origin isn't allowed");
+    }
+    if content_length > MAX_BODY {
+        return out.error(413, "Payload Too Large", "the request is too big");
+    }
+    let mut body = vec![0; content_length];
+    input.read_exact(&mut body)?;
+
+    let path = target.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let id = segments.get(1).and_then(|id| id.parse::<u64>().ok());
+    match (method.as_str(), &segments[..], id) {
+        // the preflight of browsers before a cross-origin POST or DELETE
+        ("OPTIONS", _, _) => out.send(204, "No Content", "text/plain", b""),
+        ("GET", ["scenes"], _) => {
+            let scenes = Json::Array(SCENES.iter().map(|&scene| scene.into()).collect());
+            out.json(200, "OK", &scenes)
+        }
+        ("POST", ["jobs"], _) => {
+            let job = std::str::from_utf8(&body)
+                .map_err(|_| "the job isn't UTF-8".to_string())
+                .and_then(Json::parse)
+                .and_then(|job| parse_job(&job));
+            match job {
+                Ok(mut job) => {
+                    let mut jobs = service.jobs();
+                    job.id = jobs.next_id;
+                    jobs.next_id += 1;
+                    let reply = Json::object([("id", job.id.into())]);
+                    jobs.list.push_back(job);
+                    service.queued.notify_one();
+                    drop(jobs);
+                    out.json(201, "Created", &reply)
+                }
+                Err(e) => out.error(400, "Bad Request", &e),
+            }
+        }
+        ("GET", ["jobs"], _) => {
+            let jobs = service.jobs();
+            let list = Json::Array(jobs.list.iter().map(status).collect());
+            drop(jobs);
+            out.json(200, "OK", &list)
+        }
+        ("GET", ["jobs", _], Some(id)) => {
+            let jobs = service.jobs();
+            let job = jobs.list.iter().find(|job| job.id == id).map(status);
+            drop(jobs);
+            match job {
+                Some(job) => out.json(200, "OK", &job),
+                None => out.error(404, "Not Found", "no such job"),
+            }
+        }
+        ("GET", ["jobs", _, "image"], Some(id)) => {
+            let jobs = service.jobs();
+            let image = jobs.list.iter().find(|job| job.id == id).map(|job| {
+                let content_type = match job.format {
+                    Format::Png => "image/png",
+                    Format::Pfm => "image/x-portable-floatmap",
+                };
+                match &job.state {
+                    State::Done(image) => Some((content_type, image.clone())),
+                    _ => None,
+                }
+            });
+            drop(jobs);
+            match image {
+                Some(Some((content_type, image))) => out.send(200, "OK", content_type, &image),
+                Some(None) => out.error(409, "Conflict", "the job isn't done"),
+                None => out.error(404, "Not Found", "no such job"),
+            }
+        }
+        ("DELETE", ["jobs", _], Some(id)) => {
+            let mut jobs = service.jobs();
+            let index = jobs.list.iter().position(|job| job.id == id);
+            let rendering = index.is_some_and(|i| matches!(jobs.list[i].state, State::Rendering));
+            if !rendering {
+                if let Some(i) = index {
+                    jobs.list.remove(i);
+                }
+            }
+            drop(jobs);
+            match index {
+                None => out.error(404, "Not Found", "no such job"),
+                Some(_) if rendering => out.error(409, "Conflict", "the job is rendering"),
+                Some(_) => out.send(204, "No Content", "text/plain", b""),
+            }
+        }
+        _ => out.error(404, "Not Found", "no such resource"),
+    }
+}
+
+// A job from its JSON, with the id still to be given
+fn parse_job(job: &Json) -> Result<Job, String> {
+    let Json::Object(members) = job else {
+        return Err("a job is a JSON object".to_string());
+    };
+    const KEYS: [&str; 9] = [
+        "scene",
+        "samples",
+        "noise_threshold",
+        "time",
+        "noise_target",
+        "sample_budget",
+        "seed",
+        "denoise",
+        "format",
+    ];
+    if let Some((key, _)) = members
+        .iter()
+        .find(|(key, _)| !KEYS.contains(&key.as_str()))
+    {
+        return Err(format!("unknown key \"{}\"", key));
+    }
+    let number = |key: &str| -> Result<Option<f64>, String> {
+        job.get(key)
+            .map(|value| {
+                value
+                    .as_f64()
+                    .filter(|number| number.is_finite() && *number >= 0.)
+                    .ok_or(format!("\"{}\" needs a number, 0 or more", key))
+            })
+            .transpose()
+    };
+    // integers that f64 holds exactly
+    let integer = |key: &str, max: f64| -> Result<Option<f64>, String> {
+        match number(key)? {
+            Some(n) if n.fract() != 0. || n > max => {
+                Err(format!("\"{}\" needs a whole number up to {}", key, max))
+            }
+            n => Ok(n),
+        }
+    };
+    let positive = |key: &str| -> Result<Option<f64>, String> {
+        match number(key)? {
+            Some(0.) => Err(format!("\"{}\" needs a number above 0", key)),
+            n => Ok(n),
+        }
+    };
+
+    let scene = job
+        .get("scene")
+        .ok_or("the job needs a \"scene\"".to_string())?;
+    match scene {
+        Json::String(name) if SCENES.contains(&name.as_str()) => (),
+        Json::String(_) => {
+            return Err(format!(
+                "\"scene\" is a description or one of {}",
+                SCENES.join(", ")
+            ))
+        }
+        // building it once finds what's wrong before the job is queued
+        description => drop(description::build(description)?),
+    }
+    let scene = scene.clone();
+
+    let mut settings = Settings::default();
+    if let Some(samples) = integer("samples", u32::MAX as f64)? {
+        settings.samples_per_pixel = (samples as u32).max(1);
+    }
+    if let Some(threshold) = number("noise_threshold")? {
+        settings.noise_threshold = threshold;
+    }
+    let mut modes = Vec::new();
+    if let Some(seconds) = positive("time")? {
+        modes.push(Mode::Time(seconds));
+    }
+    if let Some(target) = positive("noise_target")? {
+        modes.push(Mode::Noise(target));
+    }
+    if let Some(samples) = integer("sample_budget", 2_f64.powi(53))? {
+        modes.push(Mode::SampleBudget(samples as u64));
+    }
+    match modes[..] {
+        [] => (),
+        [mode] => settings.mode = mode,
+        _ => {
+            return Err("only one of \"time\", \"noise_target\" and \"sample_budget\"".to_string())
+        }
+    }
+
+    let seed = integer("seed", 2_f64.powi(53))?.map(|seed| seed as u64);
+    let denoise = match job.get("denoise") {
+        Some(denoise) => denoise
+            .as_bool()
+            .ok_or("\"denoise\" is true or false".to_string())?,
+        None => false,
+    };
+    let format = match job.get("format").map(|format| format.as_str()) {
+        None | Some(Some("png")) => Format::Png,
+        Some(Some("pfm")) => Format::Pfm,
+        _ => return Err("\"format\" is \"png\" or \"pfm\"".to_string()),
+    };
+    Ok(Job {
+        id: 0,
+        scene,
+        settings,
+        seed,
+        denoise,
+        format,
+        state: State::Queued,
+        progress: 0,
+        finished: None,
+    })
+}
+
+fn status(job: &Job) -> Json {
+    let (state, error) = match &job.state {
+        State::Queued => ("queued", None),
+        State::Rendering => ("rendering", None),
+        State::Done(_) => ("done", None),
+        State::Failed(e) => ("failed", Some(e.as_str())),
+    };
+    Json::object([
+        ("id", job.id.into()),
+        ("scene", job.scene.clone()),
+        ("state", state.into()),
+        ("progress", job.progress.into()),
+        ("error", error.into()),
+    ])
+}
+
+// The sending end of a connection
+struct Reply<'a> {
+    out: TcpStream,
+    // the web page origin allowed to read the replies, if any
+    allow_origin: Option<&'a str>,
+}
+
+impl Reply<'_> {
+    fn json(&mut self, code: u32, reason: &str, body: &Json) -> io::Result<()> {
+        self.send(
+            code,
+            reason,
+            "application/json",
+            body.to_string().as_bytes(),
+        )
+    }
+
+    fn error(&mut self, code: u32, reason: &str, error: &str) -> io::Result<()> {
+        self.json(code, reason, &Json::object([("error", error.into())]))
+    }
+
+    fn send(&mut self, code: u32, reason: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n",
+            code,
+            reason,
+            content_type,
+            body.len()
+        );
+        if let Some(origin) = self.allow_origin {
+            head += &format!(
+                "Access-Control-Allow-Origin: {}\r\n\
+                 Access-Control-Allow-Methods: GET, POST, DELETE, OPTIONS\r\n\
+                 Access-Control-Allow-Headers: Content-Type\r\n\
+                 Vary: Origin\r\n",
+                origin
+            );
+        }
+        head += "Connection: close\r\n\r\n";
+        let mut out = BufWriter::new(&self.out);
+        out.write_all(head.as_bytes())?;
+        out.write_all(body)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_jobs_are_evicted() {
+        let mut jobs = Jobs::default();
+        for id in 0..20 {
+            let mut job = parse_job(&Json::object([("scene", "image15".into())])).unwrap();
+            job.id = id;
+            // the first 18 finished, the others wait
+            if id < 18 {
+                job.state = State::Failed("stopped".to_string());
+                job.finished = Some(Instant::now());
+            }
+            jobs.list.push_back(job);
+        }
+        jobs.evict();
+        let ids: Vec<u64> = jobs.list.iter().map(|job| job.id).collect();
+        assert_eq!(ids, (2..20).collect::<Vec<_>>());
+    }
+}
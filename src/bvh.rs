@@ -0,0 +1,125 @@
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::RenderRng;
+
+// Bounding volume hierarchy, for scenes with many small objects like hair.
+// Every object needs a bounding box, so keep planes in a HittableList next to it.
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#boundingvolumehierarchies
+pub enum BvhNode<T>
+where
+    T: Hittable,
+{
+    Leaf {
+        object: T,
+        aabb: Aabb,
+    },
+    Node {
+        left: Box<BvhNode<T>>,
+        right: Box<BvhNode<T>>,
+        aabb: Aabb,
+    },
+}
+
+impl<T> BvhNode<T>
+where
+    T: Hittable,
+{
+    // Panics if `objects` is empty or one of them is unbounded.
+    pub fn new(objects: Vec<T>) -> Self {
+        assert!(!objects.is_empty(), "BvhNode needs at least one object");
+        let objects = objects
+            .into_iter()
+            .map(|object| {
+                let aabb = object
+                    .bounding_box()
+                    .expect("BvhNode objects need a bounding box");
+                (object, aabb)
+            })
+            .collect();
+        Self::build(objects)
+    }
+
+    // Splits the objects in half along the longest axis of their centers.
+    fn build(mut objects: Vec<(T, Aabb)>) -> Self {
+        if objects.len() == 1 {
+            let (object, aabb) = objects.pop().unwrap();
+            return BvhNode::Leaf { object, aabb };
+        }
+
+        let center = |aabb: &Aabb| (aabb.min + aabb.max) / 2.;
+        let (mut lo, mut hi) = (
+            Vector3::repeat(f64::INFINITY),
+            Vector3::repeat(-f64::INFINITY),
+        );
+        for (_, aabb) in &objects {
+            lo = lo.inf(&center(aabb));
+            hi = hi.sup(&center(aabb));
+        }
+        let axis = (hi - lo).imax();
+        objects.sort_by(|(_, a), (_, b)| center(a)[axis].total_cmp(&center(b)[axis]));
+
+        let rest = objects.split_off(objects.len() / 2);
+        let left = Box::new(Self::build(objects));
+        let right = Box::new(Self::build(rest));
+        let aabb = left.aabb().surrounding(&right.aabb());
+        BvhNode::Node { left, right, aabb }
+    }
+
+    fn aabb(&self) -> Aabb {
+        match self {
+            BvhNode::Leaf { aabb, .. } | BvhNode::Node { aabb, .. } => *aabb,
+        }
+    }
+}
+
+impl<T> Hittable for BvhNode<T>
+where
+    T: Hittable,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        if !self.aabb().hit(ray, t_min, t_max) {
+            return None;
+        }
+        match self {
+            BvhNode::Leaf { object, .. } => object.hit(ray, t_min, t_max, rng),
+            BvhNode::Node { left, right, .. } => {
+                let hit_left = left.hit(ray, t_min, t_max, rng);
+                let closest = hit_left.as_ref().map_or(t_max, |hit_record| hit_record.t);
+                right.hit(ray, t_min, closest, rng).or(hit_left)
+            }
+        }
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        match self {
+            BvhNode::Leaf { object, .. } => object.materials(materials),
+            BvhNode::Node { left, right, .. } => {
+                left.materials(materials);
+                right.materials(materials);
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.aabb())
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        if !self.aabb().hit(ray, t_min, t_max) {
+            return 1.;
+        }
+        match self {
+            BvhNode::Leaf { object, .. } => object.transmittance(ray, t_min, t_max, rng),
+            BvhNode::Node { left, right, .. } => {
+                left.transmittance(ray, t_min, t_max, rng)
+                    * right.transmittance(ray, t_min, t_max, rng)
+            }
+        }
+    }
+}
@@ -1,19 +1,173 @@
-use crate::{ray::Ray, utils::*};
-use js_sys::Math::tan;
-use nalgebra::Vector3;
-use rand::prelude::ThreadRng;
+use std::f64::consts::PI;
+use std::rc::Rc;
 
-pub struct Camera {
+use crate::utils::RenderRng;
+use crate::{ray::Ray, utils::*, Color};
+use nalgebra::{Unit, UnitQuaternion, Vector3};
+use rand::Rng;
+
+// Turns a point on the film into a ray. Film coordinates (s, t) run over
+// [0, 1] from the left and from the bottom of the image.
+pub trait Camera {
+    // None where the projection doesn't cover the film, like the corners
+    // outside a fisheye's image circle.
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray>;
+
+    // The ray along with a weight for the light it brings back, for cameras
+    // mixing several views into one pixel, like anaglyphs.
+    fn sample_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<(Ray, Color)> {
+        self.get_ray(s, t, rng)
+            .map(|ray| (ray, Color::new(1., 1., 1.)))
+    }
+}
+
+// Position and orientation shared by all the cameras, looking down -w,
+// with the shutter open from time0 to time1.
+pub(crate) struct View {
     origin: Vector3<f64>,
-    horizontal: Vector3<f64>,
-    vertical: Vector3<f64>,
     u: Vector3<f64>,
     v: Vector3<f64>,
+    w: Vector3<f64>,
+    pub(crate) time0: f64,
+    pub(crate) time1: f64,
+}
+
+impl View {
+    pub(crate) fn new(lookfrom: Vector3<f64>, lookat: Vector3<f64>, vup: Vector3<f64>) -> Self {
+        let w = (lookfrom - lookat).normalize();
+        let u = vup.cross(&w).normalize();
+        let v = w.cross(&u);
+        View {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            time0: 0.,
+            time1: 0.,
+        }
+    }
+
+    // Point given in camera space
+    pub(crate) fn point(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        self.origin + self.u * p.x + self.v * p.y + self.w * p.z
+    }
+
+    // Ray from `origin` along `direction` given in camera space (x right, y up, z back)
+    pub(crate) fn ray(
+        &self,
+        origin: Vector3<f64>,
+        direction: &Vector3<f64>,
+        rng: &mut RenderRng,
+    ) -> Ray {
+        Ray::new(
+            origin,
+            self.u * direction.x + self.v * direction.y + self.w * direction.z,
+            random_f64(rng, self.time0, self.time1),
+        )
+    }
+}
+
+// Shape of the lens opening, which is also the shape of out-of-focus highlights.
+pub enum Aperture {
+    Circle,
+    // regular polygon like the one formed by the blades of a diaphragm,
+    // turned by `rotation` degrees
+    Polygon { blades: u32, rotation: f64 },
+    Mask(Rc<ApertureMask>),
+}
+
+impl Aperture {
+    // Random point on the aperture, which fits in the unit disk
+    fn sample(&self, rng: &mut RenderRng) -> Vector3<f64> {
+        match self {
+            Aperture::Circle => random_in_unit_disk(rng),
+            Aperture::Polygon { blades, rotation } => {
+                // uniform in one of the equal triangles between the center and an edge
+                let blades = (*blades).max(3) as f64;
+                let k = (rng.gen::<f64>() * blades).floor();
+                let angle = |k: f64| deg_to_rad(*rotation) + 2. * PI * k / blades;
+                let a = Vector3::new(angle(k).cos(), angle(k).sin(), 0.);
+                let b = Vector3::new(angle(k + 1.).cos(), angle(k + 1.).sin(), 0.);
+                let r = rng.gen::<f64>().sqrt();
+                let mix = rng.gen::<f64>();
+                r * ((1. - mix) * a + mix * b)
+            }
+            Aperture::Mask(mask) => mask.sample(rng),
+        }
+    }
+}
+
+// Custom aperture shape from a grayscale image inscribed in the unit disk,
+// keeping its aspect ratio, where white lets light through and black
+// blocks it.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // sums of the weights of the pixels up to each one, row-major from the
+    // top, with the weights in [0, 1]
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    // Panics if the mask is all black.
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Self {
+        assert_eq!(weights.len(), width * height, "aperture mask size mismatch");
+        let cdf: Vec<f64> = weights
+            .iter()
+            .scan(0., |sum, &w| {
+                *sum += w.max(0.) as f64;
+                Some(*sum)
+            })
+            .collect();
+        assert!(
+            cdf.last().is_some_and(|&sum| sum > 0.),
+            "aperture mask is all black"
+        );
+        ApertureMask { width, height, cdf }
+    }
+
+    pub fn from_pgm(bytes: &[u8]) -> Result<Self, String> {
+        let (width, height, weights) = parse_pgm(bytes)?;
+        if !weights.iter().any(|&w| w > 0.) {
+            return Err("aperture mask is all black".to_string());
+        }
+        Ok(ApertureMask::new(width, height, weights))
+    }
+
+    // A pixel picked in proportion to its weight, and a uniform point in it
+    fn sample(&self, rng: &mut RenderRng) -> Vector3<f64> {
+        let total = self.cdf[self.cdf.len() - 1];
+        let u = rng.gen::<f64>() * total;
+        let i = self
+            .cdf
+            .partition_point(|&sum| sum <= u)
+            .min(self.cdf.len() - 1);
+        let x = ((i % self.width) as f64 + rng.gen::<f64>()) / self.width as f64;
+        let y = ((i / self.width) as f64 + rng.gen::<f64>()) / self.height as f64;
+        // the corners of the image touch the unit circle
+        let (w, h) = (self.width as f64, self.height as f64);
+        let half_diagonal = (w * w + h * h).sqrt();
+        Vector3::new((2. * x - 1.) * w, (1. - 2. * y) * h, 0.) / half_diagonal
+    }
+}
+
+// Thin lens camera, with depth of field from the aperture.
+pub struct PerspectiveCamera {
+    view: View,
+    horizontal: Vector3<f64>,
+    vertical: Vector3<f64>,
     lower_left_corner: Vector3<f64>,
     lens_radius: f64,
+    aperture: Aperture,
+    // how far the lens barrel cuts into the aperture towards the corners
+    cat_eye: f64,
+    // plane in focus, through focus_point; perpendicular to w unless tilted
+    focus_point: Vector3<f64>,
+    focus_normal: Vector3<f64>,
+    exposure: f64,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     // See https://raytracing.github.io/books/RayTracingInOneWeekend.html#positionablecamera/positioningandorientingthecamera
     pub fn new(
         lookfrom: Vector3<f64>,
@@ -25,35 +179,295 @@ impl Camera {
         focus_dist: f64,
     ) -> Self {
         let theta = deg_to_rad(vfov);
-        let h = tan(theta / 2.);
+        let h = (theta / 2.).tan();
         let viewport_height: f64 = 2. * h;
         let viewport_width: f64 = aspect_ratio * viewport_height;
 
-        let w = (lookfrom - lookat).normalize();
-        let u = vup.cross(&w).normalize();
-        let v = w.cross(&u);
-
-        let origin = lookfrom;
-        let horizontal = focus_dist * viewport_width * u;
-        let vertical = focus_dist * viewport_height * v;
+        let view = View::new(lookfrom, lookat, vup);
+        let horizontal = focus_dist * viewport_width * view.u;
+        let vertical = focus_dist * viewport_height * view.v;
 
-        Camera {
-            origin,
+        PerspectiveCamera {
+            lower_left_corner: lookfrom - horizontal / 2. - vertical / 2. - focus_dist * view.w,
+            focus_point: lookfrom - focus_dist * view.w,
+            focus_normal: view.w,
+            view,
             horizontal,
             vertical,
-            u,
-            v,
-            lower_left_corner: lookfrom - horizontal / 2. - vertical / 2. - focus_dist * w,
             lens_radius: aperture / 2.,
+            aperture: Aperture::Circle,
+            cat_eye: 0.,
+            exposure: 1.,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64, rng: &mut ThreadRng) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk(rng);
-        let offset = self.u * rd.x + self.v * rd.y;
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-        )
+    // Rays are sent at random times between time0 and time1, which blurs
+    // anything that moves while the shutter is open.
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.view.time0 = time0;
+        self.view.time1 = time1;
+        self
+    }
+
+    // Moves the image window by fractions of its width and height without
+    // turning the camera, like a shift lens.
+    pub fn with_shift(mut self, x: f64, y: f64) -> Self {
+        self.lower_left_corner += x * self.horizontal + y * self.vertical;
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    // Mechanical vignetting: the lens barrel clips the aperture more and more
+    // away from the center, darkening the corners and squeezing bokeh there
+    // into cat's eyes. 0 turns it off; at 1 the corners go black.
+    pub fn with_cat_eye(mut self, amount: f64) -> Self {
+        self.cat_eye = amount;
+        self
+    }
+
+    // Tilts the plane in focus by `tilt` degrees around the horizontal axis
+    // (positive leans its top away) and `swing` degrees around the vertical
+    // one (positive turns its right side away), like a tilt lens. The plane
+    // still passes through the focus distance at the center of the view.
+    pub fn with_tilt(mut self, tilt: f64, swing: f64) -> Self {
+        let rotation =
+            UnitQuaternion::from_axis_angle(&Unit::new_normalize(self.view.v), deg_to_rad(swing))
+                * UnitQuaternion::from_axis_angle(
+                    &Unit::new_normalize(self.view.u),
+                    deg_to_rad(-tilt),
+                );
+        self.focus_normal = rotation * self.view.w;
+        self
+    }
+
+    // Scales the image like a camera with these settings would, given
+    // radiance in cd/m^2, with the saturation-based sensitivity of ISO 12232.
+    // f/1.4, 1/15 s and ISO 3200 keep a scene as bright as the default sky
+    // about where it was. This doesn't change the aperture or the shutter.
+    // See Lagarde and de Rousiers, "Moving Frostbite to Physically Based Rendering"
+    pub fn with_exposure(mut self, f_stop: f64, shutter_time: f64, iso: f64) -> Self {
+        let ev100 = (f_stop * f_stop / shutter_time * 100. / iso).log2();
+        self.exposure = 1. / (1.2 * 2_f64.powf(ev100));
+        self
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray> {
+        let lens = self.aperture.sample(rng);
+        if self.cat_eye > 0. {
+            // The barrel is another disk as big as the aperture, sliding off it
+            // along the direction of the film point, up to its diameter at the corners.
+            let aspect_ratio = self.horizontal.norm() / self.vertical.norm();
+            let film = Vector3::new((2. * s - 1.) * aspect_ratio, 2. * t - 1., 0.)
+                / (aspect_ratio * aspect_ratio + 1.).sqrt();
+            if (lens - 2. * self.cat_eye * film).norm() > 1. {
+                return None;
+            }
+        }
+        let offset = self.lens_radius * (self.view.u * lens.x + self.view.v * lens.y);
+        let origin = self.view.origin + offset;
+
+        // all rays through the lens meet where the pinhole ray hits the plane in focus
+        let direction =
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.view.origin;
+        let distance = (self.focus_point - self.view.origin).dot(&self.focus_normal)
+            / direction.dot(&self.focus_normal);
+        // a plane tilted so far that this ray runs along it or away from it
+        // leaves it focused as if untilted, where `direction` ends
+        let distance = if distance.is_finite() && distance > 0. {
+            distance
+        } else {
+            1.
+        };
+        let target = self.view.origin + distance * direction;
+        Some(Ray::new(
+            origin,
+            target - origin,
+            random_f64(rng, self.view.time0, self.view.time1),
+        ))
+    }
+
+    fn sample_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<(Ray, Color)> {
+        self.get_ray(s, t, rng)
+            .map(|ray| (ray, Color::repeat(self.exposure)))
+    }
+}
+
+// Parallel rays from a `height` units tall window, so sizes don't shrink
+// with distance. Objects behind lookfrom aren't seen.
+pub struct OrthographicCamera {
+    view: View,
+    width: f64,
+    height: f64,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        height: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        OrthographicCamera {
+            view: View::new(lookfrom, lookat, vup),
+            width: height * aspect_ratio,
+            height,
+        }
+    }
+
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.view.time0 = time0;
+        self.view.time1 = time1;
+        self
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray> {
+        let origin = self.view.origin
+            + (s - 0.5) * self.width * self.view.u
+            + (t - 0.5) * self.height * self.view.v;
+        Some(self.view.ray(origin, &Vector3::new(0., 0., -1.), rng))
+    }
+}
+
+// How a fisheye lens maps the angle from its axis to the distance from the
+// center of the image circle.
+#[derive(Clone, Copy)]
+pub enum FisheyeMapping {
+    // distance proportional to the angle
+    Equidistant,
+    // equal areas on the image cover equal solid angles
+    Equisolid,
+}
+
+// Circular fisheye: the image circle spans the height of the image and
+// covers `fov` degrees across, which may be more than 180.
+pub struct FisheyeCamera {
+    view: View,
+    aspect_ratio: f64,
+    max_theta: f64,
+    mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        fov: f64,
+        aspect_ratio: f64,
+        mapping: FisheyeMapping,
+    ) -> Self {
+        FisheyeCamera {
+            view: View::new(lookfrom, lookat, vup),
+            aspect_ratio,
+            max_theta: deg_to_rad(fov) / 2.,
+            mapping,
+        }
+    }
+
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.view.time0 = time0;
+        self.view.time1 = time1;
+        self
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray> {
+        // position on the image circle, which has radius 1
+        let x = (2. * s - 1.) * self.aspect_ratio;
+        let y = 2. * t - 1.;
+        let r = (x * x + y * y).sqrt();
+        if r > 1. {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.max_theta,
+            FisheyeMapping::Equisolid => 2. * (r * (self.max_theta / 2.).sin()).asin(),
+        };
+        let (cos_phi, sin_phi) = if r > 0. { (x / r, y / r) } else { (1., 0.) };
+        let direction = Vector3::new(theta.sin() * cos_phi, theta.sin() * sin_phi, -theta.cos());
+        Some(self.view.ray(self.view.origin, &direction, rng))
+    }
+}
+
+// 360 by 180 degree panorama in latitude/longitude layout, with lookat in
+// the middle of the image. Render it with an aspect ratio of 2.
+pub struct EquirectangularCamera {
+    view: View,
+    eye_offset: f64,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Vector3<f64>, lookat: Vector3<f64>, vup: Vector3<f64>) -> Self {
+        EquirectangularCamera {
+            view: View::new(lookfrom, lookat, vup),
+            eye_offset: 0.,
+        }
+    }
+
+    // Starts each ray `offset` to the right of lookfrom, seen along the ray,
+    // which gives one eye of an omnidirectional stereo panorama.
+    pub fn with_eye_offset(mut self, offset: f64) -> Self {
+        self.eye_offset = offset;
+        self
+    }
+
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.view.time0 = time0;
+        self.view.time1 = time1;
+        self
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2. * PI;
+        let latitude = (t - 0.5) * PI;
+        let direction = Vector3::new(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            -latitude.cos() * longitude.cos(),
+        );
+        let right = self.view.u * longitude.cos() + self.view.w * longitude.sin();
+        let origin = self.view.origin + self.eye_offset * right;
+        Some(self.view.ray(origin, &direction, rng))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn aperture_mask_inside_the_lens() {
+        let mut rng = RenderRng::seed_from_u64(0);
+        // white corners of a 3x2 image, and a little light in the middle
+        let mask = ApertureMask::new(3, 2, vec![1., 0., 0., 0., 0.001, 1.]);
+        // the image spans 3 by 2 of these units
+        let unit = 2. / 13_f64.sqrt();
+        let (mut top_left, mut middle) = (0, 0);
+        for _ in 0..10000 {
+            let p = mask.sample(&mut rng);
+            assert!(p.norm() <= 1.);
+            match (p.x, p.y) {
+                (x, y) if x < -unit / 2. && y > 0. => top_left += 1,
+                (x, y) if x.abs() < unit / 2. && y < 0. => middle += 1,
+                (x, y) => assert!(x > unit / 2. && y < 0., "({}, {})", x, y),
+            }
+        }
+        assert!((4500..5500).contains(&top_left));
+        assert!(middle < 30);
+        assert!(ApertureMask::from_pgm(b"P2 2 1 255 0 0").is_err());
     }
 }
@@ -0,0 +1,298 @@
+// Render checkpoints: everything needed to stop a still render and later
+// add more samples to it, as if it had never stopped.
+//
+// The file is little-endian binary: a header with the region of the image,
+// the settings and the progress, the state of the random number generator,
+// then the accumulated samples of the framebuffer and its auxiliary buffers.
+
+use std::io::{self, Read, Write};
+
+use nalgebra::Vector3;
+use rand::SeedableRng;
+
+use crate::framebuffer::Framebuffer;
+use crate::utils::RenderRng;
+use crate::{Mode, Settings};
+
+const MAGIC: &[u8; 8] = b"RTCHECK1";
+
+pub struct Checkpoint {
+    pub settings: Settings,
+    // top left corner of the framebuffer in the image, which it covers
+    // unless the render is a tile of a distributed one
+    pub origin: (u32, u32),
+    // seeds the random parts of the scene, which have to come out the same
+    // when the render resumes
+    pub scene_seed: u64,
+    pub rng: RenderRng,
+    // passes over the image and samples taken so far
+    pub pass: u32,
+    pub taken: u64,
+    pub framebuffer: Framebuffer,
+}
+
+impl Checkpoint {
+    // A render that hasn't started yet, with fresh seeds
+    pub fn new(settings: Settings, width: u32, height: u32) -> Self {
+        Checkpoint {
+            settings,
+            origin: (0, 0),
+            scene_seed: rand::random(),
+            rng: RenderRng::from_entropy(),
+            pass: 0,
+            taken: 0,
+            framebuffer: Framebuffer::new(width, height),
+        }
+    }
+
+    // The same render with both seeds taken from `seed`, to repeat it
+    pub fn with_seed(self, seed: u64) -> Self {
+        Checkpoint {
+            scene_seed: seed,
+            rng: stream(seed, 1),
+            ..self
+        }
+    }
+
+    // Tile `index` of a distributed render, `width` by `height` pixels from
+    // `origin`. Its random numbers only depend on `seed` and `index`, so it
+    // comes out the same whichever process renders it.
+    pub fn tile(
+        settings: Settings,
+        seed: u64,
+        index: u64,
+        origin: (u32, u32),
+        (width, height): (u32, u32),
+    ) -> Self {
+        Checkpoint {
+            settings,
+            origin,
+            scene_seed: seed,
+            rng: stream(seed, index + 1),
+            pass: 0,
+            taken: 0,
+            framebuffer: Framebuffer::new(width, height),
+        }
+    }
+
+    pub fn write<W>(&self, mut out: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        out.write_all(MAGIC)?;
+        let (x, y) = self.origin;
+        write_u32s(
+            &mut out,
+            &[x, y, self.framebuffer.width, self.framebuffer.height],
+        )?;
+        write_settings(&mut out, &self.settings)?;
+        write_u64s(&mut out, &[self.scene_seed])?;
+
+        out.write_all(&self.rng.get_seed())?;
+        write_u64s(&mut out, &[self.rng.get_stream()])?;
+        out.write_all(&self.rng.get_word_pos().to_le_bytes())?;
+
+        write_u32s(&mut out, &[self.pass])?;
+        write_u64s(&mut out, &[self.taken])?;
+        self.framebuffer.write_state(&mut out)?;
+        out.flush()
+    }
+
+    pub fn read<R>(mut input: R) -> io::Result<Self>
+    where
+        R: Read,
+    {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a render checkpoint"));
+        }
+        let [x, y, width, height] = read_u32s(&mut input, 4)?[..] else {
+            unreachable!()
+        };
+        let settings = read_settings(&mut input)?;
+        let scene_seed = read_u64s(&mut input, 1)?[0];
+
+        let mut seed = [0; 32];
+        input.read_exact(&mut seed)?;
+        let mut rng = RenderRng::from_seed(seed);
+        rng.set_stream(read_u64s(&mut input, 1)?[0]);
+        let mut word_pos = [0; 16];
+        input.read_exact(&mut word_pos)?;
+        rng.set_word_pos(u128::from_le_bytes(word_pos));
+
+        let pass = read_u32s(&mut input, 1)?[0];
+        let taken = read_u64s(&mut input, 1)?[0];
+        let framebuffer = Framebuffer::read_state(&mut input, width, height)?;
+        Ok(Checkpoint {
+            settings,
+            origin: (x, y),
+            scene_seed,
+            rng,
+            pass,
+            taken,
+            framebuffer,
+        })
+    }
+}
+
+// Stream 0 of a seed builds the scene, the others sample it.
+fn stream(seed: u64, stream: u64) -> RenderRng {
+    let mut rng = RenderRng::seed_from_u64(seed);
+    rng.set_stream(stream);
+    rng
+}
+
+pub(crate) fn write_settings<W: Write>(out: &mut W, settings: &Settings) -> io::Result<()> {
+    write_u32s(out, &[settings.samples_per_pixel])?;
+    write_f64s(out, &[settings.noise_threshold])?;
+    let (mode, limit) = match settings.mode {
+        Mode::Samples => (0, 0),
+        Mode::Time(seconds) => (1, seconds.to_bits()),
+        Mode::Noise(target) => (2, target.to_bits()),
+        Mode::SampleBudget(samples) => (3, samples),
+    };
+    write_u32s(out, &[mode])?;
+    write_u64s(out, &[limit])
+}
+
+pub(crate) fn read_settings<R: Read>(input: &mut R) -> io::Result<Settings> {
+    let samples_per_pixel = read_u32s(input, 1)?[0];
+    let noise_threshold = read_f64s(input, 1)?[0];
+    let mode = read_u32s(input, 1)?[0];
+    let limit = read_u64s(input, 1)?[0];
+    let mode = match mode {
+        0 => Mode::Samples,
+        1 => Mode::Time(f64::from_bits(limit)),
+        2 => Mode::Noise(f64::from_bits(limit)),
+        3 => Mode::SampleBudget(limit),
+        _ => return Err(invalid_data("unknown render mode")),
+    };
+    Ok(Settings {
+        samples_per_pixel,
+        noise_threshold,
+        mode,
+    })
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn write_u32s<W: Write>(out: &mut W, values: &[u32]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_u64s<W: Write>(out: &mut W, values: &[u64]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_f64s<W: Write>(out: &mut W, values: &[f64]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_vectors<W: Write>(out: &mut W, values: &[Vector3<f64>]) -> io::Result<()> {
+    for value in values {
+        write_f64s(out, value.as_slice())?;
+    }
+    Ok(())
+}
+
+// `len` values, read in one go
+fn read_bytes<R: Read, const N: usize>(input: &mut R, len: usize) -> io::Result<Vec<[u8; N]>> {
+    let mut bytes = vec![0; len * N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(N)
+        .map(|chunk| chunk.try_into().unwrap())
+        .collect())
+}
+
+pub(crate) fn read_u32s<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<u32>> {
+    Ok(read_bytes(input, len)?
+        .into_iter()
+        .map(u32::from_le_bytes)
+        .collect())
+}
+
+pub(crate) fn read_u64s<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<u64>> {
+    Ok(read_bytes(input, len)?
+        .into_iter()
+        .map(u64::from_le_bytes)
+        .collect())
+}
+
+pub(crate) fn read_f64s<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<f64>> {
+    Ok(read_bytes(input, len)?
+        .into_iter()
+        .map(f64::from_le_bytes)
+        .collect())
+}
+
+pub(crate) fn read_vectors<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<Vector3<f64>>> {
+    Ok(read_f64s(input, 3 * len)?
+        .chunks_exact(3)
+        .map(Vector3::from_column_slice)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resume_scene;
+
+    fn settings() -> Settings {
+        Settings {
+            samples_per_pixel: 4,
+            noise_threshold: 0.,
+            mode: Mode::Samples,
+        }
+    }
+
+    fn bytes(checkpoint: &Checkpoint) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        checkpoint.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn write_then_read() {
+        let checkpoint = Checkpoint::new(settings(), 5, 3).with_seed(11);
+        let written = bytes(&checkpoint);
+        assert_eq!(bytes(&Checkpoint::read(&written[..]).unwrap()), written);
+        assert!(Checkpoint::read(&written[..written.len() - 1]).is_err());
+        assert!(Checkpoint::read(&b"RTCHECK0"[..]).is_err());
+    }
+
+    // A render stopped after two passes and resumed from the file comes out
+    // the same as one that never stopped, random scene and media included
+    #[test]
+    fn resume_gives_the_same_bytes() {
+        let tile = || Checkpoint::tile(settings(), 7, 3, (560, 380), (8, 6));
+        let mut whole = tile();
+        resume_scene("smoke", &mut whole, &mut |_, _| ()).unwrap();
+
+        let mut saved = None;
+        resume_scene("smoke", &mut tile(), &mut |checkpoint, _| {
+            if checkpoint.pass == 2 {
+                saved = Some(bytes(checkpoint));
+            }
+        })
+        .unwrap();
+        let mut resumed = Checkpoint::read(&saved.unwrap()[..]).unwrap();
+        assert_eq!(resumed.taken, 2 * 8 * 6);
+        resume_scene("smoke", &mut resumed, &mut |_, _| ()).unwrap();
+
+        assert_eq!(resumed.taken, 4 * 8 * 6);
+        assert_eq!(bytes(&resumed), bytes(&whole));
+    }
+}
@@ -0,0 +1,170 @@
+use std::rc::Rc;
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable, Span};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::RenderRng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    // inside either child
+    Union,
+    // inside both children
+    Intersection,
+    // inside the left child but not the right one
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Constructive solid geometry over two closed children.
+// Surfaces keep the material of the child they come from, so the walls cut
+// by a difference show the material of the right child, with normals
+// flipped to point out of the result.
+pub struct Csg<A, B>
+where
+    A: Hittable,
+    B: Hittable,
+{
+    operation: CsgOperation,
+    left: A,
+    right: B,
+}
+
+impl<A, B> Csg<A, B>
+where
+    A: Hittable,
+    B: Hittable,
+{
+    pub fn new(operation: CsgOperation, left: A, right: B) -> Self {
+        Csg {
+            operation,
+            left,
+            right,
+        }
+    }
+
+    pub fn union(left: A, right: B) -> Self {
+        Csg::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: A, right: B) -> Self {
+        Csg::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: A, right: B) -> Self {
+        Csg::new(CsgOperation::Difference, left, right)
+    }
+}
+
+// A surface crossing of one child along the ray
+struct Event {
+    hit_record: HitRecord,
+    from_left: bool,
+    entering: bool,
+}
+
+fn events(spans: Vec<Span>, from_left: bool) -> impl Iterator<Item = Event> {
+    spans.into_iter().flat_map(move |span| {
+        [
+            Event {
+                hit_record: span.enter,
+                from_left,
+                entering: true,
+            },
+            Event {
+                hit_record: span.exit,
+                from_left,
+                entering: false,
+            },
+        ]
+    })
+}
+
+impl<A, B> Hittable for Csg<A, B>
+where
+    A: Hittable,
+    B: Hittable,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        self.spans(ray, rng)
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|hit_record| hit_record.t >= t_min && hit_record.t <= t_max)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        self.left.materials(materials);
+        self.right.materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.operation {
+            CsgOperation::Union => Some(
+                self.left
+                    .bounding_box()?
+                    .surrounding(&self.right.bounding_box()?),
+            ),
+            CsgOperation::Intersection => {
+                let left = self.left.bounding_box()?;
+                let right = self.right.bounding_box()?;
+                Some(Aabb::new(
+                    left.min.sup(&right.min),
+                    left.max.inf(&right.max),
+                ))
+            }
+            CsgOperation::Difference => self.left.bounding_box(),
+        }
+    }
+
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        let mut events: Vec<Event> = events(self.left.spans(ray, rng), true)
+            .chain(events(self.right.spans(ray, rng), false))
+            .collect();
+        events.sort_by(|a, b| a.hit_record.t.total_cmp(&b.hit_record.t));
+
+        // Walk the crossings in order and emit a boundary whenever the
+        // combined inside/outside state changes.
+        let mut spans = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<HitRecord> = None;
+        for event in events {
+            let was_inside = self.operation.inside(in_left, in_right);
+            if event.from_left {
+                in_left = event.entering;
+            } else {
+                in_right = event.entering;
+            }
+            let is_inside = self.operation.inside(in_left, in_right);
+            if was_inside == is_inside {
+                continue;
+            }
+
+            let mut hit_record = event.hit_record;
+            if event.entering != is_inside {
+                // Entering the right child of a difference leaves the result,
+                // so the surface faces the other way.
+                let outward_normal = -hit_record.outward_normal();
+                hit_record.set_face_normal(ray, &outward_normal);
+            }
+            if is_inside {
+                enter = Some(hit_record);
+            } else if let Some(enter) = enter.take() {
+                spans.push(Span {
+                    enter,
+                    exit: hit_record,
+                });
+            }
+        }
+        spans
+    }
+}
@@ -0,0 +1,303 @@
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::*;
+
+pub enum CurveBasis {
+    // every 3 points after the first add a segment passing through its last point
+    Bezier,
+    // uniform cubic B-spline: every point after the third adds a segment
+    BSpline,
+}
+
+#[derive(Clone, Copy)]
+pub enum CurveType {
+    // ribbon always facing the ray
+    Flat,
+    // ribbon facing the ray, shaded as if it were a tube
+    Round,
+}
+
+// Point and derivative of a cubic Bezier curve at t, by de Casteljau.
+fn eval_bezier(cp: &[Vector3<f64>; 4], t: f64) -> (Vector3<f64>, Vector3<f64>) {
+    let lerp = |a: &Vector3<f64>, b: &Vector3<f64>| a * (1. - t) + b * t;
+    let (a, b, c) = (
+        lerp(&cp[0], &cp[1]),
+        lerp(&cp[1], &cp[2]),
+        lerp(&cp[2], &cp[3]),
+    );
+    let (d, e) = (lerp(&a, &b), lerp(&b, &c));
+    let derivative = if (e - d).norm_squared() > 0. {
+        3. * (e - d)
+    } else {
+        cp[3] - cp[0]
+    };
+    (lerp(&d, &e), derivative)
+}
+
+// The two halves of a cubic Bezier curve, sharing the middle point.
+fn split_bezier(cp: &[Vector3<f64>; 4]) -> [[Vector3<f64>; 4]; 2] {
+    let mid = (cp[0] + 3. * cp[1] + 3. * cp[2] + cp[3]) / 8.;
+    [
+        [
+            cp[0],
+            (cp[0] + cp[1]) / 2.,
+            (cp[0] + 2. * cp[1] + cp[2]) / 4.,
+            mid,
+        ],
+        [
+            mid,
+            (cp[1] + 2. * cp[2] + cp[3]) / 4.,
+            (cp[2] + cp[3]) / 2.,
+            cp[3],
+        ],
+    ]
+}
+
+// Cubic Bezier curve swept into a ribbon whose width goes linearly from
+// `width0` to `width1`, for hair, fur and fibers thinner than a pixel or so.
+// See Nakamaru and Ohno, "Ray Tracing for Curves Primitive", as done in pbrt-v3.
+pub struct Curve {
+    control: [Vector3<f64>; 4],
+    width0: f64,
+    width1: f64,
+    // range of u this curve covers along its strand
+    u0: f64,
+    u1: f64,
+    curve_type: CurveType,
+    material: Rc<dyn Material>,
+    // times to split the curve before a piece counts as straight
+    max_depth: u32,
+}
+
+impl Curve {
+    pub fn new(
+        control: [Vector3<f64>; 4],
+        width0: f64,
+        width1: f64,
+        material: Rc<dyn Material>,
+    ) -> Self {
+        // Enough splits to keep the pieces within 5% of the width of straight,
+        // from the largest second difference of the control points.
+        let mut l0: f64 = 0.;
+        for i in 0..2 {
+            let d = control[i] - 2. * control[i + 1] + control[i + 2];
+            l0 = l0.max(d.abs().max());
+        }
+        let eps = width0.max(width1) * 0.05;
+        let r0 = (std::f64::consts::SQRT_2 * 6. * l0 / (8. * eps)).log2() / 2.;
+        Curve {
+            control,
+            width0,
+            width1,
+            u0: 0.,
+            u1: 1.,
+            curve_type: CurveType::Flat,
+            material,
+            max_depth: if r0 > 0. { (r0 as u32).min(10) } else { 0 },
+        }
+    }
+
+    pub fn with_type(mut self, curve_type: CurveType) -> Self {
+        self.curve_type = curve_type;
+        self
+    }
+
+    // Segments of one strand through `points`, with the width tapering from
+    // `width0` at the first point to `width1` at the last, and u running from
+    // 0 to 1 over the whole strand.
+    pub fn strand(
+        points: &[Vector3<f64>],
+        basis: CurveBasis,
+        width0: f64,
+        width1: f64,
+        curve_type: CurveType,
+        material: Rc<dyn Material>,
+    ) -> Vec<Curve> {
+        let segments: Vec<[Vector3<f64>; 4]> = match basis {
+            CurveBasis::Bezier => {
+                assert!(
+                    points.len() >= 4 && (points.len() - 1).is_multiple_of(3),
+                    "Bezier strands need 3n + 1 points"
+                );
+                points
+                    .windows(4)
+                    .step_by(3)
+                    .map(|p| [p[0], p[1], p[2], p[3]])
+                    .collect()
+            }
+            CurveBasis::BSpline => {
+                assert!(points.len() >= 4, "B-spline strands need 4 points or more");
+                points
+                    .windows(4)
+                    .map(|p| {
+                        [
+                            (p[0] + 4. * p[1] + p[2]) / 6.,
+                            (2. * p[1] + p[2]) / 3.,
+                            (p[1] + 2. * p[2]) / 3.,
+                            (p[1] + 4. * p[2] + p[3]) / 6.,
+                        ]
+                    })
+                    .collect()
+            }
+        };
+
+        let n = segments.len() as f64;
+        let width = |u: f64| width0 + (width1 - width0) * u;
+        segments
+            .into_iter()
+            .enumerate()
+            .map(|(i, control)| {
+                let (u0, u1) = (i as f64 / n, (i + 1) as f64 / n);
+                let mut curve = Curve::new(control, width(u0), width(u1), Rc::clone(&material))
+                    .with_type(curve_type);
+                curve.u0 = u0;
+                curve.u1 = u1;
+                curve
+            })
+            .collect()
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        self.width0 + (self.width1 - self.width0) * u
+    }
+
+    // Closest hit on the piece `cp` of the curve covering [u0, u1], with the
+    // control points in ray space: the ray starts at the origin and runs along
+    // +z with unit speed. Returns z, u and v.
+    fn intersect(
+        &self,
+        cp: &[Vector3<f64>; 4],
+        u0: f64,
+        u1: f64,
+        depth: u32,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        // skip pieces whose box, grown by half the width, misses the ray
+        let half_width = 0.5 * self.width(u0).max(self.width(u1));
+        let lo = cp
+            .iter()
+            .fold(Vector3::repeat(f64::INFINITY), |lo, p| lo.inf(p));
+        let hi = cp
+            .iter()
+            .fold(Vector3::repeat(-f64::INFINITY), |hi, p| hi.sup(p));
+        if lo.x - half_width > 0.
+            || hi.x + half_width < 0.
+            || lo.y - half_width > 0.
+            || hi.y + half_width < 0.
+            || lo.z - half_width > z_max
+            || hi.z + half_width < z_min
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let u_mid = (u0 + u1) / 2.;
+            let [first, second] = split_bezier(cp);
+            let hit_first = self.intersect(&first, u0, u_mid, depth - 1, z_min, z_max);
+            let closest = hit_first.map_or(z_max, |(z, _, _)| z);
+            return self
+                .intersect(&second, u_mid, u1, depth - 1, z_min, closest)
+                .or(hit_first);
+        }
+
+        // The piece is nearly straight: only accept rays passing between the
+        // planes perpendicular to it at both ends, so neighbors don't overlap.
+        if (cp[1].x - cp[0].x) * -cp[0].x + (cp[1].y - cp[0].y) * -cp[0].y < 0.
+            || (cp[2].x - cp[3].x) * -cp[3].x + (cp[2].y - cp[3].y) * -cp[3].y < 0.
+        {
+            return None;
+        }
+        // parameter of the point on the chord closest to the ray
+        let (sx, sy) = (cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = sx * sx + sy * sy;
+        if denom == 0. {
+            return None;
+        }
+        let w = (-cp[0].x * sx - cp[0].y * sy) / denom;
+        let u = (u0 + (u1 - u0) * w).clamp(u0, u1);
+        let hit_width = self.width(u);
+
+        let (pc, dpcdw) = eval_bezier(cp, w.clamp(0., 1.));
+        let distance2 = pc.x * pc.x + pc.y * pc.y;
+        if distance2 > hit_width * hit_width * 0.25 || pc.z < z_min || pc.z > z_max {
+            return None;
+        }
+        // v runs across the ribbon, from its right edge to its left
+        let offset = distance2.sqrt() / hit_width;
+        let v = if dpcdw.x * -pc.y + pc.x * dpcdw.y > 0. {
+            0.5 + offset
+        } else {
+            0.5 - offset
+        };
+        Some((pc.z, u, v))
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        // ray space, with x perpendicular to both the ray and the chord
+        let length = ray.direction.norm();
+        let z = ray.direction / length;
+        let x = z.cross(&(self.control[3] - self.control[0]));
+        let x = if x.norm_squared() > 0. {
+            x.normalize()
+        } else {
+            orthonormal_basis(&z).0
+        };
+        let y = z.cross(&x);
+        let to_ray = |v: &Vector3<f64>| Vector3::new(v.dot(&x), v.dot(&y), v.dot(&z));
+        let cp = self.control.map(|p| to_ray(&(p - ray.origin)));
+
+        let (z_hit, u, v) =
+            self.intersect(&cp, 0., 1., self.max_depth, t_min * length, t_max * length)?;
+
+        // Face the ray, and for round curves lean towards the edges like a
+        // tube would, keeping the normal perpendicular to the curve.
+        let (_, dpdu) = eval_bezier(&self.control, u);
+        let tangent = to_ray(&dpdu).normalize();
+        let facing = Vector3::new(0., 0., -1.);
+        let normal = match self.curve_type {
+            CurveType::Flat => facing,
+            CurveType::Round => {
+                let side = Vector3::new(-tangent.y, tangent.x, 0.).normalize();
+                let angle = (v - 0.5) * PI;
+                angle.cos() * facing + angle.sin() * side
+            }
+        };
+        let normal = (normal - tangent * normal.dot(&tangent)).normalize();
+        let outward_normal = x * normal.x + y * normal.y + z * normal.z;
+
+        Some(
+            HitRecord::new(
+                ray,
+                z_hit / length,
+                &outward_normal,
+                (self.u0 + (self.u1 - self.u0) * u, v),
+                Rc::clone(&self.material),
+            )
+            .with_tangent(dpdu),
+        )
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let half_width = 0.5 * self.width0.max(self.width1);
+        let aabb = self.control[1..]
+            .iter()
+            .fold(Aabb::new(self.control[0], self.control[0]), |aabb, p| {
+                aabb.surrounding(&Aabb::new(*p, *p))
+            });
+        Some(aabb.padded(half_width))
+    }
+}
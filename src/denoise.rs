@@ -0,0 +1,238 @@
+// Edge-avoiding a-trous wavelet filter that blurs away the noise of a few
+// samples per pixel while the albedo, normal and depth buffers keep edges
+// sharp. As in SVGF, the lighting is filtered with the albedo divided out,
+// so textures survive, and the sample variance sets how far colors may
+// differ and still be averaged.
+// See https://jo.dreggn.org/home/2010_atrous.pdf and
+// https://research.nvidia.com/publication/2017-07_spatiotemporal-variance-guided-filtering-real-time-reconstruction-path-traced
+
+use nalgebra::Vector3;
+
+use crate::framebuffer::Framebuffer;
+use crate::utils::luminance;
+use crate::Color;
+
+// B3 spline, applied with holes of 2^i pixels in iteration i
+const KERNEL: [f64; 3] = [3. / 8., 1. / 4., 1. / 16.];
+
+pub struct Denoiser {
+    iterations: u32,
+    sigma_luminance: f64,
+    normal_power: f64,
+    sigma_depth: f64,
+    sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Denoiser {
+            iterations: 5,
+            sigma_luminance: 4.,
+            normal_power: 128.,
+            sigma_depth: 1.,
+            sigma_albedo: 0.1,
+        }
+    }
+
+    // Each iteration doubles the reach, 5 covers 125 pixels across
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    // Luminance differences allowed, in standard deviations of the noise
+    pub fn with_sigma_luminance(mut self, sigma: f64) -> Self {
+        self.sigma_luminance = sigma;
+        self
+    }
+
+    // Higher powers stop the filter at smaller changes of the normal
+    pub fn with_normal_power(mut self, power: f64) -> Self {
+        self.normal_power = power;
+        self
+    }
+
+    // Depth differences allowed, relative to what the slope of the surface
+    // explains
+    pub fn with_sigma_depth(mut self, sigma: f64) -> Self {
+        self.sigma_depth = sigma;
+        self
+    }
+
+    pub fn with_sigma_albedo(mut self, sigma: f64) -> Self {
+        self.sigma_albedo = sigma;
+        self
+    }
+
+    // Filtered linear colors, row by row from the top left
+    pub fn denoise(&self, framebuffer: &Framebuffer) -> Vec<Color> {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let aovs = &framebuffer.aovs;
+        let len = (width * height) as usize;
+        let mut albedo = Vec::with_capacity(len);
+        let mut normal = Vec::with_capacity(len);
+        let mut depth = Vec::with_capacity(len);
+        let mut lighting = Vec::with_capacity(len);
+        let mut variance = Vec::with_capacity(len);
+        for y in 0..height {
+            for x in 0..width {
+                let a = aovs.albedo(x, y).map(|c| c.max(1e-3));
+                let n = aovs.normal(x, y);
+                albedo.push(a);
+                normal.push(if n.norm() > 0. { n.normalize() } else { n });
+                depth.push(aovs.depth(x, y));
+                lighting.push(framebuffer.color(x, y).component_div(&a));
+                // variance of the mean of the samples
+                let samples = framebuffer.samples(x, y).max(1) as f64;
+                let v = framebuffer
+                    .variance(x, y)
+                    .component_div(&a.component_mul(&a));
+                variance.push(luminance(&v) / samples);
+            }
+        }
+
+        let (width, height) = (width as usize, height as usize);
+        let index = |x: usize, y: usize| y * width + x;
+        let gradient = depth_gradient(&depth, width, height);
+
+        for i in 0..self.iterations {
+            let step = 1 << i;
+            // the variance estimate of few samples is itself noisy, so the
+            // luminance weights use a blurred one
+            let blurred = blur(&variance, width, height);
+            let mut next_lighting = Vec::with_capacity(len);
+            let mut next_variance = Vec::with_capacity(len);
+            for y in 0..height {
+                for x in 0..width {
+                    let p = index(x, y);
+                    let luminance_p = luminance(&lighting[p]);
+                    let sigma_l = self.sigma_luminance * blurred[p].sqrt() + 1e-10;
+                    let mut sum = Color::zeros();
+                    let mut sum_variance = 0.;
+                    let mut sum_weight = 0.;
+                    for dy in -2..=2_isize {
+                        let qy = y as isize + dy * step;
+                        if qy < 0 || qy >= height as isize {
+                            continue;
+                        }
+                        for dx in -2..=2_isize {
+                            let qx = x as isize + dx * step;
+                            if qx < 0 || qx >= width as isize {
+                                continue;
+                            }
+                            let q = index(qx as usize, qy as usize);
+                            let kernel = KERNEL[dx.unsigned_abs()] * KERNEL[dy.unsigned_abs()];
+                            let w_luminance =
+                                (-(luminance_p - luminance(&lighting[q])).abs() / sigma_l).exp();
+                            let w_normal = self.normal_weight(&normal[p], &normal[q]);
+                            let offset = ((dx * step).abs() as f64, (dy * step).abs() as f64);
+                            let w_depth =
+                                self.depth_weight(depth[p], depth[q], gradient[p], offset);
+                            let w_albedo = (-(albedo[p] - albedo[q]).norm_squared()
+                                / (self.sigma_albedo * self.sigma_albedo))
+                                .exp();
+                            let weight = kernel * w_luminance * w_normal * w_depth * w_albedo;
+                            sum += weight * lighting[q];
+                            sum_variance += weight * weight * variance[q];
+                            sum_weight += weight;
+                        }
+                    }
+                    // the center always has a weight, of at least the kernel's
+                    next_lighting.push(sum / sum_weight);
+                    next_variance.push(sum_variance / (sum_weight * sum_weight));
+                }
+            }
+            lighting = next_lighting;
+            variance = next_variance;
+        }
+
+        lighting
+            .iter()
+            .zip(&albedo)
+            .map(|(l, a)| l.component_mul(a))
+            .collect()
+    }
+
+    // Zero vectors stand for the background
+    fn normal_weight(&self, p: &Vector3<f64>, q: &Vector3<f64>) -> f64 {
+        match (p.norm() > 0., q.norm() > 0.) {
+            (false, false) => 1.,
+            (true, true) => p.dot(q).max(0.).powf(self.normal_power),
+            _ => 0.,
+        }
+    }
+
+    fn depth_weight(&self, p: f64, q: f64, gradient: (f64, f64), offset: (f64, f64)) -> f64 {
+        match (p.is_finite(), q.is_finite()) {
+            (false, false) => 1.,
+            (true, true) => {
+                let expected = gradient.0 * offset.0 + gradient.1 * offset.1;
+                (-(p - q).abs() / (self.sigma_depth * expected + 1e-3 * p)).exp()
+            }
+            _ => 0.,
+        }
+    }
+}
+
+// Change of depth per pixel in x and y, taking the smaller of the one-sided
+// differences so the slope doesn't jump across silhouettes
+fn depth_gradient(depth: &[f64], width: usize, height: usize) -> Vec<(f64, f64)> {
+    let difference = |p: usize, q: Option<usize>| {
+        q.map(|q| (depth[q] - depth[p]).abs())
+            .filter(|d| d.is_finite())
+    };
+    let slope = |p: usize, before: Option<usize>, after: Option<usize>| match (
+        difference(p, before),
+        difference(p, after),
+    ) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(d), None) | (None, Some(d)) => d,
+        (None, None) => 0.,
+    };
+    let mut gradient = Vec::with_capacity(depth.len());
+    for y in 0..height {
+        for x in 0..width {
+            let p = y * width + x;
+            gradient.push((
+                slope(p, (x > 0).then(|| p - 1), (x + 1 < width).then_some(p + 1)),
+                slope(
+                    p,
+                    (y > 0).then(|| p - width),
+                    (y + 1 < height).then_some(p + width),
+                ),
+            ));
+        }
+    }
+    gradient
+}
+
+// 3x3 Gaussian
+fn blur(values: &[f64], width: usize, height: usize) -> Vec<f64> {
+    const WEIGHTS: [f64; 2] = [1. / 2., 1. / 4.];
+    let mut blurred = Vec::with_capacity(values.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.;
+            let mut sum_weight = 0.;
+            for dy in -1..=1_isize {
+                for dx in -1..=1_isize {
+                    let (qx, qy) = (x as isize + dx, y as isize + dy);
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    let weight = WEIGHTS[dx.unsigned_abs()] * WEIGHTS[dy.unsigned_abs()];
+                    sum += weight * values[qy as usize * width + qx as usize];
+                    sum_weight += weight;
+                }
+            }
+            blurred.push(sum / sum_weight);
+        }
+    }
+    blurred
+}
@@ -0,0 +1,393 @@
+// Scenes described in JSON, for programs that bring their own scenes, like
+// the render service:
+//
+//     {
+//       "camera": {"look_from": [13, 2, 3], "look_at": [0, 0, 0], "vfov": 20},
+//       "materials": {
+//         "ground": {"type": "lambertian", "albedo": [0.5, 0.5, 0.5]}
+//       },
+//       "objects": [
+//         {"type": "sphere", "center": [0, -1000, 0], "radius": 1000,
+//          "material": "ground"},
+//         {"type": "sphere", "center": [0, 1, 0], "radius": 1,
+//          "material": {"type": "dielectric", "ior": 1.5}}
+//       ],
+//       "lights": [
+//         {"type": "point", "position": [0, 10, 0], "intensity": [100, 100, 100]}
+//       ]
+//     }
+//
+// The camera is a perspective camera with "look_from", "look_at", "vup"
+// ([0, 1, 0] by default), "vfov" in degrees (20), "aperture" (0) and
+// "focus_distance" (from look_from to look_at).
+//
+// Materials are "lambertian" with an "albedo", "metal" with an "albedo" and
+// a "fuzz" (0), or "dielectric" with an "ior". Objects take the name of one
+// of the "materials" or a material of their own.
+//
+// Objects are
+//     "sphere": "center", "radius"
+//     "plane": "point", "normal", "uv_scale" (1)
+//     "disk": "center", "normal", "radius"
+//     "box": "min", "max"
+//     "cylinder", "cone": "center" of the base, "radius", "height",
+//         "capped" (false)
+//     "torus": "center", "major_radius", "minor_radius"
+//     "medium": fog of a "density" and an "albedo" filling a convex
+//         "boundary" object, which needs no material
+// and any of them can be scaled by "scale", then rotated by "rotate"
+// ({"axis": [x, y, z], "angle": degrees}), then moved by "translate".
+//
+// Lights are
+//     "point": "position", "intensity"
+//     "spot": "position", "look_at", "intensity", "inner_angle",
+//         "outer_angle", both half-angles in degrees
+//     "directional": "direction" the light travels in, "irradiance",
+//         "angular_diameter" in degrees (0)
+//
+// The background is the sky of the built-in scenes.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::camera::{Camera, PerspectiveCamera};
+use crate::hit::{Hittable, HittableList, Sphere};
+use crate::instance::{Instance, Transform};
+use crate::json::Json;
+use crate::light::{DirectionalLight, Light, PointLight, SpotLight};
+use crate::material::{Dielectic, Lambertian, Material, Metal};
+use crate::medium::ConstantMedium;
+use crate::plane::{Disk, Plane};
+use crate::quadric::{Cone, Cylinder, Torus};
+use crate::rect::Cuboid;
+use crate::{Color, Scene, ASPECT_RATIO};
+
+type Materials = HashMap<String, Rc<dyn Material>>;
+
+// The scene `description` describes, or what's wrong with it
+pub fn build(description: &Json) -> Result<Scene<Box<dyn Hittable>>, String> {
+    let fields = Fields::new(
+        description,
+        "the scene",
+        &["camera", "materials", "objects", "lights"],
+    )?;
+    let camera = camera(&Fields::new(
+        fields.required("camera")?,
+        "the camera",
+        &[
+            "look_from",
+            "look_at",
+            "vup",
+            "vfov",
+            "aperture",
+            "focus_distance",
+        ],
+    )?)?;
+
+    let mut materials = Materials::new();
+    if let Some(json) = fields.get("materials") {
+        let Json::Object(members) = json else {
+            return Err("\"materials\" is an object of named materials".to_string());
+        };
+        for (name, json) in members {
+            materials.insert(name.clone(), material(json, &format!("material {}", name))?);
+        }
+    }
+
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+    for (i, json) in fields.array("objects")?.iter().enumerate() {
+        world.add(object(json, &format!("object {}", i), &materials, false)?);
+    }
+    let mut lights: Vec<Box<dyn Light>> = Vec::new();
+    for (i, json) in fields.array("lights")?.iter().enumerate() {
+        lights.push(light(json, &format!("light {}", i))?);
+    }
+    Ok((world, lights, camera))
+}
+
+fn camera(fields: &Fields) -> Result<Box<dyn Camera>, String> {
+    let look_from = fields.vector("look_from")?;
+    let look_at = fields.vector("look_at")?;
+    let vup = fields.optional_vector("vup")?.unwrap_or(Vector3::y());
+    let focus_distance = match fields.optional_number("focus_distance")? {
+        Some(distance) => distance,
+        None => (look_from - look_at).norm(),
+    };
+    if look_from == look_at || vup.cross(&(look_from - look_at)).norm() == 0. {
+        return Err("the camera needs to look somewhere off vup".to_string());
+    }
+    Ok(Box::new(PerspectiveCamera::new(
+        look_from,
+        look_at,
+        vup,
+        fields.optional_number("vfov")?.unwrap_or(20.),
+        ASPECT_RATIO,
+        fields.optional_number("aperture")?.unwrap_or(0.),
+        focus_distance,
+    )))
+}
+
+fn material(json: &Json, what: &str) -> Result<Rc<dyn Material>, String> {
+    let fields = Fields::new(json, what, &["type", "albedo", "fuzz", "ior"])?;
+    Ok(match fields.kind()? {
+        "lambertian" => Rc::new(Lambertian::new(fields.vector("albedo")?)),
+        "metal" => Rc::new(Metal::new(
+            fields.vector("albedo")?,
+            fields.optional_number("fuzz")?.unwrap_or(0.),
+        )),
+        "dielectric" => Rc::new(Dielectic::new(fields.number("ior")?)),
+        kind => return Err(format!("{} has an unknown type {}", what, kind)),
+    })
+}
+
+// The boundary of a medium only gives it its shape, so it needs no material.
+fn object(
+    json: &Json,
+    what: &str,
+    materials: &Materials,
+    boundary: bool,
+) -> Result<Box<dyn Hittable>, String> {
+    let fields = Fields::new(
+        json,
+        what,
+        &[
+            "type",
+            "material",
+            "center",
+            "radius",
+            "point",
+            "normal",
+            "uv_scale",
+            "min",
+            "max",
+            "height",
+            "capped",
+            "major_radius",
+            "minor_radius",
+            "boundary",
+            "density",
+            "albedo",
+            "scale",
+            "rotate",
+            "translate",
+        ],
+    )?;
+    let kind = fields.kind()?;
+    let material = || match fields.get("material") {
+        Some(Json::String(name)) => materials
+            .get(name)
+            .cloned()
+            .ok_or(format!("{} has an unknown material {}", what, name)),
+        Some(json) => material(json, &format!("the material of {}", what)),
+        None if boundary => Ok(Rc::new(Lambertian::new(Color::zeros())) as Rc<dyn Material>),
+        None => Err(format!("{} needs \"material\"", what)),
+    };
+    let capped = || match fields.get("capped") {
+        Some(capped) => capped
+            .as_bool()
+            .ok_or(format!("\"capped\" of {} is true or false", what)),
+        None => Ok(false),
+    };
+    let object: Box<dyn Hittable> = match kind {
+        "sphere" => Box::new(Sphere {
+            center: fields.vector("center")?,
+            radius: fields.number("radius")?,
+            material: material()?,
+        }),
+        "plane" => Box::new(Plane::new(
+            fields.vector("point")?,
+            fields.vector("normal")?,
+            fields.optional_number("uv_scale")?.unwrap_or(1.),
+            material()?,
+        )),
+        "disk" => Box::new(Disk::new(
+            fields.vector("center")?,
+            fields.vector("normal")?,
+            fields.number("radius")?,
+            material()?,
+        )),
+        "box" => Box::new(Cuboid::new(
+            fields.vector("min")?,
+            fields.vector("max")?,
+            material()?,
+        )),
+        "cylinder" => Box::new(Cylinder {
+            center: fields.vector("center")?,
+            radius: fields.number("radius")?,
+            height: fields.number("height")?,
+            capped: capped()?,
+            material: material()?,
+        }),
+        "cone" => Box::new(Cone {
+            center: fields.vector("center")?,
+            radius: fields.number("radius")?,
+            height: fields.number("height")?,
+            capped: capped()?,
+            material: material()?,
+        }),
+        "torus" => Box::new(Torus {
+            center: fields.vector("center")?,
+            major_radius: fields.number("major_radius")?,
+            minor_radius: fields.number("minor_radius")?,
+            material: material()?,
+        }),
+        "medium" => Box::new(ConstantMedium::new(
+            object(
+                fields.required("boundary")?,
+                &format!("the boundary of {}", what),
+                materials,
+                true,
+            )?,
+            fields.number("density")?,
+            fields.vector("albedo")?,
+        )),
+        kind => return Err(format!("{} has an unknown type {}", what, kind)),
+    };
+
+    let mut transform = Transform::identity();
+    let mut transformed = false;
+    if let Some(scale) = fields.optional_vector("scale")? {
+        if scale.iter().any(|&s| s == 0.) {
+            return Err(format!("\"scale\" of {} can't be 0", what));
+        }
+        transform = transform.then(&Transform::scaling(scale));
+        transformed = true;
+    }
+    if let Some(rotate) = fields.get("rotate") {
+        let what = format!("\"rotate\" of {}", what);
+        let rotate = Fields::new(rotate, &what, &["axis", "angle"])?;
+        let axis = rotate.vector("axis")?;
+        if axis.norm() == 0. {
+            return Err(format!("\"axis\" of {} can't be 0", what));
+        }
+        transform = transform.then(&Transform::rotation(axis, rotate.number("angle")?));
+        transformed = true;
+    }
+    if let Some(offset) = fields.optional_vector("translate")? {
+        transform = transform.then(&Transform::translation(offset));
+        transformed = true;
+    }
+    Ok(if transformed {
+        Box::new(Instance::new(object, transform))
+    } else {
+        object
+    })
+}
+
+fn light(json: &Json, what: &str) -> Result<Box<dyn Light>, String> {
+    let fields = Fields::new(
+        json,
+        what,
+        &[
+            "type",
+            "position",
+            "look_at",
+            "intensity",
+            "inner_angle",
+            "outer_angle",
+            "direction",
+            "irradiance",
+            "angular_diameter",
+        ],
+    )?;
+    Ok(match fields.kind()? {
+        "point" => Box::new(PointLight::new(
+            fields.vector("position")?,
+            fields.vector("intensity")?,
+        )),
+        "spot" => Box::new(SpotLight::new(
+            fields.vector("position")?,
+            fields.vector("look_at")?,
+            fields.vector("intensity")?,
+            fields.number("inner_angle")?,
+            fields.number("outer_angle")?,
+        )),
+        "directional" => Box::new(DirectionalLight::new(
+            fields.vector("direction")?,
+            fields.vector("irradiance")?,
+            fields.optional_number("angular_diameter")?.unwrap_or(0.),
+        )),
+        kind => return Err(format!("{} has an unknown type {}", what, kind)),
+    })
+}
+
+// The members of a JSON object, with `what` it describes for the errors
+struct Fields<'a> {
+    json: &'a Json,
+    what: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    // Fails for anything but an object with only the `known` keys
+    fn new(json: &'a Json, what: &'a str, known: &[&str]) -> Result<Self, String> {
+        let Json::Object(members) = json else {
+            return Err(format!("{} is an object", what));
+        };
+        if let Some((key, _)) = members
+            .iter()
+            .find(|(key, _)| !known.contains(&key.as_str()))
+        {
+            return Err(format!("{} has an unknown key \"{}\"", what, key));
+        }
+        Ok(Fields { json, what })
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Json> {
+        self.json.get(key)
+    }
+
+    fn required(&self, key: &str) -> Result<&'a Json, String> {
+        self.get(key)
+            .ok_or(format!("{} needs \"{}\"", self.what, key))
+    }
+
+    fn kind(&self) -> Result<&'a str, String> {
+        self.required("type")?
+            .as_str()
+            .ok_or(format!("\"type\" of {} is a string", self.what))
+    }
+
+    fn array(&self, key: &str) -> Result<&'a [Json], String> {
+        match self.get(key) {
+            Some(Json::Array(values)) => Ok(values),
+            Some(_) => Err(format!("\"{}\" of {} is an array", key, self.what)),
+            None => Ok(&[]),
+        }
+    }
+
+    fn optional_number(&self, key: &str) -> Result<Option<f64>, String> {
+        self.get(key)
+            .map(|json| {
+                json.as_f64()
+                    .ok_or(format!("\"{}\" of {} is a number", key, self.what))
+            })
+            .transpose()
+    }
+
+    fn number(&self, key: &str) -> Result<f64, String> {
+        self.required(key)?;
+        Ok(self.optional_number(key)?.unwrap())
+    }
+
+    fn optional_vector(&self, key: &str) -> Result<Option<Vector3<f64>>, String> {
+        let error = || format!("\"{}\" of {} is an array of 3 numbers", key, self.what);
+        match self.get(key) {
+            Some(Json::Array(values)) if values.len() == 3 => {
+                let mut vector = Vector3::zeros();
+                for (i, value) in values.iter().enumerate() {
+                    vector[i] = value.as_f64().ok_or_else(error)?;
+                }
+                Ok(Some(vector))
+            }
+            Some(_) => Err(error()),
+            None => Ok(None),
+        }
+    }
+
+    fn vector(&self, key: &str) -> Result<Vector3<f64>, String> {
+        self.required(key)?;
+        Ok(self.optional_vector(key)?.unwrap())
+    }
+}
@@ -0,0 +1,339 @@
+// Distributed rendering of the still over TCP. A coordinator splits the
+// image into tiles and hands them out to the worker processes connected to
+// it, which build the same scene from the same seed. A tile's samples only
+// depend on the seed and the tile, so the merged image doesn't depend on
+// which worker rendered what. Tiles of workers that disconnect or go
+// silent go back to the queue for the others.
+//
+// The coordinator opens with the job: a magic number, the settings and the
+// seed. Then it sends 1 followed by a tile (index as u64, then x, y, width
+// and height as u32) for every tile until it sends 0 for the end. The
+// worker answers a tile with 1, the length as u64 and the state of the
+// tile's framebuffer, and sends 2 every few seconds as a heartbeat. A worker
+// that stays silent for longer, because its machine lost power or left the
+// network, counts as gone. Everything is little-endian.
+
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::checkpoint::{
+    invalid_data, read_settings, read_u32s, read_u64s, write_settings, write_u32s, write_u64s,
+    Checkpoint,
+};
+use crate::framebuffer::Framebuffer;
+use crate::{framebuffer_size, resume_still, Mode, Settings};
+
+const MAGIC: &[u8; 8] = b"RTJOB002";
+// Side of the square tiles, in framebuffer pixels
+const TILE_SIZE: u32 = 64;
+// How often workers send a heartbeat, and how long the coordinator waits for
+// one before it gives their tile to another worker
+const HEARTBEAT: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// What workers send
+const TILE: u8 = 1;
+const ALIVE: u8 = 2;
+
+// x, y, width and height in framebuffer pixels
+type Tile = (u32, u32, u32, u32);
+
+// Renders the still with the workers that connect to `listener`, which may
+// also be started before. Settings with a time limit are refused, as the
+// result would depend on the speed of the workers.
+pub fn coordinate(listener: TcpListener, settings: Settings, seed: u64) -> io::Result<Framebuffer> {
+    let (width, height) = framebuffer_size();
+    coordinate_region(listener, settings, seed, (0, 0, width, height), TILE_SIZE)
+}
+
+// Renders `region` of the still in tiles of `tile_size` pixels
+fn coordinate_region(
+    listener: TcpListener,
+    settings: Settings,
+    seed: u64,
+    region: Tile,
+    tile_size: u32,
+) -> io::Result<Framebuffer> {
+    if let Mode::Time(_) = settings.mode {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "distributed renders can't stop after a time limit",
+        ));
+    }
+    let (left, top, width, height) = region;
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            tiles.push((
+                left + x,
+                top + y,
+                tile_size.min(width - x),
+                tile_size.min(height - y),
+            ));
+        }
+    }
+    let mut job = MAGIC.to_vec();
+    write_settings(&mut job, &settings)?;
+    write_u64s(&mut job, &[seed])?;
+    let job = Arc::new(job);
+    let tiles = Arc::new(tiles);
+    let queue = Arc::new(Mutex::new((0..tiles.len()).collect::<VecDeque<_>>()));
+    let done = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    {
+        let (tiles, queue, done) = (tiles.clone(), queue.clone(), done.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (job, tiles, queue, done, sender) = (
+                    job.clone(),
+                    tiles.clone(),
+                    queue.clone(),
+                    done.clone(),
+                    sender.clone(),
+                );
+                thread::spawn(move || serve(stream, &job, &tiles, &queue, &done, &sender));
+            }
+        });
+    }
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    let mut finished = 0;
+    while finished < tiles.len() {
+        let (index, state): (usize, Vec<u8>) = receiver
+            .recv()
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        let (x, y, tile_width, tile_height) = tiles[index];
+        match Framebuffer::read_state(&mut &state[..], tile_width, tile_height) {
+            Ok(tile) => {
+                framebuffer.insert(&tile, x - left, y - top);
+                finished += 1;
+                crate::log!("tile {} of {} done", finished, tiles.len());
+            }
+            Err(e) => {
+                crate::log!("tile {} came back broken, {}", index, e);
+                queue.lock().unwrap().push_back(index);
+            }
+        }
+    }
+    done.store(true, Ordering::Relaxed);
+    Ok(framebuffer)
+}
+
+// Feeds tiles to one worker until they run out or the worker goes away.
+fn serve(
+    stream: TcpStream,
+    job: &[u8],
+    tiles: &[Tile],
+    queue: &Mutex<VecDeque<usize>>,
+    done: &AtomicBool,
+    sender: &Sender<(usize, Vec<u8>)>,
+) {
+    let peer = stream
+        .peer_addr()
+        .map_or("?".to_string(), |address| address.to_string());
+    crate::log!("worker {} joined", peer);
+    let mut current = None;
+    let result = (|| -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut input = BufReader::new(stream.try_clone()?);
+        let mut out = BufWriter::new(stream);
+        out.write_all(job)?;
+        loop {
+            let index = loop {
+                if done.load(Ordering::Relaxed) {
+                    out.write_all(&[0])?;
+                    return out.flush();
+                }
+                // the last tiles may come back from workers that left
+                match queue.lock().unwrap().pop_front() {
+                    Some(index) => break index,
+                    None => thread::sleep(Duration::from_millis(100)),
+                }
+            };
+            current = Some(index);
+            let (x, y, width, height) = tiles[index];
+            out.write_all(&[1])?;
+            write_u64s(&mut out, &[index as u64])?;
+            write_u32s(&mut out, &[x, y, width, height])?;
+            out.flush()?;
+
+            loop {
+                let mut message = [0];
+                input.read_exact(&mut message)?;
+                match message[0] {
+                    ALIVE => continue,
+                    TILE => break,
+                    _ => return Err(invalid_data("unknown message")),
+                }
+            }
+            let len = read_u64s(&mut input, 1)?[0];
+            let mut state = Vec::new();
+            (&mut input).take(len).read_to_end(&mut state)?;
+            if state.len() as u64 != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            current = None;
+            if sender.send((index, state)).is_err() {
+                return Ok(());
+            }
+        }
+    })();
+    if let Err(e) = result {
+        // what read timeouts come back as depends on the platform
+        let reason = match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                format!("silent for {} s", TIMEOUT.as_secs())
+            }
+            _ => e.to_string(),
+        };
+        crate::log!("worker {} left: {}", peer, reason);
+        if let Some(index) = current {
+            queue.lock().unwrap().push_front(index);
+        }
+    }
+}
+
+// Renders the tiles the coordinator at `address` sends until it says
+// they're all done.
+pub fn work(address: &str) -> io::Result<()> {
+    let stream = TcpStream::connect(address)?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let out = Mutex::new(BufWriter::new(stream));
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a render coordinator"));
+    }
+    let settings = read_settings(&mut input)?;
+    let seed = read_u64s(&mut input, 1)?[0];
+    // how many samples a tile got would depend on the speed of this machine
+    if let Mode::Time(_) = settings.mode {
+        return Err(invalid_data(
+            "distributed renders can't stop after a time limit",
+        ));
+    }
+
+    let out = &out;
+    let (stop, stopped) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        // the heartbeat, until the tiles are done or the coordinator is gone
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT) {
+                let mut out = out.lock().unwrap();
+                if out.write_all(&[ALIVE]).and_then(|_| out.flush()).is_err() {
+                    return;
+                }
+            }
+        });
+        let result = render_tiles(&mut input, out, settings, seed);
+        drop(stop);
+        result
+    })
+}
+
+fn render_tiles<R: Read>(
+    input: &mut R,
+    out: &Mutex<BufWriter<TcpStream>>,
+    settings: Settings,
+    seed: u64,
+) -> io::Result<()> {
+    loop {
+        let mut message = [0];
+        input.read_exact(&mut message)?;
+        if message[0] == 0 {
+            return Ok(());
+        }
+        let index = read_u64s(input, 1)?[0];
+        let [x, y, width, height] = read_u32s(input, 4)?[..] else {
+            unreachable!()
+        };
+        // a sample budget is shared out over the tiles by their area
+        let (image_width, image_height) = framebuffer_size();
+        let settings = match settings.mode {
+            Mode::SampleBudget(samples) => Settings {
+                mode: Mode::SampleBudget(
+                    samples * (width * height) as u64 / (image_width * image_height) as u64,
+                ),
+                ..settings
+            },
+            _ => settings,
+        };
+        let mut tile = Checkpoint::tile(settings, seed, index, (x, y), (width, height));
+        resume_still(&mut tile, &mut |_, _| ());
+
+        let mut state = Vec::new();
+        tile.framebuffer.write_state(&mut state)?;
+        let mut out = out.lock().unwrap();
+        out.write_all(&[TILE])?;
+        write_u64s(&mut *out, &[state.len() as u64])?;
+        out.write_all(&state)?;
+        out.flush()?;
+        crate::log!("tile {} rendered", index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_render_matches_the_tiles() {
+        let settings = Settings {
+            samples_per_pixel: 1,
+            noise_threshold: 0.,
+            mode: Mode::Samples,
+        };
+        let seed = 5;
+        // a few small tiles from the middle of the image keep this quick
+        let (image_width, image_height) = framebuffer_size();
+        let region = (image_width / 2, image_height / 2, 10, 6);
+        let tile_size = 4;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let workers = thread::spawn(move || {
+            // a worker that takes the first tile and leaves without rendering it
+            let mut stream = TcpStream::connect(&address)?;
+            let mut magic = [0; 8];
+            stream.read_exact(&mut magic)?;
+            assert_eq!(&magic, MAGIC);
+            read_settings(&mut stream)?;
+            assert_eq!(read_u64s(&mut stream, 1)?, [seed]);
+            let mut message = [0];
+            stream.read_exact(&mut message)?;
+            assert_eq!(message, [1]);
+            assert_eq!(read_u64s(&mut stream, 1)?, [0]);
+            read_u32s(&mut stream, 4)?;
+            drop(stream);
+            // and one that renders them all, that one included
+            work(&address)
+        });
+        let framebuffer = coordinate_region(listener, settings, seed, region, tile_size).unwrap();
+        workers.join().unwrap().unwrap();
+
+        // the same tiles rendered here, in the order they were listed
+        let (left, top, width, height) = region;
+        let mut expected = Framebuffer::new(width, height);
+        let mut index = 0;
+        for y in (0..height).step_by(tile_size as usize) {
+            for x in (0..width).step_by(tile_size as usize) {
+                let size = (tile_size.min(width - x), tile_size.min(height - y));
+                let mut tile = Checkpoint::tile(settings, seed, index, (left + x, top + y), size);
+                resume_still(&mut tile, &mut |_, _| ());
+                expected.insert(&tile.framebuffer, x, y);
+                index += 1;
+            }
+        }
+        let (mut state, mut expected_state) = (Vec::new(), Vec::new());
+        framebuffer.write_state(&mut state).unwrap();
+        expected.write_state(&mut expected_state).unwrap();
+        assert!(state == expected_state);
+    }
+}
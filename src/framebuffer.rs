@@ -0,0 +1,193 @@
+use std::io::{self, Read, Write};
+
+use crate::aov::Aovs;
+use crate::checkpoint::{read_u32s, read_vectors, write_u32s, write_vectors};
+use crate::png::write_png;
+use crate::utils::clamp;
+use crate::Color;
+
+// Linear colors accumulated sample by sample, row by row from the top left,
+// along with the auxiliary buffers of the first hits.
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Color>,
+    // sums of the squared samples, for the variance
+    squares: Vec<Color>,
+    samples: Vec<u32>,
+    pub aovs: Aovs,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Framebuffer {
+            width,
+            height,
+            sums: vec![Color::zeros(); len],
+            squares: vec![Color::zeros(); len],
+            samples: vec![0; len],
+            aovs: Aovs::new(width, height),
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
+        let i = self.index(x, y);
+        self.sums[i] += color;
+        self.squares[i] += color.component_mul(&color);
+        self.samples[i] += 1;
+    }
+
+    // Average of the samples, black before the first one
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        match self.samples[i] {
+            0 => Color::zeros(),
+            n => self.sums[i] / n as f64,
+        }
+    }
+
+    pub fn samples(&self, x: u32, y: u32) -> u32 {
+        self.samples[self.index(x, y)]
+    }
+
+    // Unbiased variance of the samples of each channel, zero until there
+    // are two of them
+    pub fn variance(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        match self.samples[i] {
+            0 | 1 => Color::zeros(),
+            n => {
+                let mean = self.sums[i] / n as f64;
+                (self.squares[i] / n as f64 - mean.component_mul(&mean)).map(|v| v.max(0.))
+                    * (n as f64 / (n - 1) as f64)
+            }
+        }
+    }
+
+    // The averages of all pixels, row by row from the top left
+    pub fn colors(&self) -> Vec<Color> {
+        let mut colors = Vec::with_capacity(self.sums.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                colors.push(self.color(x, y));
+            }
+        }
+        colors
+    }
+
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        rgb8(&self.colors())
+    }
+
+    pub fn write_ppm<W>(&self, out: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write_ppm(out, self.width, self.height, |x, y| {
+            to_rgb8(self.color(x, y))
+        })
+    }
+
+    pub fn write_png<W>(&self, out: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write_png(out, self.width, self.height, |x, y| {
+            to_rgb8(self.color(x, y))
+        })
+    }
+
+    // The linear colors, without clamping
+    pub fn write_pfm<W>(&self, out: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write_pfm(out, self.width, self.height, |x, y| self.color(x, y))
+    }
+
+    // Copies the samples of `tile`, a part of this framebuffer starting at
+    // (x, y), into it
+    pub fn insert(&mut self, tile: &Framebuffer, x: u32, y: u32) {
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                let (i, j) = (self.index(x + tx, y + ty), tile.index(tx, ty));
+                self.sums[i] = tile.sums[j];
+                self.squares[i] = tile.squares[j];
+                self.samples[i] = tile.samples[j];
+            }
+        }
+        self.aovs.insert(&tile.aovs, x, y);
+    }
+
+    // The accumulated samples, for checkpoints
+    pub(crate) fn write_state<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_vectors(out, &self.sums)?;
+        write_vectors(out, &self.squares)?;
+        write_u32s(out, &self.samples)?;
+        self.aovs.write_state(out)
+    }
+
+    pub(crate) fn read_state<R: Read>(input: &mut R, width: u32, height: u32) -> io::Result<Self> {
+        let len = (width * height) as usize;
+        Ok(Framebuffer {
+            width,
+            height,
+            sums: read_vectors(input, len)?,
+            squares: read_vectors(input, len)?,
+            samples: read_u32s(input, len)?,
+            aovs: Aovs::read_state(input, width, height)?,
+        })
+    }
+}
+
+// 8-bit RGB, gamma-corrected for gamma=2.0
+pub fn to_rgb8(color: Color) -> [u8; 3] {
+    color
+        .map(|c| (256. * clamp(c.sqrt(), 0., 0.999)) as u8)
+        .into()
+}
+
+// 8-bit RGB triples of a whole image
+pub fn rgb8(colors: &[Color]) -> Vec<u8> {
+    colors.iter().flat_map(|&color| to_rgb8(color)).collect()
+}
+
+// Binary PPM, which most image viewers open
+pub fn write_ppm<W, F>(mut out: W, width: u32, height: u32, pixel: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(u32, u32) -> [u8; 3],
+{
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    for y in 0..height {
+        for x in 0..width {
+            out.write_all(&pixel(x, y))?;
+        }
+    }
+    out.flush()
+}
+
+// Portable float map, the HDR counterpart of PPM that compositing tools and
+// denoisers read. Rows go from the bottom up.
+// See https://www.pauldebevec.com/Research/HDR/PFM/
+pub fn write_pfm<W, F>(mut out: W, width: u32, height: u32, pixel: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(u32, u32) -> Color,
+{
+    // a negative scale means little-endian
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for c in pixel(x, y).iter() {
+                out.write_all(&(*c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
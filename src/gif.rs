@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Animated GIF looping forever. Every frame gets its own 256-color palette
+// from median cut, with Floyd-Steinberg dithering to hide the banding.
+// See https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+pub struct GifWriter<W>
+where
+    W: Write,
+{
+    out: W,
+    width: usize,
+    height: usize,
+    // in hundredths of a second
+    delay: u16,
+}
+
+impl<W> GifWriter<W>
+where
+    W: Write,
+{
+    pub fn new(mut out: W, width: u32, height: u32, fps: u32) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        // logical screen without a global palette
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        out.write_all(&[0, 0, 0])?;
+        // Netscape extension, repeating forever
+        out.write_all(&[0x21, 0xff, 11])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[3, 1, 0, 0, 0])?;
+        Ok(GifWriter {
+            out,
+            width: width as usize,
+            height: height as usize,
+            delay: (100. / fps as f64).round() as u16,
+        })
+    }
+
+    // `rgb` holds the frame as 8-bit RGB triples, row by row from the top.
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let pixels: Vec<[u8; 3]> = rgb.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+        let palette = median_cut(&pixels, 256);
+        let indices = dither(&pixels, self.width, &palette);
+
+        // graphic control extension with the frame delay
+        self.out.write_all(&[0x21, 0xf9, 4, 0])?;
+        self.out.write_all(&self.delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+
+        // image descriptor covering the screen, with a local palette
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x80 | 7])?;
+        for i in 0..256 {
+            self.out
+                .write_all(&palette.get(i).copied().unwrap_or([0, 0, 0]))?;
+        }
+
+        self.out.write_all(&[8])?;
+        for block in lzw_encode(&indices, 8).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Splits the colors into boxes, always halving the box with the widest
+// channel range at its median, and returns the mean color of each box.
+// See https://en.wikipedia.org/wiki/Median_cut
+fn median_cut(pixels: &[[u8; 3]], colors: usize) -> Vec<[u8; 3]> {
+    let range = |colors: &[[u8; 3]]| -> (u8, usize) {
+        (0..3)
+            .map(|c| {
+                let (lo, hi) = colors
+                    .iter()
+                    .fold((255, 0), |(lo, hi), p| (p[c].min(lo), p[c].max(hi)));
+                (hi - lo, c)
+            })
+            .max()
+            .unwrap()
+    };
+
+    // boxes along with their widest channel range
+    let mut boxes = vec![(range(pixels), pixels.to_vec())];
+    while boxes.len() < colors {
+        let (i, &((width, channel), _)) = boxes
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, ((width, _), _))| *width)
+            .unwrap();
+        if width == 0 {
+            break;
+        }
+        let (_, mut lower) = boxes.swap_remove(i);
+        lower.sort_unstable_by_key(|p| p[channel]);
+        let upper = lower.split_off(lower.len() / 2);
+        boxes.push((range(&lower), lower));
+        boxes.push((range(&upper), upper));
+    }
+
+    boxes
+        .iter()
+        .filter(|(_, b)| !b.is_empty())
+        .map(|(_, b)| {
+            let mut sum = [0usize; 3];
+            for p in b {
+                for c in 0..3 {
+                    sum[c] += p[c] as usize;
+                }
+            }
+            sum.map(|s| ((s + b.len() / 2) / b.len()) as u8)
+        })
+        .collect()
+}
+
+// Palette index of every pixel, spreading the error of each choice over the
+// pixels still to come.
+// See https://en.wikipedia.org/wiki/Floyd%E2%80%93Steinberg_dithering
+fn dither(pixels: &[[u8; 3]], width: usize, palette: &[[u8; 3]]) -> Vec<u8> {
+    let height = pixels.len() / width;
+    let mut colors: Vec<[f64; 3]> = pixels.iter().map(|p| p.map(|c| c as f64)).collect();
+    // nearest palette entry, cached on 5 bits per channel
+    let mut nearest = vec![None; 1 << 15];
+    let mut indices = Vec::with_capacity(pixels.len());
+
+    for y in 0..height {
+        for x in 0..width {
+            let color = colors[y * width + x].map(|c| c.clamp(0., 255.));
+            let [r, g, b] = color.map(|c| c as usize >> 3);
+            let key = (r << 10) | (g << 5) | b;
+            let index = *nearest[key].get_or_insert_with(|| {
+                let distance =
+                    |p: &[u8; 3]| -> f64 { (0..3).map(|c| (p[c] as f64 - color[c]).powi(2)).sum() };
+                (0..palette.len())
+                    .min_by(|&i, &j| distance(&palette[i]).total_cmp(&distance(&palette[j])))
+                    .unwrap() as u8
+            });
+            indices.push(index);
+
+            let chosen = palette[index as usize];
+            let error: [f64; 3] = std::array::from_fn(|c| color[c] - chosen[c] as f64);
+            let mut spread = |dx: isize, dy: usize, weight: f64| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    let neighbour = &mut colors[(y + dy) * width + nx as usize];
+                    for c in 0..3 {
+                        neighbour[c] += error[c] * weight;
+                    }
+                }
+            };
+            spread(1, 0, 7. / 16.);
+            spread(-1, 1, 3. / 16.);
+            spread(0, 1, 5. / 16.);
+            spread(1, 1, 1. / 16.);
+        }
+    }
+    indices
+}
+
+// Codes of varying sizes packed least significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+// Variable-length LZW as GIF uses it, with codes growing up to 12 bits.
+fn lzw_encode(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter {
+        bytes: Vec::new(),
+        bits: 0,
+        count: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = min_code_size + 1;
+    let mut next = end + 1;
+    out.write(clear, size);
+
+    if let Some((&first, rest)) = indices.split_first() {
+        let mut prefix = first as u16;
+        for &k in rest {
+            if let Some(&code) = table.get(&(prefix, k)) {
+                prefix = code;
+                continue;
+            }
+            out.write(prefix, size);
+            if next == 4096 {
+                // the table is full, start over
+                out.write(clear, size);
+                table.clear();
+                size = min_code_size + 1;
+                next = end + 1;
+            } else {
+                table.insert((prefix, k), next);
+                next += 1;
+                if next > 1 << size {
+                    size += 1;
+                }
+            }
+            prefix = k as u16;
+        }
+        out.write(prefix, size);
+    }
+    out.write(end, size);
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads codes the way GIF decoders do, the table one entry behind the
+    // encoder's
+    fn lzw_decode(bytes: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_code_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut out = Vec::new();
+        let (mut bits, mut count, mut pos) = (0u32, 0, 0);
+        loop {
+            while count < size {
+                bits |= (*bytes.get(pos).expect("no end code") as u32) << count;
+                pos += 1;
+                count += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            count -= size;
+
+            if code == clear {
+                table = (0..clear).map(|i| vec![i as u8]).collect();
+                // the clear and end codes take two places
+                table.extend([Vec::new(), Vec::new()]);
+                size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match previous {
+                Some(previous) => {
+                    let entry = if code < table.len() {
+                        table[code].clone()
+                    } else {
+                        assert_eq!(code, table.len(), "code from the future");
+                        let mut entry = table[previous].clone();
+                        entry.push(table[previous][0]);
+                        entry
+                    };
+                    if table.len() < 4096 {
+                        let mut added = table[previous].clone();
+                        added.push(entry[0]);
+                        table.push(added);
+                        if table.len() == 1 << size && size < 12 {
+                            size += 1;
+                        }
+                    }
+                    entry
+                }
+                None => table[code].clone(),
+            };
+            out.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+        assert_eq!(pos, bytes.len(), "bytes after the end code");
+        out
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let mut noise = Vec::new();
+        let mut state = 1u32;
+        for _ in 0..100_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            noise.push((state >> 24) as u8);
+        }
+        // runs of the same index, which repeat a code just being defined
+        let runs: Vec<u8> = (0..20_000).map(|i| (i / 700) as u8).collect();
+        // few colors, which fill the table with long strings
+        let gradient: Vec<u8> = (0..50_000).map(|i| (i % 7 + i / 5000) as u8).collect();
+        for indices in [vec![], vec![42], noise, runs, gradient] {
+            assert_eq!(lzw_decode(&lzw_encode(&indices, 8), 8), indices);
+        }
+    }
+
+    #[test]
+    fn lzw_small_code_size() {
+        let indices: Vec<u8> = (0..10_000u32).map(|i| ((i * i) >> 3 & 3) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 2), 2), indices);
+    }
+}
@@ -0,0 +1,410 @@
+use std::f64::consts::{LN_2, PI};
+
+use nalgebra::Vector3;
+use rand::Rng;
+
+use super::Color;
+use crate::hit::HitRecord;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::*;
+
+// Lobes followed explicitly: R, TT and TRT. Longer paths are lumped into one.
+const P_MAX: usize = 3;
+const SQRT_PI_OVER_8: f64 = 0.626657069;
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.).sqrt()
+}
+
+fn safe_asin(x: f64) -> f64 {
+    x.clamp(-1., 1.).asin()
+}
+
+// Modified Bessel function of the first kind, order 0
+fn i0(x: f64) -> f64 {
+    let mut val = 0.;
+    let mut x2i = 1.;
+    let mut ifact = 1.;
+    let mut i4 = 1.;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f64;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.;
+    }
+    val
+}
+
+fn log_i0(x: f64) -> f64 {
+    if x > 12. {
+        x + 0.5 * (-(2. * PI).ln() + (1. / x).ln() + 1. / (8. * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+// Unpolarized Fresnel reflectance of a dielectric with index of refraction eta
+fn fresnel(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(-1., 1.);
+    let (cos_i, eta_i, eta_t) = if cos_i > 0. {
+        (cos_i, 1., eta)
+    } else {
+        (-cos_i, eta, 1.)
+    };
+    let sin_t = eta_i / eta_t * safe_sqrt(1. - cos_i * cos_i);
+    if sin_t >= 1. {
+        return 1.;
+    }
+    let cos_t = safe_sqrt(1. - sin_t * sin_t);
+    let r_parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let r_perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+// Longitudinal scattering
+fn mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // in log space, as sinh(1 / v) overflows
+        (log_i0(a) - b - 1. / v + LN_2 + (1. / (2. * v)).ln()).exp()
+    } else {
+        (-b).exp() * i0(a) / ((1. / v).sinh() * 2. * v)
+    }
+}
+
+// Attenuation of each lobe by Fresnel reflection and absorption
+fn ap(cos_theta_o: f64, eta: f64, h: f64, transmittance: &Color) -> [Color; P_MAX + 1] {
+    let cos_gamma_o = safe_sqrt(1. - h * h);
+    let f = fresnel(cos_theta_o * cos_gamma_o, eta);
+    let mut ap = [Color::zeros(); P_MAX + 1];
+    ap[0] = Color::repeat(f);
+    ap[1] = (1. - f) * (1. - f) * transmittance;
+    for p in 2..P_MAX {
+        ap[p] = ap[p - 1].component_mul(transmittance) * f;
+    }
+    let rest = (Color::repeat(1.) - transmittance * f).map(|x| 1. / x);
+    ap[P_MAX] = (ap[P_MAX - 1] * f)
+        .component_mul(transmittance)
+        .component_mul(&rest);
+    ap
+}
+
+// Azimuthal angle of the light leaving lobe p
+fn phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    let p = p as f64;
+    2. * p * gamma_t - 2. * gamma_o + p * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1. + (-x / s).exp()) * (1. + (-x / s).exp()))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1. / (1. + (-x / s).exp())
+}
+
+// Logistic distribution restricted to [-PI, PI]
+fn trimmed_logistic(x: f64, s: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(PI, s) - logistic_cdf(-PI, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64) -> f64 {
+    let k = logistic_cdf(PI, s) - logistic_cdf(-PI, s);
+    let x = -s * (1. / (u * k + logistic_cdf(-PI, s)) - 1.).ln();
+    x.clamp(-PI, PI)
+}
+
+// Azimuthal scattering
+fn np(phi_diff: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi_diff - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2. * PI;
+    }
+    while dphi < -PI {
+        dphi += 2. * PI;
+    }
+    trimmed_logistic(dphi, s)
+}
+
+// Hair fiber scattering with Marschner's R, TT and TRT lobes, for Curve.
+// Directions are measured against the curve tangent, and v across the
+// curve gives the offset of the hit from the fiber axis.
+// See Chiang et al., "A Practical and Controllable Hair and Fur Model for
+// Production Path Tracing", as done in pbrt-v3.
+pub struct Hair {
+    eta: f64,
+    // absorption inside the fiber per unit of its radius
+    sigma_a: Color,
+    // longitudinal variance of each lobe
+    v: [f64; P_MAX + 1],
+    // azimuthal logistic scale
+    s: f64,
+    // tilt of the scales on the fiber surface, for 2^k * alpha
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl Hair {
+    // beta_m and beta_n in [0, 1] are the longitudinal and azimuthal roughness,
+    // alpha the tilt of the cuticle scales in degrees (about 2 for human hair).
+    pub fn new(sigma_a: Color, beta_m: f64, beta_n: f64, alpha: f64) -> Self {
+        let mut v = [0.; P_MAX + 1];
+        v[0] = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        v[1] = 0.25 * v[0];
+        v[2] = 4. * v[0];
+        for p in 3..=P_MAX {
+            v[p] = v[2];
+        }
+        let s =
+            SQRT_PI_OVER_8 * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [0.; 3];
+        let mut cos_2k_alpha = [0.; 3];
+        sin_2k_alpha[0] = deg_to_rad(alpha).sin();
+        cos_2k_alpha[0] = safe_sqrt(1. - sin_2k_alpha[0] * sin_2k_alpha[0]);
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2. * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1] * cos_2k_alpha[i - 1]
+                - sin_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+        }
+
+        Hair {
+            eta: 1.55,
+            sigma_a,
+            v,
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    // Natural hair colors from the concentration of the two melanin pigments:
+    // eumelanin from about 0.05 (blonde) to 8 (black), pheomelanin for red.
+    pub fn from_melanin(
+        eumelanin: f64,
+        pheomelanin: f64,
+        beta_m: f64,
+        beta_n: f64,
+        alpha: f64,
+    ) -> Self {
+        let sigma_a =
+            eumelanin * Color::new(0.419, 0.697, 1.37) + pheomelanin * Color::new(0.187, 0.4, 1.05);
+        Hair::new(sigma_a, beta_m, beta_n, alpha)
+    }
+
+    // Absorption giving roughly the given overall color, for dyed hair and fur.
+    pub fn from_color(color: Color, beta_m: f64, beta_n: f64, alpha: f64) -> Self {
+        let b = beta_n;
+        let scale = 5.969 - 0.215 * b + 2.532 * b * b - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5);
+        let sigma_a = color.map(|c| (c.max(1e-4).ln() / scale).powi(2));
+        Hair::new(sigma_a, beta_m, beta_n, alpha)
+    }
+
+    pub fn with_eta(mut self, eta: f64) -> Self {
+        self.eta = eta;
+        self
+    }
+
+    // sin and cos of theta_o tilted by the scales for lobe p
+    fn tilted(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (s, c) = (self.sin_2k_alpha, self.cos_2k_alpha);
+        let (sin_op, cos_op) = match p {
+            0 => (
+                sin_theta_o * c[1] - cos_theta_o * s[1],
+                cos_theta_o * c[1] + sin_theta_o * s[1],
+            ),
+            1 => (
+                sin_theta_o * c[0] + cos_theta_o * s[0],
+                cos_theta_o * c[0] - sin_theta_o * s[0],
+            ),
+            2 => (
+                sin_theta_o * c[2] + cos_theta_o * s[2],
+                cos_theta_o * c[2] - sin_theta_o * s[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin_op, cos_op.abs())
+    }
+
+    // Angle of the refracted ray inside the fiber, and its transmittance
+    // across the fiber, for light leaving along theta_o at offset h.
+    fn refracted(&self, sin_theta_o: f64, cos_theta_o: f64, h: f64) -> (f64, Color) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1. - sin_theta_t * sin_theta_t);
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = h / etap;
+        let cos_gamma_t = safe_sqrt(1. - sin_gamma_t * sin_gamma_t);
+        let transmittance = (-self.sigma_a * (2. * cos_gamma_t / cos_theta_t)).map(f64::exp);
+        (safe_asin(sin_gamma_t), transmittance)
+    }
+
+    // Probability of sampling each lobe, by its share of the attenuation
+    fn lobe_pdf(&self, sin_theta_o: f64, cos_theta_o: f64, h: f64) -> [f64; P_MAX + 1] {
+        let (_, transmittance) = self.refracted(sin_theta_o, cos_theta_o, h);
+        let ap = ap(cos_theta_o, self.eta, h, &transmittance);
+        let total: f64 = ap.iter().map(luminance).sum();
+        ap.map(|a| luminance(&a) / total)
+    }
+
+    // BSDF times |cos theta_i| for directions in the local frame: x along the
+    // fiber, z towards the viewer.
+    fn f(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, h: f64) -> Color {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1. - sin_theta_o * sin_theta_o);
+        let phi_o = wo.z.atan2(wo.y);
+        let sin_theta_i = wi.x;
+        let cos_theta_i = safe_sqrt(1. - sin_theta_i * sin_theta_i);
+        let phi_i = wi.z.atan2(wi.y);
+
+        let gamma_o = safe_asin(h);
+        let (gamma_t, transmittance) = self.refracted(sin_theta_o, cos_theta_o, h);
+        let ap = ap(cos_theta_o, self.eta, h, &transmittance);
+        let phi_diff = phi_i - phi_o;
+
+        let mut fsum = Color::zeros();
+        for (p, ap) in ap.iter().enumerate().take(P_MAX) {
+            let (sin_op, cos_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+            fsum += mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p])
+                * np(phi_diff, p, self.s, gamma_o, gamma_t)
+                * ap;
+        }
+        fsum += mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap[P_MAX]
+            / (2. * PI);
+        fsum
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, h: f64) -> f64 {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1. - sin_theta_o * sin_theta_o);
+        let phi_o = wo.z.atan2(wo.y);
+        let sin_theta_i = wi.x;
+        let cos_theta_i = safe_sqrt(1. - sin_theta_i * sin_theta_i);
+        let phi_i = wi.z.atan2(wi.y);
+
+        let gamma_o = safe_asin(h);
+        let (gamma_t, _) = self.refracted(sin_theta_o, cos_theta_o, h);
+        let lobe_pdf = self.lobe_pdf(sin_theta_o, cos_theta_o, h);
+        let phi_diff = phi_i - phi_o;
+
+        let mut pdf = 0.;
+        for (p, lobe_pdf) in lobe_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_op, cos_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+            pdf += mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p])
+                * lobe_pdf
+                * np(phi_diff, p, self.s, gamma_o, gamma_t);
+        }
+        pdf += mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * lobe_pdf[P_MAX]
+            / (2. * PI);
+        pdf
+    }
+
+    // Picks a lobe, then theta_i from its Mp and phi_i from its Np.
+    fn sample(&self, wo: &Vector3<f64>, h: f64, rng: &mut RenderRng) -> Vector3<f64> {
+        let sin_theta_o = wo.x;
+        let cos_theta_o = safe_sqrt(1. - sin_theta_o * sin_theta_o);
+        let phi_o = wo.z.atan2(wo.y);
+
+        let lobe_pdf = self.lobe_pdf(sin_theta_o, cos_theta_o, h);
+        let mut u: f64 = rng.gen();
+        let mut p = 0;
+        while p < P_MAX && u >= lobe_pdf[p] {
+            u -= lobe_pdf[p];
+            p += 1;
+        }
+
+        let (sin_op, cos_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+        let u_theta = rng.gen::<f64>().max(1e-5);
+        let cos_theta = 1. + self.v[p] * (u_theta + (1. - u_theta) * (-2. / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1. - cos_theta * cos_theta);
+        let cos_phi = (2. * PI * rng.gen::<f64>()).cos();
+        let sin_theta_i = -cos_theta * sin_op + sin_theta * cos_phi * cos_op;
+        let cos_theta_i = safe_sqrt(1. - sin_theta_i * sin_theta_i);
+
+        let dphi = if p < P_MAX {
+            let (gamma_t, _) = self.refracted(sin_theta_o, cos_theta_o, h);
+            phi(p, safe_asin(h), gamma_t) + sample_trimmed_logistic(rng.gen(), self.s)
+        } else {
+            2. * PI * rng.gen::<f64>()
+        };
+        let phi_i = phi_o + dphi;
+        Vector3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        )
+    }
+
+    // Local frame at the hit: along the fiber, and towards the viewer
+    // perpendicular to it. Also the offset h in [-1, 1] across the fiber.
+    fn frame(&self, ray: &Ray, hit_record: &HitRecord) -> ([Vector3<f64>; 3], f64) {
+        let x = if hit_record.tangent.norm_squared() > 0. {
+            hit_record.tangent.normalize()
+        } else {
+            orthonormal_basis(&hit_record.normal).0
+        };
+        let wo = -ray.direction.normalize();
+        let z = wo - x * wo.dot(&x);
+        let z = if z.norm_squared() > 1e-12 {
+            z.normalize()
+        } else {
+            (hit_record.normal - x * hit_record.normal.dot(&x)).normalize()
+        };
+        let y = z.cross(&x);
+        ([x, y, z], -1. + 2. * hit_record.v)
+    }
+}
+
+fn to_local(frame: &[Vector3<f64>; 3], w: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(w.dot(&frame[0]), w.dot(&frame[1]), w.dot(&frame[2]))
+}
+
+impl Material for Hair {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut RenderRng,
+    ) -> Option<(Ray, Color)> {
+        let (frame, h) = self.frame(ray_in, hit_record);
+        let wo = to_local(&frame, &-ray_in.direction.normalize());
+        let wi = self.sample(&wo, h, rng);
+        let pdf = self.pdf(&wo, &wi, h);
+        if pdf <= 0. || !pdf.is_finite() {
+            return None;
+        }
+        let direction = frame[0] * wi.x + frame[1] * wi.y + frame[2] * wi.z;
+        let scattered = Ray::new(hit_record.p, direction, ray_in.time);
+        Some((scattered, self.f(&wo, &wi, h) / pdf))
+    }
+
+    fn eval(&self, ray: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Color {
+        let (frame, h) = self.frame(ray, hit_record);
+        let wo = to_local(&frame, &-ray.direction.normalize());
+        let wi = to_local(&frame, &direction.normalize());
+        self.f(&wo, &wi, h)
+    }
+
+    // what gets through the fiber once across its diameter
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        self.sigma_a.map(|sigma_a| (-2. * sigma_a).exp())
+    }
+}
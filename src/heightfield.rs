@@ -0,0 +1,279 @@
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::noise::Perlin;
+use crate::ray::Ray;
+use crate::utils::*;
+
+// Terrain from a grid of nx * nz heights, spread over `size.x` by `size.z`
+// starting at `origin`, with heights in [0, 1] scaled to `size.y`.
+// Each cell is split into two triangles, which are only built while a ray
+// walks over the cell, so large grids stay one f32 per sample.
+pub struct Heightfield {
+    nx: usize,
+    nz: usize,
+    // row-major, x fastest
+    heights: Vec<f32>,
+    origin: Vector3<f64>,
+    size: Vector3<f64>,
+    min_height: f64,
+    max_height: f64,
+    material: Rc<dyn Material>,
+}
+
+impl Heightfield {
+    // Panics unless there are nx * nz >= 4 heights.
+    pub fn new(
+        nx: usize,
+        nz: usize,
+        heights: Vec<f32>,
+        origin: Vector3<f64>,
+        size: Vector3<f64>,
+        material: Rc<dyn Material>,
+    ) -> Self {
+        assert!(nx >= 2 && nz >= 2, "heightfield needs at least 2x2 samples");
+        assert_eq!(heights.len(), nx * nz, "height count mismatch");
+        let min = heights.iter().cloned().fold(f32::INFINITY, f32::min) as f64;
+        let max = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max) as f64;
+        Heightfield {
+            nx,
+            nz,
+            heights,
+            origin,
+            size,
+            min_height: min * size.y,
+            max_height: max * size.y,
+            material,
+        }
+    }
+
+    // Heights from a binary (P5) or ASCII (P2) PGM grayscale image of at
+    // least 2x2 pixels, white being the highest.
+    pub fn from_pgm(
+        bytes: &[u8],
+        origin: Vector3<f64>,
+        size: Vector3<f64>,
+        material: Rc<dyn Material>,
+    ) -> Result<Self, String> {
+        let (nx, nz, heights) = parse_pgm(bytes)?;
+        if nx < 2 || nz < 2 {
+            return Err(format!(
+                "heightfield needs at least 2x2 samples, the image is {}x{}",
+                nx, nz
+            ));
+        }
+        Ok(Heightfield::new(nx, nz, heights, origin, size, material))
+    }
+
+    // Hills from `octaves` of Perlin noise, `features` hills across.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_noise(
+        nx: usize,
+        nz: usize,
+        features: f64,
+        octaves: u32,
+        origin: Vector3<f64>,
+        size: Vector3<f64>,
+        material: Rc<dyn Material>,
+        rng: &mut RenderRng,
+    ) -> Self {
+        let perlin = Perlin::new(rng);
+        let mut heights = Vec::with_capacity(nx * nz);
+        for z in 0..nz {
+            for x in 0..nx {
+                let p = Vector3::new(
+                    x as f64 / nx as f64 * features,
+                    0.5,
+                    z as f64 / nz as f64 * features,
+                );
+                heights.push(clamp(0.5 + 0.5 * perlin.fbm(&p, octaves), 0., 1.) as f32);
+            }
+        }
+        Heightfield::new(nx, nz, heights, origin, size, material)
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.size.x / (self.nx - 1) as f64,
+            self.size.z / (self.nz - 1) as f64,
+        )
+    }
+
+    fn height(&self, x: usize, z: usize) -> f64 {
+        self.heights[z * self.nx + x] as f64 * self.size.y
+    }
+
+    // Local position of grid vertex (x, z)
+    fn vertex(&self, x: usize, z: usize) -> Vector3<f64> {
+        let (dx, dz) = self.cell_size();
+        Vector3::new(x as f64 * dx, self.height(x, z), z as f64 * dz)
+    }
+
+    // Smooth vertex normal from central differences of the neighbors
+    fn vertex_normal(&self, x: usize, z: usize) -> Vector3<f64> {
+        let (dx, dz) = self.cell_size();
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.nx - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.nz - 1));
+        let slope_x = (self.height(x1, z) - self.height(x0, z)) / ((x1 - x0) as f64 * dx);
+        let slope_z = (self.height(x, z1) - self.height(x, z0)) / ((z1 - z0) as f64 * dz);
+        Vector3::new(-slope_x, 1., -slope_z).normalize()
+    }
+
+    // Intersects the two triangles of cell (x, z), given in local coordinates.
+    fn hit_cell(
+        &self,
+        x: usize,
+        z: usize,
+        origin: &Vector3<f64>,
+        direction: &Vector3<f64>,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, Vector3<f64>)> {
+        let corners = [(x, z), (x + 1, z), (x + 1, z + 1), (x, z + 1)];
+        let mut closest: Option<(f64, Vector3<f64>)> = None;
+        for [a, b, c] in [[0, 3, 1], [2, 1, 3]] {
+            let (va, vb, vc) = (corners[a], corners[b], corners[c]);
+            let v0 = self.vertex(va.0, va.1);
+            let v1 = self.vertex(vb.0, vb.1);
+            let v2 = self.vertex(vc.0, vc.1);
+            if let Some((t, b1, b2)) = intersect_triangle(origin, direction, &v0, &v1, &v2) {
+                let t_far = closest.map_or(t_max, |(t, _)| t);
+                if t < t_min || t > t_far {
+                    continue;
+                }
+                let normal = (1. - b1 - b2) * self.vertex_normal(va.0, va.1)
+                    + b1 * self.vertex_normal(vb.0, vb.1)
+                    + b2 * self.vertex_normal(vc.0, vc.1);
+                closest = Some((t, normal.normalize()));
+            }
+        }
+        closest
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.bounding_box()?.interval(ray, t_min, t_max)?;
+        let origin = ray.origin - self.origin;
+        let d = ray.direction;
+        let (dx, dz) = self.cell_size();
+        let (last_x, last_z) = (self.nx as i64 - 2, self.nz as i64 - 2);
+
+        // 2D DDA over the cells the ray passes above, starting where it enters the box
+        // See Amanatides and Woo, "A Fast Voxel Traversal Algorithm for Ray Tracing"
+        let start = origin + t_enter * d;
+        let mut cell_x = ((start.x / dx).floor() as i64).clamp(0, last_x);
+        let mut cell_z = ((start.z / dz).floor() as i64).clamp(0, last_z);
+        let step_x: i64 = if d.x >= 0. { 1 } else { -1 };
+        let step_z: i64 = if d.z >= 0. { 1 } else { -1 };
+        let boundary = |cell: i64, step: i64, size: f64| (cell + step.max(0)) as f64 * size;
+        let mut t_next_x = if d.x != 0. {
+            (boundary(cell_x, step_x, dx) - origin.x) / d.x
+        } else {
+            f64::INFINITY
+        };
+        let mut t_next_z = if d.z != 0. {
+            (boundary(cell_z, step_z, dz) - origin.z) / d.z
+        } else {
+            f64::INFINITY
+        };
+        let t_delta_x = if d.x != 0. {
+            dx / d.x.abs()
+        } else {
+            f64::INFINITY
+        };
+        let t_delta_z = if d.z != 0. {
+            dz / d.z.abs()
+        } else {
+            f64::INFINITY
+        };
+
+        let mut t = t_enter;
+        while t <= t_exit {
+            let t_leave = t_next_x.min(t_next_z).min(t_exit);
+            // skip the cell if the ray stays above or below all four corners meanwhile
+            let (x, z) = (cell_x as usize, cell_z as usize);
+            let (y0, y1) = (origin.y + t * d.y, origin.y + t_leave * d.y);
+            let corner_heights = [
+                self.height(x, z),
+                self.height(x + 1, z),
+                self.height(x, z + 1),
+                self.height(x + 1, z + 1),
+            ];
+            let cell_min = corner_heights.iter().cloned().fold(f64::INFINITY, f64::min);
+            let cell_max = corner_heights
+                .iter()
+                .cloned()
+                .fold(f64::NEG_INFINITY, f64::max);
+            if y0.min(y1) <= cell_max && y0.max(y1) >= cell_min {
+                if let Some((t_hit, normal)) = self.hit_cell(x, z, &origin, &d, t_min, t_max) {
+                    let p = origin + t_hit * d;
+                    return Some(HitRecord::new(
+                        ray,
+                        t_hit,
+                        &normal,
+                        (p.x / self.size.x, p.z / self.size.z),
+                        Rc::clone(&self.material),
+                    ));
+                }
+            }
+
+            if t_next_x < t_next_z {
+                cell_x += step_x;
+                t = t_next_x;
+                t_next_x += t_delta_x;
+            } else {
+                cell_z += step_z;
+                t = t_next_z;
+                t_next_z += t_delta_z;
+            }
+            if cell_x < 0 || cell_x > last_x || cell_z < 0 || cell_z > last_z {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(
+            Aabb::new(
+                self.origin + Vector3::new(0., self.min_height, 0.),
+                self.origin + Vector3::new(self.size.x, self.max_height, self.size.z),
+            )
+            .padded(0.0001),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::Color;
+
+    fn from_pgm(bytes: &[u8]) -> Result<Heightfield, String> {
+        Heightfield::from_pgm(
+            bytes,
+            Vector3::zeros(),
+            Vector3::repeat(1.),
+            Rc::new(Lambertian::new(Color::repeat(0.5))),
+        )
+    }
+
+    #[test]
+    fn pgm_smaller_than_2x2() {
+        assert!(from_pgm(b"P2 1 1 255 7").is_err());
+        assert!(from_pgm(b"P2 1 3 255 7 8 9").is_err());
+        let heightfield = from_pgm(b"P2 2 2 255 0 255 51 102").unwrap();
+        assert_eq!((heightfield.nx, heightfield.nz), (2, 2));
+        assert_eq!(heightfield.max_height, 1.);
+    }
+}
@@ -1,25 +1,63 @@
+use std::f64::consts::PI;
 use std::rc::Rc;
 
-use js_sys::Math::sqrt;
 use nalgebra::Vector3;
 
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::utils::RenderRng;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Vector3<f64>,
-    t: f64,
+    pub t: f64,
     pub normal: Vector3<f64>,
+    // surface coordinates of the hit point, both in [0, 1]
+    pub u: f64,
+    pub v: f64,
     // front_face := ray dot normal < 0.
     // i.e. true  => ray hits front of surface
     //      false => ray hits front of surface
     pub front_face: bool,
+    // direction of increasing u along the surface, for anisotropic materials
+    // like hair; zero where the shape doesn't define one
+    pub tangent: Vector3<f64>,
+    // index of the hit object in the world's top-level list
+    pub object_id: usize,
 
     pub material: Rc<dyn Material>,
 }
 
 impl HitRecord {
-    fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vector3<f64>) {
+    pub fn new(
+        ray: &Ray,
+        t: f64,
+        outward_normal: &Vector3<f64>,
+        (u, v): (f64, f64),
+        material: Rc<dyn Material>,
+    ) -> Self {
+        let mut hit_record = HitRecord {
+            p: ray.at(t),
+            t,
+            normal: Default::default(),
+            u,
+            v,
+            front_face: Default::default(),
+            tangent: Vector3::zeros(),
+            object_id: 0,
+            material,
+        };
+        hit_record.set_face_normal(ray, outward_normal);
+        hit_record
+    }
+
+    pub fn with_tangent(mut self, tangent: Vector3<f64>) -> Self {
+        self.tangent = tangent;
+        self
+    }
+
+    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vector3<f64>) {
         self.front_face = ray.direction.dot(outward_normal) < 0.;
         self.normal = if self.front_face {
             *outward_normal
@@ -27,10 +65,147 @@ impl HitRecord {
             -*outward_normal
         };
     }
+
+    // Normal pointing out of the surface, whichever side the ray came from.
+    pub fn outward_normal(&self) -> Vector3<f64> {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+}
+
+// Stretch of a ray inside a solid, between the hit entering it and the hit leaving it.
+#[derive(Clone)]
+pub struct Span {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord>;
+
+    // Adds every material of the object to `materials`, always in the same
+    // order, which numbers them for the material id buffer.
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>);
+
+    // None for unbounded objects such as infinite planes.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // Fraction of light passing along the ray between t_min and t_max, used for
+    // shadow rays. Hard surfaces block it completely; media override this.
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        if self.hit(ray, t_min, t_max, rng).is_some() {
+            0.
+        } else {
+            1.
+        }
+    }
+
+    // Every span along the whole ray (t from -INFINITY to INFINITY) that is inside
+    // the object, in order. Used by CSG. The default walks all the surface hits and
+    // pairs entering with leaving ones, so it only makes sense for closed objects.
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        // guards against surfaces that keep reporting hits at the same t
+        const MAX_HITS: usize = 64;
+        let mut spans = Vec::new();
+        let mut enter: Option<HitRecord> = None;
+        let mut t = f64::NEG_INFINITY;
+        for _ in 0..MAX_HITS {
+            let hit_record = match self.hit(ray, t, f64::INFINITY, rng) {
+                Some(hit_record) => hit_record,
+                None => break,
+            };
+            t = hit_record.t + 0.0001;
+            if hit_record.front_face {
+                if enter.is_none() {
+                    enter = Some(hit_record);
+                }
+            } else if let Some(enter) = enter.take() {
+                spans.push(Span {
+                    enter,
+                    exit: hit_record,
+                });
+            }
+        }
+        spans
+    }
+}
+
+impl<T> Hittable for &T
+where
+    T: Hittable + ?Sized,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        (**self).hit(ray, t_min, t_max, rng)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        (**self).materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        (**self).transmittance(ray, t_min, t_max, rng)
+    }
+
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        (**self).spans(ray, rng)
+    }
+}
+
+impl<T> Hittable for Box<T>
+where
+    T: Hittable + ?Sized,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        (**self).hit(ray, t_min, t_max, rng)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        (**self).materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        (**self).transmittance(ray, t_min, t_max, rng)
+    }
+
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        (**self).spans(ray, rng)
+    }
+}
+
+impl<T> Hittable for Rc<T>
+where
+    T: Hittable + ?Sized,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        (**self).hit(ray, t_min, t_max, rng)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        (**self).materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        (**self).transmittance(ray, t_min, t_max, rng)
+    }
+
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        (**self).spans(ray, rng)
+    }
 }
 
 pub struct HittableList<T>
@@ -40,6 +215,15 @@ where
     objects: Vec<T>,
 }
 
+impl<T> Default for HittableList<T>
+where
+    T: Hittable,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> HittableList<T>
 where
     T: Hittable,
@@ -53,24 +237,55 @@ where
     pub fn add(&mut self, object: T) {
         self.objects.push(object);
     }
+}
 
-    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl<T> Hittable for HittableList<T>
+where
+    T: Hittable,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
         let mut hit_anything = None;
         let mut closest_so_far = t_max;
 
-        for object in &self.objects {
+        for (object_id, object) in self.objects.iter().enumerate() {
             // get a hit_record of the closest object by passing
             // closest_so_far as t_max
-            match object.hit(ray, t_min, closest_so_far) {
-                Some(hit_record) => {
-                    closest_so_far = hit_record.t;
-                    hit_anything = Some(hit_record);
-                }
-                None => (),
+            if let Some(hit_record) = object.hit(ray, t_min, closest_so_far, rng) {
+                closest_so_far = hit_record.t;
+                hit_anything = Some(HitRecord {
+                    object_id,
+                    ..hit_record
+                });
             }
         }
         hit_anything
     }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        for object in &self.objects {
+            object.materials(materials);
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.objects.iter();
+        let mut output_box = objects.next()?.bounding_box()?;
+        for object in objects {
+            output_box = output_box.surrounding(&object.bounding_box()?);
+        }
+        Some(output_box)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        let mut transmittance = 1.;
+        for object in &self.objects {
+            transmittance *= object.transmittance(ray, t_min, t_max, rng);
+            if transmittance == 0. {
+                break;
+            }
+        }
+        transmittance
+    }
 }
 
 pub struct Sphere {
@@ -80,7 +295,7 @@ pub struct Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
         let oc = ray.origin - self.center;
         let a = ray.direction.dot(&ray.direction);
         let half_b = oc.dot(&ray.direction);
@@ -91,23 +306,87 @@ impl Hittable for Sphere {
         if discriminant < 0. {
             return None;
         }
-        let sqrtd = sqrt(discriminant);
+        let sqrtd = discriminant.sqrt();
 
         // Find the nearest root that lies in the acceptable range.
-        let root = (-half_b - sqrtd) / a;
+        // The far root is hit by rays starting inside the sphere.
+        let mut root = (-half_b - sqrtd) / a;
         if root < t_min || root > t_max {
-            return None;
+            root = (-half_b + sqrtd) / a;
+            if root < t_min || root > t_max {
+                return None;
+            }
         }
 
-        let mut hit_record = HitRecord {
-            p: ray.at(root),
-            t: root,
-            normal: Default::default(),
-            front_face: Default::default(),
-            material: Rc::clone(&self.material),
-        };
         let outward_normal = (ray.at(root) - self.center) / self.radius;
-        hit_record.set_face_normal(ray, &outward_normal);
-        Some(hit_record)
+        Some(HitRecord::new(
+            ray,
+            root,
+            &outward_normal,
+            sphere_uv(&outward_normal),
+            Rc::clone(&self.material),
+        ))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        let r = Vector3::new(r, r, r);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+}
+
+// Sphere moving linearly from center0 at time0 to center1 at time1
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#motionblur
+pub struct MovingSphere {
+    pub center0: Vector3<f64>,
+    pub center1: Vector3<f64>,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub material: Rc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f64) -> Vector3<f64> {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+        self.center0
+            + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        Sphere {
+            center: self.center(ray.time),
+            radius: self.radius,
+            material: Rc::clone(&self.material),
+        }
+        .hit(ray, t_min, t_max, rng)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        let r = Vector3::new(r, r, r);
+        let box0 = Aabb::new(self.center0 - r, self.center0 + r);
+        let box1 = Aabb::new(self.center1 - r, self.center1 + r);
+        Some(box0.surrounding(&box1))
+    }
+}
+
+// (u, v) of a point p on the unit sphere centered at the origin.
+// u: angle around the Y axis from X=-1, v: angle from Y=-1 to Y=+1.
+pub fn sphere_uv(p: &Vector3<f64>) -> (f64, f64) {
+    let theta = (-p.y).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2. * PI), theta / PI)
 }
@@ -0,0 +1,331 @@
+use std::rc::Rc;
+
+use nalgebra::{Matrix4, Point3, Unit, UnitQuaternion, Vector3};
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable, Span};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::*;
+
+// Affine transform together with its inverse.
+// Transforms compose like matrices: `a.then(&b)` applies `a` first, then `b`.
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    // Panics if `matrix` is singular (e.g. scaled by zero).
+    pub fn from_matrix(matrix: Matrix4<f64>) -> Self {
+        Transform {
+            matrix,
+            inverse: matrix
+                .try_inverse()
+                .expect("transform matrix must be invertible"),
+        }
+    }
+
+    pub fn translation(offset: Vector3<f64>) -> Self {
+        Transform {
+            matrix: Matrix4::new_translation(&offset),
+            inverse: Matrix4::new_translation(&-offset),
+        }
+    }
+
+    // Rotation by `angle` degrees around `axis` (right-handed).
+    pub fn rotation(axis: Vector3<f64>, angle: f64) -> Self {
+        let axisangle = Unit::new_normalize(axis).into_inner() * deg_to_rad(angle);
+        Transform {
+            matrix: Matrix4::new_rotation(axisangle),
+            inverse: Matrix4::new_rotation(-axisangle),
+        }
+    }
+
+    pub fn from_quaternion(rotation: &UnitQuaternion<f64>) -> Self {
+        Transform {
+            matrix: rotation.to_homogeneous(),
+            inverse: rotation.inverse().to_homogeneous(),
+        }
+    }
+
+    // Non-uniform scaling; every component must be non-zero.
+    pub fn scaling(scale: Vector3<f64>) -> Self {
+        Transform {
+            matrix: Matrix4::new_nonuniform_scaling(&scale),
+            inverse: Matrix4::new_nonuniform_scaling(&scale.map(|s| 1. / s)),
+        }
+    }
+
+    pub fn then(&self, next: &Transform) -> Self {
+        Transform {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix4<f64> {
+        &self.matrix
+    }
+
+    pub fn point(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        self.matrix.transform_point(&Point3::from(*p)).coords
+    }
+
+    pub fn vector(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        self.matrix.transform_vector(v)
+    }
+
+    // Normals transform by the inverse transpose to stay perpendicular to the
+    // surface under non-uniform scaling.
+    pub fn normal(&self, n: &Vector3<f64>) -> Vector3<f64> {
+        (self.inverse.fixed_slice::<3, 3>(0, 0).transpose() * n).normalize()
+    }
+
+    pub fn inverse_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse
+                .transform_point(&Point3::from(ray.origin))
+                .coords,
+            self.inverse.transform_vector(&ray.direction),
+            ray.time,
+        )
+    }
+
+    // Box containing all eight transformed corners of `aabb`.
+    pub fn bounding_box(&self, aabb: &Aabb) -> Aabb {
+        let mut min = Vector3::repeat(f64::INFINITY);
+        let mut max = Vector3::repeat(f64::NEG_INFINITY);
+        for i in 0..8 {
+            let corner = Vector3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            let p = self.point(&corner);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        Aabb::new(min, max)
+    }
+}
+
+// Scale, then rotation, then translation. Unlike a matrix this can be
+// interpolated without shearing the object halfway.
+#[derive(Debug, Clone, Copy)]
+pub struct Pose {
+    pub translation: Vector3<f64>,
+    pub rotation: UnitQuaternion<f64>,
+    pub scale: Vector3<f64>,
+}
+
+impl Pose {
+    pub fn new(
+        translation: Vector3<f64>,
+        rotation: UnitQuaternion<f64>,
+        scale: Vector3<f64>,
+    ) -> Self {
+        Pose {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn identity() -> Self {
+        Pose::new(
+            Vector3::zeros(),
+            UnitQuaternion::identity(),
+            Vector3::new(1., 1., 1.),
+        )
+    }
+
+    // Pose with a rotation by `angle` degrees around `axis`.
+    pub fn rotated(mut self, axis: Vector3<f64>, angle: f64) -> Self {
+        self.rotation =
+            UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), deg_to_rad(angle))
+                * self.rotation;
+        self
+    }
+
+    // Linear interpolation of translation and scale, spherical of rotation.
+    // Rotations take the shortest way, so keep the angle between two poses below 180 degrees.
+    pub fn interpolate(&self, other: &Pose, t: f64) -> Pose {
+        Pose {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::scaling(self.scale)
+            .then(&Transform::from_quaternion(&self.rotation))
+            .then(&Transform::translation(self.translation))
+    }
+}
+
+// Places a (possibly shared) hittable in the world with an affine transform.
+// Wrap the object in `Rc` to instance it many times without copying it:
+//
+//     let mesh = Rc::new(mesh);
+//     world.add(Instance::new(Rc::clone(&mesh), Transform::translation(offset)));
+pub struct Instance<T>
+where
+    T: Hittable,
+{
+    object: T,
+    transform: Transform,
+}
+
+impl<T> Instance<T>
+where
+    T: Hittable,
+{
+    pub fn new(object: T, transform: Transform) -> Self {
+        Instance { object, transform }
+    }
+
+    // Moves a hit found with the object-space ray back into the world.
+    fn to_world(&self, ray: &Ray, mut hit_record: HitRecord) -> HitRecord {
+        hit_record.p = self.transform.point(&hit_record.p);
+        hit_record.tangent = self.transform.vector(&hit_record.tangent);
+        let outward_normal = self.transform.normal(&hit_record.outward_normal());
+        hit_record.set_face_normal(ray, &outward_normal);
+        hit_record
+    }
+}
+
+impl<T> Hittable for Instance<T>
+where
+    T: Hittable,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        // Intersect in object space. The direction isn't normalized, so t is
+        // the same in both spaces.
+        let local_ray = self.transform.inverse_ray(ray);
+        let hit_record = self.object.hit(&local_ray, t_min, t_max, rng)?;
+        Some(self.to_world(ray, hit_record))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        self.object.materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let aabb = self.object.bounding_box()?;
+        Some(self.transform.bounding_box(&aabb))
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        let local_ray = self.transform.inverse_ray(ray);
+        self.object.transmittance(&local_ray, t_min, t_max, rng)
+    }
+
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        let local_ray = self.transform.inverse_ray(ray);
+        self.object
+            .spans(&local_ray, rng)
+            .into_iter()
+            .map(|span| Span {
+                enter: self.to_world(ray, span.enter),
+                exit: self.to_world(ray, span.exit),
+            })
+            .collect()
+    }
+}
+
+// Instance moving from pose0 at time0 to pose1 at time1
+pub struct MovingInstance<T>
+where
+    T: Hittable,
+{
+    object: T,
+    pose0: Pose,
+    pose1: Pose,
+    time0: f64,
+    time1: f64,
+}
+
+impl<T> MovingInstance<T>
+where
+    T: Hittable,
+{
+    pub fn new(object: T, pose0: Pose, pose1: Pose, time0: f64, time1: f64) -> Self {
+        MovingInstance {
+            object,
+            pose0,
+            pose1,
+            time0,
+            time1,
+        }
+    }
+
+    pub fn transform(&self, time: f64) -> Transform {
+        let t = if self.time1 == self.time0 {
+            0.
+        } else {
+            ((time - self.time0) / (self.time1 - self.time0)).clamp(0., 1.)
+        };
+        self.pose0.interpolate(&self.pose1, t).transform()
+    }
+}
+
+impl<T> Hittable for MovingInstance<T>
+where
+    T: Hittable,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        Instance::new(&self.object, self.transform(ray.time)).hit(ray, t_min, t_max, rng)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        Instance::new(&self.object, self.transform(ray.time)).transmittance(ray, t_min, t_max, rng)
+    }
+
+    fn spans(&self, ray: &Ray, rng: &mut RenderRng) -> Vec<Span> {
+        Instance::new(&self.object, self.transform(ray.time)).spans(ray, rng)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        self.object.materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let aabb = self.object.bounding_box()?;
+        // Translation and scale move every corner along a straight line, so
+        // without rotation the boxes at both ends hold the whole motion
+        if self.pose0.rotation == self.pose1.rotation {
+            let start = self.transform(self.time0).bounding_box(&aabb);
+            return Some(start.surrounding(&self.transform(self.time1).bounding_box(&aabb)));
+        }
+        // Rotating, the object stays within the distance of its farthest
+        // corner from the translation, which moves along a straight line
+        let mut radius: f64 = 0.;
+        for i in 0..8 {
+            let corner = Vector3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            for scale in [self.pose0.scale, self.pose1.scale] {
+                radius = radius.max(scale.component_mul(&corner).norm());
+            }
+        }
+        let sphere = |center: Vector3<f64>| {
+            Aabb::new(
+                center - Vector3::repeat(radius),
+                center + Vector3::repeat(radius),
+            )
+        };
+        Some(sphere(self.pose0.translation).surrounding(&sphere(self.pose1.translation)))
+    }
+}
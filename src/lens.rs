@@ -0,0 +1,486 @@
+// Camera tracing rays through a real lens system, element by element, which
+// brings the distortion, vignetting and focus breathing a thin lens can't.
+// Ported from pbrt-v3's RealisticCamera.
+// See https://pbr-book.org/3ed-2018/Camera_Models/Realistic_Cameras
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::camera::{Camera, View};
+use crate::poly::solve_quadratic;
+use crate::ray::Ray;
+use crate::utils::*;
+use crate::Color;
+
+// Prescriptions are written in millimeters, while the scene is in meters.
+const MM: f64 = 0.001;
+
+// Film radii at which the exit pupil is bounded
+const PUPIL_BOUNDS: usize = 64;
+
+// Rays traced per film radius to bound the exit pupil
+const PUPIL_SAMPLES: usize = 16384;
+
+// The double Gauss 50mm f/2 from pbrt's scenes, after US patent 2,673,491.
+pub const DOUBLE_GAUSS_50MM: &str = "
+# radius  thickness  ior    aperture
+29.475    3.76       1.67   25.2
+84.83     0.12       1      25.2
+19.275    4.025      1.67   23
+40.77     3.275      1.699  23
+12.75     5.705      1      18
+0         4.5        0      17.1
+-14.495   1.18       1.603  17
+40.77     6.065      1.658  20
+-20.385   0.19       1      20
+437.065   3.22       1.717  20
+-39.73    0          1      20
+";
+
+// One surface of the lens, as a row of a patent's prescription table.
+#[derive(Clone, Copy)]
+pub struct LensElement {
+    // radius of curvature, positive when the center lies towards the film;
+    // 0 for the aperture stop
+    pub radius: f64,
+    // distance along the axis to the next surface, or to the film for the
+    // last one
+    pub thickness: f64,
+    // index of refraction behind the surface, 0 or 1 for air
+    pub eta: f64,
+    pub aperture_diameter: f64,
+}
+
+// Reads a prescription table: one surface per line from the front of the
+// lens to the back, as radius, thickness, index of refraction and aperture
+// diameter in millimeters. Lines starting with # are comments.
+pub fn parse_prescription(text: &str) -> Result<Vec<LensElement>, String> {
+    let mut elements = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(|value| value.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if values.len() != 4 {
+            return Err(format!(
+                "line {}: expected 4 values, found {}",
+                i + 1,
+                values.len()
+            ));
+        }
+        elements.push(LensElement {
+            radius: values[0],
+            thickness: values[1],
+            eta: values[2],
+            aperture_diameter: values[3],
+        });
+    }
+    if elements.is_empty() {
+        return Err("empty prescription".to_string());
+    }
+    Ok(elements)
+}
+
+// Pupil bounds on the plane of the rear element, as (min, max)
+type Bounds = (Vector2<f64>, Vector2<f64>);
+
+fn area(bounds: &Bounds) -> f64 {
+    let size = bounds.1 - bounds.0;
+    size.x * size.y
+}
+
+// Van der Corput sequence in `base`, to spread the pupil samples evenly
+fn radical_inverse(base: usize, mut i: usize) -> f64 {
+    let (mut result, mut scale) = (0., 1. / base as f64);
+    while i > 0 {
+        result += (i % base) as f64 * scale;
+        i /= base;
+        scale /= base as f64;
+    }
+    result
+}
+
+// Hit of the ray with a spherical surface centered on the axis at z_center,
+// with the normal facing back along the ray
+fn intersect_element(
+    radius: f64,
+    z_center: f64,
+    origin: &Vector3<f64>,
+    direction: &Vector3<f64>,
+) -> Option<(f64, Vector3<f64>)> {
+    let o = origin - Vector3::new(0., 0., z_center);
+    let roots = solve_quadratic([
+        direction.norm_squared(),
+        2. * direction.dot(&o),
+        o.norm_squared() - radius * radius,
+    ]);
+    let (t0, t1) = roots
+        .iter()
+        .fold((f64::INFINITY, -f64::INFINITY), |(lo, hi), &t| {
+            (lo.min(t), hi.max(t))
+        });
+    if roots.is_empty() {
+        return None;
+    }
+    // the surface is the half of the sphere facing the other elements
+    let t = if (direction.z > 0.) != (radius < 0.) {
+        t0
+    } else {
+        t1
+    };
+    if t < 0. {
+        return None;
+    }
+    let n = (o + t * direction).normalize();
+    Some((t, if n.dot(direction) > 0. { -n } else { n }))
+}
+
+// Refraction of the unit direction `wi` leaving the surface, None on total
+// internal reflection
+fn transmit(wi: &Vector3<f64>, n: &Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+    let cos_i = n.dot(wi);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i).max(0.);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(eta * -wi + (eta * cos_i - cos_t) * n)
+}
+
+// In camera space the film sits at z = 0 and the lens towards +z, with x
+// to the right and y up.
+pub struct RealisticCamera {
+    view: View,
+    // in meters, from the front; the last thickness is the film distance
+    elements: Vec<LensElement>,
+    film_width: f64,
+    film_height: f64,
+    exit_pupil_bounds: Vec<Bounds>,
+}
+
+impl RealisticCamera {
+    // `elements` come from a prescription table in millimeters, which also
+    // gives the aperture_diameter of the stop and the film_diagonal, 43.3 for
+    // full frame. The film moves to focus at focus_distance from it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        elements: &[LensElement],
+        aperture_diameter: f64,
+        focus_distance: f64,
+        film_diagonal: f64,
+        aspect_ratio: f64,
+    ) -> Result<Self, String> {
+        let elements = elements
+            .iter()
+            .map(|element| {
+                // the stop can't open wider than the lens was built for
+                let diameter = if element.radius == 0. {
+                    aperture_diameter.min(element.aperture_diameter)
+                } else {
+                    element.aperture_diameter
+                };
+                LensElement {
+                    radius: element.radius * MM,
+                    thickness: element.thickness * MM,
+                    eta: element.eta,
+                    aperture_diameter: diameter * MM,
+                }
+            })
+            .collect();
+        let film_diagonal = film_diagonal * MM;
+        let film_width = film_diagonal / (1. + 1. / (aspect_ratio * aspect_ratio)).sqrt();
+        let mut camera = RealisticCamera {
+            view: View::new(lookfrom, lookat, vup),
+            elements,
+            film_width,
+            film_height: film_width / aspect_ratio,
+            exit_pupil_bounds: Vec::new(),
+        };
+
+        let film_distance = camera.focus_thick_lens(focus_distance)?;
+        camera.elements.last_mut().unwrap().thickness = film_distance;
+
+        let film_radius = film_diagonal / 2.;
+        camera.exit_pupil_bounds = (0..PUPIL_BOUNDS)
+            .map(|i| {
+                camera.bound_exit_pupil(
+                    i as f64 / PUPIL_BOUNDS as f64 * film_radius,
+                    (i + 1) as f64 / PUPIL_BOUNDS as f64 * film_radius,
+                )
+            })
+            .collect();
+        Ok(camera)
+    }
+
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.view.time0 = time0;
+        self.view.time1 = time1;
+        self
+    }
+
+    fn front_z(&self) -> f64 {
+        self.elements.iter().map(|element| element.thickness).sum()
+    }
+
+    fn rear_z(&self) -> f64 {
+        self.elements.last().unwrap().thickness
+    }
+
+    fn rear_radius(&self) -> f64 {
+        self.elements.last().unwrap().aperture_diameter / 2.
+    }
+
+    // Index of refraction in front of surface i
+    fn eta_before(&self, i: usize) -> f64 {
+        match i.checked_sub(1).map(|i| self.elements[i].eta) {
+            Some(eta) if eta != 0. => eta,
+            _ => 1.,
+        }
+    }
+
+    // Index of refraction behind surface i
+    fn eta_after(&self, i: usize) -> f64 {
+        match self.elements[i].eta {
+            eta if eta != 0. => eta,
+            _ => 1.,
+        }
+    }
+
+    // Hit of the ray, given in lens space (camera space with z flipped),
+    // with surface i at element_z, and the direction it leaves with when
+    // going from eta_i to eta_t. None when it misses the element's opening.
+    fn trace_element(
+        &self,
+        i: usize,
+        element_z: f64,
+        origin: &Vector3<f64>,
+        direction: &Vector3<f64>,
+        eta_i: f64,
+        eta_t: f64,
+    ) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let element = &self.elements[i];
+        let is_stop = element.radius == 0.;
+        let (t, n) = if is_stop {
+            if direction.z == 0. {
+                return None;
+            }
+            ((element_z - origin.z) / direction.z, Vector3::zeros())
+        } else {
+            intersect_element(
+                element.radius,
+                element_z + element.radius,
+                origin,
+                direction,
+            )?
+        };
+        if t < 0. {
+            return None;
+        }
+        let hit = origin + t * direction;
+        let aperture_radius = element.aperture_diameter / 2.;
+        if hit.x * hit.x + hit.y * hit.y > aperture_radius * aperture_radius {
+            return None;
+        }
+        if is_stop {
+            return Some((hit, *direction));
+        }
+        let direction = transmit(&-direction.normalize(), &n, eta_i / eta_t)?;
+        Some((hit, direction))
+    }
+
+    // Ray from the film out through the front of the lens, in camera space
+    fn trace_from_film(
+        &self,
+        origin: &Vector3<f64>,
+        direction: &Vector3<f64>,
+    ) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let flip = Vector3::new(1., 1., -1.);
+        let (mut origin, mut direction) =
+            (origin.component_mul(&flip), direction.component_mul(&flip));
+        let mut element_z = 0.;
+        for i in (0..self.elements.len()).rev() {
+            element_z -= self.elements[i].thickness;
+            (origin, direction) = self.trace_element(
+                i,
+                element_z,
+                &origin,
+                &direction,
+                self.eta_after(i),
+                self.eta_before(i),
+            )?;
+        }
+        Some((origin.component_mul(&flip), direction.component_mul(&flip)))
+    }
+
+    // Ray from the scene in through the back of the lens, in camera space
+    fn trace_from_scene(
+        &self,
+        origin: &Vector3<f64>,
+        direction: &Vector3<f64>,
+    ) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let flip = Vector3::new(1., 1., -1.);
+        let (mut origin, mut direction) =
+            (origin.component_mul(&flip), direction.component_mul(&flip));
+        let mut element_z = -self.front_z();
+        for i in 0..self.elements.len() {
+            (origin, direction) = self.trace_element(
+                i,
+                element_z,
+                &origin,
+                &direction,
+                self.eta_before(i),
+                self.eta_after(i),
+            )?;
+            element_z += self.elements[i].thickness;
+        }
+        Some((origin.component_mul(&flip), direction.component_mul(&flip)))
+    }
+
+    // Principal plane and focal point along z from a ray parallel to the
+    // axis entering at x_in and the ray coming out of the lens
+    fn cardinal_points(x_in: f64, origin: &Vector3<f64>, direction: &Vector3<f64>) -> (f64, f64) {
+        let focal_t = -origin.x / direction.x;
+        let principal_t = (x_in - origin.x) / direction.x;
+        (
+            origin.z + principal_t * direction.z,
+            origin.z + focal_t * direction.z,
+        )
+    }
+
+    // Film distance behind the rear element that brings focus_distance
+    // into focus, from the thick lens approximation of the system.
+    // See https://pbr-book.org/3ed-2018/Camera_Models/Realistic_Cameras#FocusingThickLenses
+    fn focus_thick_lens(&self, focus_distance: f64) -> Result<f64, String> {
+        // close to the axis, where the approximation holds
+        let x = 0.001 * self.film_width;
+        let (front_z, rear_z) = (self.front_z(), self.rear_z());
+
+        let scene_origin = Vector3::new(x, 0., front_z + 1.);
+        let (origin, direction) = self
+            .trace_from_scene(&scene_origin, &Vector3::new(0., 0., -1.))
+            .ok_or("no ray gets through the lens from the scene")?;
+        let (pz0, fz0) = Self::cardinal_points(x, &origin, &direction);
+
+        let film_origin = Vector3::new(x, 0., rear_z - 1.);
+        let (origin, direction) = self
+            .trace_from_film(&film_origin, &Vector3::new(0., 0., 1.))
+            .ok_or("no ray gets through the lens from the film")?;
+        let (pz1, _) = Self::cardinal_points(x, &origin, &direction);
+
+        // distances towards the scene are negative from here on, as in pbrt
+        let (pz0, fz0, pz1) = (-pz0, -fz0, -pz1);
+        let f = fz0 - pz0;
+        let z = -focus_distance;
+        let c = (pz1 - z - pz0) * (pz1 - z - 4. * f - pz0);
+        if c <= 0. {
+            return Err(format!(
+                "focus distance {} is too close for this lens",
+                focus_distance
+            ));
+        }
+        Ok(rear_z + 0.5 * (pz1 - z + pz0 - c.sqrt()))
+    }
+
+    // Bounds of the points on the rear element that let light through to
+    // the film between radii x0 and x1 along the x axis.
+    // See https://pbr-book.org/3ed-2018/Camera_Models/Realistic_Cameras#GeneratingRays
+    fn bound_exit_pupil(&self, x0: f64, x1: f64) -> Bounds {
+        let rear_radius = 1.5 * self.rear_radius();
+        let rear_bounds = (
+            Vector2::new(-rear_radius, -rear_radius),
+            Vector2::new(rear_radius, rear_radius),
+        );
+        let rear_z = self.rear_z();
+        let mut pupil: Option<Bounds> = None;
+        for i in 0..PUPIL_SAMPLES {
+            let film = Vector3::new(
+                x0 + (i as f64 + 0.5) / PUPIL_SAMPLES as f64 * (x1 - x0),
+                0.,
+                0.,
+            );
+            let rear = Vector2::new(
+                -rear_radius + radical_inverse(2, i) * 2. * rear_radius,
+                -rear_radius + radical_inverse(3, i) * 2. * rear_radius,
+            );
+            let inside = pupil.is_some_and(|(min, max)| {
+                rear.x >= min.x && rear.y >= min.y && rear.x <= max.x && rear.y <= max.y
+            });
+            if inside
+                || self
+                    .trace_from_film(&film, &(Vector3::new(rear.x, rear.y, rear_z) - film))
+                    .is_some()
+            {
+                pupil = Some(match pupil {
+                    Some((min, max)) => (min.inf(&rear), max.sup(&rear)),
+                    None => (rear, rear),
+                });
+            }
+        }
+        match pupil {
+            // pad by about a sample spacing to make up for the missed edges
+            Some((min, max)) => {
+                let pad =
+                    2. * (rear_bounds.1 - rear_bounds.0).norm() / (PUPIL_SAMPLES as f64).sqrt();
+                (min.add_scalar(-pad), max.add_scalar(pad))
+            }
+            None => rear_bounds,
+        }
+    }
+
+    // Point on the rear element to aim at from the film point, and the
+    // area of the bounds it was picked from
+    fn sample_exit_pupil(&self, film: &Vector2<f64>, rng: &mut RenderRng) -> (Vector3<f64>, f64) {
+        let film_radius = film.norm();
+        let film_diagonal = self.film_width.hypot(self.film_height);
+        let index = (film_radius / (film_diagonal / 2.) * PUPIL_BOUNDS as f64) as usize;
+        let (min, max) = self.exit_pupil_bounds[index.min(PUPIL_BOUNDS - 1)];
+        let lens = Vector2::new(random_f64(rng, min.x, max.x), random_f64(rng, min.y, max.y));
+        // the bounds were computed along x, rotate them to the film point
+        let (sin, cos) = if film_radius != 0. {
+            (film.y / film_radius, film.x / film_radius)
+        } else {
+            (0., 1.)
+        };
+        (
+            Vector3::new(
+                cos * lens.x - sin * lens.y,
+                sin * lens.x + cos * lens.y,
+                self.rear_z(),
+            ),
+            area(&(min, max)),
+        )
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray> {
+        self.sample_ray(s, t, rng).map(|(ray, _)| ray)
+    }
+
+    // Rays blocked inside the lens give None. The weight falls off with
+    // cos^4 and with the size of the pupil, relative to the image center.
+    fn sample_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<(Ray, Color)> {
+        // the lens flips the image onto the film
+        let film = Vector2::new((0.5 - s) * self.film_width, (0.5 - t) * self.film_height);
+        let (rear, pupil_area) = self.sample_exit_pupil(&film, rng);
+        let film = Vector3::new(film.x, film.y, 0.);
+        let (origin, direction) = self.trace_from_film(&film, &(rear - film))?;
+
+        let cos_theta = (rear - film).normalize().z;
+        let weight = cos_theta.powi(4) * pupil_area / area(&self.exit_pupil_bounds[0]);
+        // camera space looks down +z while the view looks down -w
+        let flip = Vector3::new(1., 1., -1.);
+        let origin = self.view.point(&origin.component_mul(&flip));
+        let ray = self
+            .view
+            .ray(origin, &direction.component_mul(&flip).normalize(), rng);
+        Some((ray, Color::repeat(weight)))
+    }
+}
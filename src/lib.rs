@@ -1,113 +1,557 @@
-mod camera;
-mod hit;
-mod material;
-mod ray;
-mod utils;
+pub mod aabb;
+pub mod adaptive;
+pub mod animation;
+pub mod aov;
+pub mod bezier;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod csg;
+pub mod curve;
+pub mod denoise;
+pub mod description;
+pub mod distributed;
+pub mod framebuffer;
+pub mod gif;
+pub mod hair;
+pub mod heightfield;
+pub mod hit;
+pub mod instance;
+pub mod json;
+pub mod lens;
+pub mod light;
+pub mod material;
+pub mod medium;
+pub mod noise;
+pub mod plane;
+pub mod png;
+pub mod poly;
+pub mod quadric;
+pub mod ray;
+pub mod rect;
+pub mod sdf;
+pub mod stereo;
+pub mod utils;
+pub mod video;
+pub mod voxel;
 
-use camera::Camera;
+use aabb::Aabb;
+use animation::{CameraAnimation, Interpolation, Track};
+use bezier::BezierPatch;
+use bvh::BvhNode;
+use camera::{Aperture, Camera, EquirectangularCamera, PerspectiveCamera};
+use checkpoint::Checkpoint;
+use csg::Csg;
+use curve::{Curve, CurveBasis, CurveType};
+use denoise::Denoiser;
+use framebuffer::{rgb8, to_rgb8, Framebuffer};
+use gif::GifWriter;
+use hair::Hair;
+use heightfield::Heightfield;
 use hit::Hittable;
-use hit::{HittableList, Sphere};
-use js_sys::Math::{atan, sqrt};
-use nalgebra::Vector3;
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use hit::{HitRecord, HittableList, MovingSphere, Sphere};
+use instance::{Instance, MovingInstance, Pose, Transform};
+use lens::{parse_prescription, RealisticCamera, DOUBLE_GAUSS_50MM};
+use light::{direct_lighting, DirectionalLight, Light, PointLight, SpotLight};
+use nalgebra::{UnitQuaternion, Vector2, Vector3};
+use plane::{Disk, Plane};
+use quadric::{Cone, Cylinder, Quadric};
+use rand::{Rng, SeedableRng};
 use ray::Ray;
-use std::f64::INFINITY;
+use rect::{Cuboid, XYRect, YZRect};
+use sdf::{
+    Capsule, Mandelbulb, Repetition, RoundBox, SdfBox, SdfObject, SdfSphere, SmoothUnion,
+    Subtraction, Torus, Translate, Twist,
+};
 use std::rc::Rc;
+use stereo::{Convergence, StereoCamera, StereoLayout};
 use utils::*;
+use video::Y4mWriter;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::CanvasRenderingContext2d;
 
-use crate::material::{Dielectic, Lambertian, Metal};
+use crate::material::{Dielectic, Lambertian, Material, Metal};
+use crate::medium::{ConstantMedium, GridMedium};
+use crate::voxel::VoxelGrid;
 
 const ASPECT_RATIO: f64 = 3. / 2.;
 const WIDTH: u32 = 1200;
 const HEIGHT: u32 = (WIDTH as f64 / ASPECT_RATIO) as u32;
 const RESOLUTION: u32 = 1;
 const SAMPLES_PER_PIXEL: u32 = 8;
+// Above 0, SAMPLES_PER_PIXEL becomes an average: pixels stop once their error
+// on screen falls below this, and noisier ones get the samples they leave.
+const NOISE_THRESHOLD: f64 = 0.;
+// When the render stops, see Mode
+const MODE: Mode = Mode::Samples;
 const MAX_DEPTH: i32 = 10;
+const FRAMES: u32 = 0;
+// Filter the noise out of what is drawn, for quick previews at few samples
+const DENOISE: bool = false;
+
+// Samples every pixel gets before adaptive sampling picks the noisy ones
+const FIRST_PASS_SAMPLES: u32 = 4;
+// Most samples a pixel can get, as a multiple of SAMPLES_PER_PIXEL
+const MAX_SAMPLES_SCALE: u32 = 16;
 
 // (r, g, b) = (x, y, z)
-type Color = Vector3<f64>;
+pub type Color = Vector3<f64>;
+
+// What every *_scene() function returns
+pub type Scene<T> = (HittableList<T>, Vec<Box<dyn Light>>, Box<dyn Camera>);
 
 pub struct Info {
     progress: u32,
 }
 
+impl Default for Info {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Info {
     pub fn new() -> Self {
         Info { progress: 0 }
     }
 
-    pub fn update_progress(&mut self, x: u32, y: u32) {
-        self.progress = (((y * WIDTH + x) as f32 / (WIDTH * HEIGHT) as f32) * 100.) as u32;
+    // `done` is the finished part of the render, from 0 to 1
+    pub fn update_progress(&mut self, done: f64) {
+        self.progress = (done.clamp(0., 1.) * 100.) as u32;
+    }
+
+    // Percentage of the render completed
+    pub fn progress(&self) -> u32 {
+        self.progress
+    }
+}
+
+// When a render stops. Every pixel gets one sample before any limit
+// applies, so whenever it stops the image is complete.
+#[derive(Clone, Copy)]
+pub enum Mode {
+    // SAMPLES_PER_PIXEL samples per pixel, on average with adaptive sampling
+    Samples,
+    // after this many seconds of wall-clock time
+    Time(f64),
+    // once no pixel is noisier on screen than this, with adaptive sampling
+    // up to MAX_SAMPLES_SCALE times SAMPLES_PER_PIXEL samples per pixel
+    Noise(f64),
+    // after this many samples over the whole image
+    SampleBudget(u64),
+}
+
+// How much to sample, from the constants above unless the native CLI says
+// otherwise
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub samples_per_pixel: u32,
+    pub noise_threshold: f64,
+    pub mode: Mode,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            samples_per_pixel: SAMPLES_PER_PIXEL,
+            noise_threshold: NOISE_THRESHOLD,
+            mode: MODE,
+        }
     }
 }
 
 #[wasm_bindgen(start)]
 pub fn start() -> Result<(), JsValue> {
+    // animations are drawn frame by frame from index.js
+    if FRAMES == 0 {
+        let framebuffer = render_still(&Settings::default());
+        draw(&canvas_context()?, framebuffer.width, &image(&framebuffer));
+    }
+    Ok(())
+}
+
+// Number of frames of animation_scene() to render instead of the still, with
+// frame i at time i / FRAMES.
+#[wasm_bindgen]
+pub fn frame_count() -> u32 {
+    FRAMES
+}
+
+// Draws one frame and hands it back as RGB bytes for a VideoWriter.
+#[wasm_bindgen]
+pub fn draw_frame(frame: u32) -> Result<Vec<u8>, JsValue> {
+    let framebuffer = render_frame(frame, FRAMES, &Settings::default());
+    let colors = image(&framebuffer);
+    draw(&canvas_context()?, framebuffer.width, &colors);
+    Ok(rgb8(&colors))
+}
+
+enum VideoFormat {
+    Y4m(Y4mWriter<Vec<u8>>),
+    Gif(GifWriter<Vec<u8>>),
+}
+
+// Collects the frames from draw_frame() into a file for the page to download.
+#[wasm_bindgen]
+pub struct VideoWriter {
+    format: VideoFormat,
+}
+
+#[wasm_bindgen]
+impl VideoWriter {
+    pub fn y4m(fps: u32) -> Result<VideoWriter, JsValue> {
+        let (width, height) = framebuffer_size();
+        let writer = Y4mWriter::new(Vec::new(), width, height, fps).map_err(to_js_error)?;
+        Ok(VideoWriter {
+            format: VideoFormat::Y4m(writer),
+        })
+    }
+
+    pub fn gif(fps: u32) -> Result<VideoWriter, JsValue> {
+        let (width, height) = framebuffer_size();
+        let writer = GifWriter::new(Vec::new(), width, height, fps).map_err(to_js_error)?;
+        Ok(VideoWriter {
+            format: VideoFormat::Gif(writer),
+        })
+    }
+
+    pub fn add_frame(&mut self, rgb: &[u8]) -> Result<(), JsValue> {
+        match &mut self.format {
+            VideoFormat::Y4m(writer) => writer.write_frame(rgb),
+            VideoFormat::Gif(writer) => writer.write_frame(rgb),
+        }
+        .map_err(to_js_error)
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, JsValue> {
+        match self.format {
+            VideoFormat::Y4m(writer) => writer.finish(),
+            VideoFormat::Gif(writer) => writer.finish(),
+        }
+        .map_err(to_js_error)
+    }
+}
+
+fn to_js_error(error: std::io::Error) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
+
+fn canvas_context() -> Result<CanvasRenderingContext2d, JsValue> {
     let document = web_sys::window().unwrap().document().unwrap();
     let canvas = document.get_element_by_id("canvas").unwrap();
     let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
     canvas.set_height(HEIGHT);
     canvas.set_width(WIDTH);
 
-    let context = canvas
+    Ok(canvas
         .get_context("2d")?
         .unwrap()
-        .dyn_into::<CanvasRenderingContext2d>()?;
+        .dyn_into::<CanvasRenderingContext2d>()?)
+}
 
-    draw(&context);
+// The colors to draw, denoised if DENOISE is set
+fn image(framebuffer: &Framebuffer) -> Vec<Color> {
+    if DENOISE {
+        Denoiser::new().denoise(framebuffer)
+    } else {
+        framebuffer.colors()
+    }
+}
 
-    Ok(())
+// Paints every pixel, row by row, as a RESOLUTION-sized block.
+fn draw(context: &CanvasRenderingContext2d, width: u32, colors: &[Color]) {
+    context.save();
+    for (i, &color) in colors.iter().enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        write_color(context, x * RESOLUTION, y * RESOLUTION, to_rgb8(color));
+    }
+    log!("Done!");
 }
 
-fn draw(context: &CanvasRenderingContext2d) {
-    let mut info = Info::new();
-    let mut rng = rand::thread_rng();
+// Size of the rendered image: one pixel per RESOLUTION-sized block
+pub fn framebuffer_size() -> (u32, u32) {
+    (WIDTH.div_ceil(RESOLUTION), HEIGHT.div_ceil(RESOLUTION))
+}
+
+pub fn render_still(settings: &Settings) -> Framebuffer {
+    let (width, height) = framebuffer_size();
+    let mut checkpoint = Checkpoint::new(*settings, width, height);
+    resume_still(&mut checkpoint, &mut |_, _| ());
+    checkpoint.framebuffer
+}
+
+// Adds samples to the still in `checkpoint` until its settings say to stop,
+// handing it and the progress so far to `save` after every pass over the
+// image.
+pub fn resume_still(checkpoint: &mut Checkpoint, save: &mut dyn FnMut(&Checkpoint, &Info)) {
+    let mut rng = RenderRng::seed_from_u64(checkpoint.scene_seed);
 
     //
     // World
     //
-    // Replace this with image15_scene(), image20_scene(), or image21_scene(&mut rng).
+    // Replace this with image15_scene(), image20_scene(), image21_scene(&mut rng),
+    // lights_scene(&mut rng), stage_scene(), instances_scene(&mut rng),
+    // motion_blur_scene(&mut rng), smoke_scene(&mut rng), cloud_scene(&mut rng),
+    // csg_scene(), sdf_scene(), shapes_scene(), terrain_scene(&mut rng),
+    // bezier_scene(), fur_scene(&mut rng), panorama_scene(&mut rng),
+    // stereo_scene(&mut rng), bokeh_scene(&mut rng), or lens_scene(&mut rng).
     // this number of image corresponds to the book:
     // https://raytracing.github.io/books/RayTracingInOneWeekend.html
-    let (world, camera) = image21_scene(&mut rng);
+    let (world, lights, camera) = image21_scene(&mut rng);
+
+    render(&world, &lights, camera.as_ref(), checkpoint, save)
+}
+
+// resume_still() for a scene built elsewhere, like one described in JSON
+pub fn resume_with<T>(
+    (world, lights, camera): &Scene<T>,
+    checkpoint: &mut Checkpoint,
+    save: &mut dyn FnMut(&Checkpoint, &Info),
+) where
+    T: Hittable,
+{
+    render(world, lights, camera.as_ref(), checkpoint, save)
+}
+
+// The names resume_scene() knows the scenes by
+pub const SCENES: &[&str] = &[
+    "image15",
+    "image20",
+    "image21",
+    "lights",
+    "stage",
+    "instances",
+    "motion_blur",
+    "smoke",
+    "cloud",
+    "csg",
+    "sdf",
+    "shapes",
+    "terrain",
+    "bezier",
+    "fur",
+    "panorama",
+    "stereo",
+    "bokeh",
+    "lens",
+];
+
+// resume_still() for the scene called `name`, for callers that pick the
+// scene at run time, like the render service
+pub fn resume_scene(
+    name: &str,
+    checkpoint: &mut Checkpoint,
+    save: &mut dyn FnMut(&Checkpoint, &Info),
+) -> Result<(), String> {
+    let mut rng = RenderRng::seed_from_u64(checkpoint.scene_seed);
+    let rng = &mut rng;
+    // the scenes differ in the type of their objects
+    macro_rules! render_scene {
+        ($scene:expr) => {{
+            let (world, lights, camera) = $scene;
+            render(&world, &lights, camera.as_ref(), checkpoint, save)
+        }};
+    }
+    match name {
+        "image15" => render_scene!(image15_scene()),
+        "image20" => render_scene!(image20_scene()),
+        "image21" => render_scene!(image21_scene(rng)),
+        "lights" => render_scene!(lights_scene(rng)),
+        "stage" => render_scene!(stage_scene()),
+        "instances" => render_scene!(instances_scene(rng)),
+        "motion_blur" => render_scene!(motion_blur_scene(rng)),
+        "smoke" => render_scene!(smoke_scene(rng)),
+        "cloud" => render_scene!(cloud_scene(rng)),
+        "csg" => render_scene!(csg_scene()),
+        "sdf" => render_scene!(sdf_scene()),
+        "shapes" => render_scene!(shapes_scene()),
+        "terrain" => render_scene!(terrain_scene(rng)),
+        "bezier" => render_scene!(bezier_scene()),
+        "fur" => render_scene!(fur_scene(rng)),
+        "panorama" => render_scene!(panorama_scene(rng)),
+        "stereo" => render_scene!(stereo_scene(rng)),
+        "bokeh" => render_scene!(bokeh_scene(rng)),
+        "lens" => render_scene!(lens_scene(rng)),
+        _ => return Err(format!("unknown scene {}", name)),
+    }
+    Ok(())
+}
+
+// Frame `frame` of animation_scene(), out of `frames` in the loop
+pub fn render_frame(frame: u32, frames: u32, settings: &Settings) -> Framebuffer {
+    log!("frame {} of {}", frame + 1, frames);
+    let (width, height) = framebuffer_size();
+    let mut checkpoint = Checkpoint::new(*settings, width, height);
+    let (world, lights, camera) = animation_scene(frame as f64 / frames as f64);
+    render(
+        &world,
+        &lights,
+        camera.as_ref(),
+        &mut checkpoint,
+        &mut |_, _| (),
+    );
+    checkpoint.framebuffer
+}
+
+// Continues the render in `checkpoint`, which holds the samples so far.
+fn render<T>(
+    world: &HittableList<T>,
+    lights: &[Box<dyn Light>],
+    camera: &dyn Camera,
+    checkpoint: &mut Checkpoint,
+    save: &mut dyn FnMut(&Checkpoint, &Info),
+) where
+    T: Hittable,
+{
+    let mut info = Info::new();
+    let settings = checkpoint.settings;
+    let (width, height) = (checkpoint.framebuffer.width, checkpoint.framebuffer.height);
+    let pixels = (width * height) as u64;
+    let samples_per_pixel = settings.samples_per_pixel.max(1);
+    let threshold = match settings.mode {
+        Mode::Noise(target) => target.max(f64::MIN_POSITIVE),
+        _ => settings.noise_threshold,
+    };
+    let adaptive = threshold > 0.;
+    let budget = match settings.mode {
+        Mode::Samples => pixels * samples_per_pixel as u64,
+        Mode::SampleBudget(samples) => samples.max(pixels),
+        Mode::Time(_) | Mode::Noise(_) => u64::MAX,
+    };
+    let start = now();
+    let max_samples = MAX_SAMPLES_SCALE * samples_per_pixel;
+    let taken_before = checkpoint.taken;
+    let whole_image = checkpoint.origin == (0, 0) && (width, height) == framebuffer_size();
+    checkpoint.framebuffer.aovs.number_materials(world);
 
     //
     // Render
     //
-    context.save();
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-            if x == 0 && y % 3 == 0 {
-                info.update_progress(x, y);
-                log!("progress y = {}, {}% completed", y, info.progress);
+    // Passes of one sample for every pixel, until adaptive sampling has
+    // enough of them to tell which pixels are noisy. The budgets count the
+    // samples from before a resume too, the time limit doesn't.
+    'render: loop {
+        let pass = checkpoint.pass;
+        let uniform = !adaptive || pass < FIRST_PASS_SAMPLES;
+        let pass_pixels = if uniform {
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .collect()
+        } else {
+            adaptive::noisy_pixels(&checkpoint.framebuffer, threshold, max_samples)
+        };
+        if pass_pixels.is_empty() {
+            break;
+        }
+        for &(x, y) in &pass_pixels {
+            let framebuffer = &mut checkpoint.framebuffer;
+            // doubling the samples of a pixel halves its variance
+            let samples = framebuffer.samples(x, y);
+            let n = if uniform {
+                1
+            } else {
+                samples.min(max_samples - samples)
+            };
+            for _ in 0..n {
+                if checkpoint.taken >= budget {
+                    break 'render;
+                }
+                sample_pixel(
+                    framebuffer,
+                    x,
+                    y,
+                    checkpoint.origin,
+                    world,
+                    lights,
+                    camera,
+                    &mut checkpoint.rng,
+                );
+                checkpoint.taken += 1;
             }
-            if x % RESOLUTION != 0 || y % RESOLUTION != 0 {
-                continue;
+            if let Mode::Time(seconds) = settings.mode {
+                if pass > 0 && now() - start >= seconds {
+                    break 'render;
+                }
             }
+        }
+        checkpoint.pass += 1;
 
-            let mut pixel_color = Color::new(0., 0., 0.);
-            for _ in 0..SAMPLES_PER_PIXEL {
-                let u =
-                    (x as f64 + random_f64(&mut rng, 0., RESOLUTION as f64)) / (WIDTH - 1) as f64;
-                let v = 1.
-                    - (y as f64 + random_f64(&mut rng, 0., RESOLUTION as f64))
-                        / (HEIGHT - 1) as f64;
+        info.update_progress(match settings.mode {
+            Mode::Time(seconds) => (now() - start) / seconds,
+            Mode::Noise(_) => 1. - pass_pixels.len() as f64 / pixels as f64,
+            Mode::Samples | Mode::SampleBudget(_) => checkpoint.taken as f64 / budget as f64,
+        });
+        // tiles of distributed renders are too many to log
+        if whole_image {
+            log!(
+                "pass {}: {} of {} pixels sampled, {}% completed",
+                checkpoint.pass,
+                pass_pixels.len(),
+                pixels,
+                info.progress
+            );
+        }
+        save(checkpoint, &info);
+    }
+    if whole_image {
+        log!(
+            "{} samples in {:.1} s, {:.2} per pixel",
+            checkpoint.taken - taken_before,
+            now() - start,
+            checkpoint.taken as f64 / pixels as f64
+        );
+    }
+}
 
-                let ray = camera.get_ray(u, v, &mut rng);
+// Adds the color of one camera ray through a random point of pixel (x, y)
+// of the framebuffer, which starts at `origin` in the image
+#[allow(clippy::too_many_arguments)]
+fn sample_pixel<T>(
+    framebuffer: &mut Framebuffer,
+    x: u32,
+    y: u32,
+    origin: (u32, u32),
+    world: &HittableList<T>,
+    lights: &[Box<dyn Light>],
+    camera: &dyn Camera,
+    rng: &mut RenderRng,
+) where
+    T: Hittable,
+{
+    let (image_x, image_y) = (origin.0 + x, origin.1 + y);
+    let u = ((image_x * RESOLUTION) as f64 + random_f64(rng, 0., RESOLUTION as f64))
+        / (WIDTH - 1) as f64;
+    let v = 1.
+        - ((image_y * RESOLUTION) as f64 + random_f64(rng, 0., RESOLUTION as f64))
+            / (HEIGHT - 1) as f64;
 
-                pixel_color += ray_color(&ray, &world, &mut rng, MAX_DEPTH);
-            }
-            write_color(&context, x, y, pixel_color);
+    // some projections leave parts of the image without rays
+    let color = match camera.sample_ray(u, v, rng) {
+        Some((ray, weight)) => {
+            let hit_record = world.hit(&ray, 0.001, f64::INFINITY, rng);
+            let background = background(&ray);
+            framebuffer
+                .aovs
+                .add_sample(x, y, &ray, hit_record.as_ref(), background);
+            let color = match &hit_record {
+                Some(hit_record) => shade(&ray, hit_record, world, lights, rng, MAX_DEPTH),
+                None => background,
+            };
+            weight.component_mul(&color)
         }
-    }
-    log!("Done!");
+        None => Color::zeros(),
+    };
+    framebuffer.add_sample(x, y, color);
 }
 
-fn ray_color<T>(ray: &Ray, world: &HittableList<T>, rng: &mut ThreadRng, depth: i32) -> Color
+fn ray_color<T>(
+    ray: &Ray,
+    world: &HittableList<T>,
+    lights: &[Box<dyn Light>],
+    rng: &mut RenderRng,
+    depth: i32,
+) -> Color
 where
     T: Hittable,
 {
@@ -115,49 +559,55 @@ where
     if depth < 0 {
         return Color::new(0., 0., 0.);
     }
-    match world.hit(ray, 0.001, INFINITY) {
-        Some(hit_record) => {
-            match hit_record.material.scatter(ray, &hit_record, rng) {
-                Some((scattered, attenuation)) => {
-                    // FIXME: in place
-                    return attenuation.component_mul(&ray_color(&scattered, world, rng, depth));
-                }
-                None => {
-                    return Color::new(0., 0., 0.);
-                }
-            }
-        }
-        None => {
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.);
-            (1. - t) * Color::new(1., 1., 1.) + t * Color::new(0.5, 0.7, 1.)
-        }
+    match world.hit(ray, 0.001, f64::INFINITY, rng) {
+        Some(hit_record) => shade(ray, &hit_record, world, lights, rng, depth),
+        None => background(ray),
     }
 }
 
-fn write_color(context: &CanvasRenderingContext2d, x: u32, y: u32, color: Color) {
-    let (r, g, b) = (color.x, color.y, color.z);
+// Light leaving the hit point back along the ray
+fn shade<T>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    world: &HittableList<T>,
+    lights: &[Box<dyn Light>],
+    rng: &mut RenderRng,
+    depth: i32,
+) -> Color
+where
+    T: Hittable,
+{
+    // Light from the delta lights reaches this point only via shadow rays.
+    let direct = direct_lighting(ray, hit_record, lights, world, rng, |direction| {
+        hit_record.material.eval(ray, hit_record, direction)
+    });
+    let emitted = hit_record.material.emitted(hit_record);
+    match hit_record.material.scatter(ray, hit_record, rng) {
+        Some((scattered, attenuation)) => {
+            // FIXME: in place
+            emitted
+                + direct
+                + attenuation.component_mul(&ray_color(&scattered, world, lights, rng, depth - 1))
+        }
+        None => emitted + direct,
+    }
+}
 
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let scale = 1. / SAMPLES_PER_PIXEL as f64;
-    let r = sqrt(scale * r);
-    let g = sqrt(scale * g);
-    let b = sqrt(scale * b);
+fn background(ray: &Ray) -> Color {
+    let unit_direction = ray.direction.normalize();
+    let t = 0.5 * (unit_direction.y + 1.);
+    (1. - t) * Color::new(1., 1., 1.) + t * Color::new(0.5, 0.7, 1.)
+}
 
-    let px = x as f64;
-    let py = y as f64;
-    let color = JsValue::from_str(&format!(
-        "rgba({},{},{},{})",
-        255. * clamp(r, 0., 0.999),
-        255. * clamp(g, 0., 0.999),
-        255. * clamp(b, 0., 0.999),
-        255.
-    ));
+fn write_color(context: &CanvasRenderingContext2d, x: u32, y: u32, [r, g, b]: [u8; 3]) {
+    let color = JsValue::from_str(&format!("rgb({},{},{})", r, g, b));
+    #[allow(deprecated)]
     context.set_fill_style(&color);
-    context.fill_rect(px, py, px + RESOLUTION as f64, py + RESOLUTION as f64);
+    context.fill_rect(x as f64, y as f64, RESOLUTION as f64, RESOLUTION as f64);
 }
 
-fn image15_scene() -> (HittableList<Sphere>, Camera) {
+#[allow(dead_code)]
+fn image15_scene() -> Scene<Sphere> {
     let mut world = HittableList::new();
 
     let material_ground = Lambertian::new(Color::new(0.8, 0.8, 0.));
@@ -189,11 +639,12 @@ fn image15_scene() -> (HittableList<Sphere>, Camera) {
     let lookfrom = Vector3::new(0., 0., 0.);
     let lookat = Vector3::new(0., 0., -1.);
     let vup = Vector3::new(0., 1., 0.);
-    let vfov = rad_to_deg(2. * atan(2.0 /* = h */ / 2.));
+    let h: f64 = 2.0;
+    let vfov = rad_to_deg(2. * (h / 2.).atan());
     let dist_to_focus = (lookfrom - lookat).norm();
     let aperture = 0.1;
 
-    let camera = Camera::new(
+    let camera = PerspectiveCamera::new(
         lookfrom,
         lookat,
         vup,
@@ -202,10 +653,11 @@ fn image15_scene() -> (HittableList<Sphere>, Camera) {
         aperture,
         dist_to_focus,
     );
-    (world, camera)
+    (world, Vec::new(), Box::new(camera))
 }
 
-fn image20_scene() -> (HittableList<Sphere>, Camera) {
+#[allow(dead_code)]
+fn image20_scene() -> Scene<Sphere> {
     //
     // World
     //
@@ -230,7 +682,7 @@ fn image20_scene() -> (HittableList<Sphere>, Camera) {
         world.add(Sphere {
             center: Vector3::new(-1., 0., -1.),
             radius: 0.5,
-            material: Rc::new(material_left.clone()),
+            material: Rc::new(material_left),
         });
         world.add(Sphere {
             center: Vector3::new(-1., 0., -1.),
@@ -253,7 +705,7 @@ fn image20_scene() -> (HittableList<Sphere>, Camera) {
     let dist_to_focus = (lookfrom - lookat).norm();
     let aperture = 2.;
 
-    let camera = Camera::new(
+    let camera = PerspectiveCamera::new(
         lookfrom,
         lookat,
         vup,
@@ -262,10 +714,10 @@ fn image20_scene() -> (HittableList<Sphere>, Camera) {
         aperture,
         dist_to_focus,
     );
-    (world, camera)
+    (world, Vec::new(), Box::new(camera))
 }
 
-fn image21_scene(rng: &mut ThreadRng) -> (HittableList<Sphere>, Camera) {
+fn image21_scene(rng: &mut RenderRng) -> Scene<Sphere> {
     //
     // World
     //
@@ -345,7 +797,282 @@ fn image21_scene(rng: &mut ThreadRng) -> (HittableList<Sphere>, Camera) {
     let dist_to_focus = 10.;
     let aperture = 0.1;
 
-    let camera = Camera::new(
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        20.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, Vec::new(), Box::new(camera))
+}
+
+// image21 lit by a point light, a spot light and a sun.
+#[allow(dead_code)]
+fn lights_scene(rng: &mut RenderRng) -> Scene<Sphere> {
+    let (world, mut lights, camera) = image21_scene(rng);
+
+    // warm bulb above the brown sphere
+    lights.push(Box::new(PointLight::new(
+        Vector3::new(-4., 4., 2.),
+        Color::new(20., 16., 12.),
+    )));
+    // spot on the glass sphere
+    lights.push(Box::new(SpotLight::new(
+        Vector3::new(0., 6., 4.),
+        Vector3::new(0., 1., 0.),
+        Color::new(40., 40., 40.),
+        10.,
+        20.,
+    )));
+    // low sun from the back with the apparent size of the real one
+    lights.push(Box::new(DirectionalLight::new(
+        Vector3::new(-1., -0.5, -0.5),
+        Color::new(1., 0.9, 0.8),
+        0.53,
+    )));
+
+    (world, lights, camera)
+}
+
+// A product stage: floor plane, two walls, a box and a pedestal.
+#[allow(dead_code)]
+fn stage_scene() -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    let floor: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.6, 0.6, 0.6)));
+    let wall: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.7, 0.3, 0.3)));
+    let pedestal: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 0.2));
+    let crate_material: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.2, 0.4, 0.6)));
+
+    world.add(Box::new(Plane::new(
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        1.,
+        floor,
+    )));
+    world.add(Box::new(XYRect {
+        x0: -4.,
+        x1: 4.,
+        y0: 0.,
+        y1: 4.,
+        k: -3.,
+        material: Rc::clone(&wall),
+    }));
+    world.add(Box::new(YZRect {
+        y0: 0.,
+        y1: 4.,
+        z0: -3.,
+        z1: 3.,
+        k: -4.,
+        material: wall,
+    }));
+    world.add(Box::new(Cuboid::new(
+        Vector3::new(-2.5, 0., -1.5),
+        Vector3::new(-1., 1.5, 0.),
+        crate_material,
+    )));
+    world.add(Box::new(Disk::new(
+        Vector3::new(1., 0.01, 0.),
+        Vector3::new(0., 1., 0.),
+        1.2,
+        pedestal,
+    )));
+    world.add(Box::new(Sphere {
+        center: Vector3::new(1., 0.7, 0.),
+        radius: 0.7,
+        material: Rc::new(Dielectic::new(1.5)),
+    }));
+
+    let lights: Vec<Box<dyn Light>> = vec![Box::new(SpotLight::new(
+        Vector3::new(2., 5., 3.),
+        Vector3::new(0., 0., 0.),
+        Color::new(60., 60., 60.),
+        20.,
+        35.,
+    ))];
+
+    let lookfrom = Vector3::new(4., 2.5, 6.);
+    let lookat = Vector3::new(0., 0.8, 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.05;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        40.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, lights, Box::new(camera))
+}
+
+// A few hundred transformed copies of a single shared "table" model.
+#[allow(dead_code)]
+fn instances_scene(rng: &mut RenderRng) -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    world.add(Box::new(Plane::new(
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        1.,
+        Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+
+    // The model is built once around the origin and shared by every instance.
+    let wood: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.3, 0.1)));
+    let mut table: HittableList<Box<dyn Hittable>> = HittableList::new();
+    table.add(Box::new(Cuboid::new(
+        Vector3::new(-0.5, 0.4, -0.3),
+        Vector3::new(0.5, 0.45, 0.3),
+        Rc::clone(&wood),
+    )));
+    for (x, z) in [(-0.45, -0.25), (0.4, -0.25), (-0.45, 0.2), (0.4, 0.2)] {
+        table.add(Box::new(Cuboid::new(
+            Vector3::new(x, 0., z),
+            Vector3::new(x + 0.05, 0.4, z + 0.05),
+            Rc::clone(&wood),
+        )));
+    }
+    table.add(Box::new(Sphere {
+        center: Vector3::new(0., 0.55, 0.),
+        radius: 0.1,
+        material: Rc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 0.)),
+    }));
+    let table = Rc::new(table);
+
+    for a in -10..10 {
+        for b in -10..10 {
+            let transform = Transform::scaling(Vector3::new(
+                random_f64(rng, 0.6, 1.2),
+                random_f64(rng, 0.6, 1.4),
+                random_f64(rng, 0.6, 1.2),
+            ))
+            .then(&Transform::rotation(
+                Vector3::new(0., 1., 0.),
+                random_f64(rng, 0., 360.),
+            ))
+            .then(&Transform::translation(Vector3::new(
+                a as f64 * 1.5,
+                0.,
+                b as f64 * 1.5,
+            )));
+            world.add(Box::new(Instance::new(Rc::clone(&table), transform)));
+        }
+    }
+
+    let lookfrom = Vector3::new(12., 6., 10.);
+    let lookat = Vector3::new(0., 0., 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        30.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, Vec::new(), Box::new(camera))
+}
+
+// image21 with bouncing diffuse spheres and a spinning box, shot with the
+// shutter open from time 0 to 1.
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#motionblur/puttingeverythingtogether
+#[allow(dead_code)]
+fn motion_blur_scene(rng: &mut RenderRng) -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    let ground_material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    world.add(Box::new(Sphere {
+        center: Vector3::new(0., -1000., 0.),
+        radius: 1000.,
+        material: Rc::new(ground_material),
+    }));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = rng.gen::<f64>();
+            let center = Vector3::new(
+                a as f64 + 0.9 * rng.gen::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.gen::<f64>(),
+            );
+
+            if (center - Vector3::new(4., 0.2, 0.)).norm() > 0.9 {
+                if choose_mat < 0.8 {
+                    // bouncing diffuse
+                    let albedo: Color = random_vec3(rng).component_mul(&random_vec3(rng));
+                    let center1 = center + Vector3::new(0., random_f64(rng, 0., 0.5), 0.);
+                    world.add(Box::new(MovingSphere {
+                        center0: center,
+                        center1,
+                        time0: 0.,
+                        time1: 1.,
+                        radius: 0.2,
+                        material: Rc::new(Lambertian::new(albedo)),
+                    }));
+                } else if choose_mat < 0.95 {
+                    // metal
+                    let albedo = random_vec3(rng) * 0.5 + Vector3::new(0.5, 0.5, 0.5);
+                    let fuzz = random_f64(rng, 0., 0.5);
+                    world.add(Box::new(Sphere {
+                        center,
+                        radius: 0.2,
+                        material: Rc::new(Metal::new(albedo, fuzz)),
+                    }));
+                } else {
+                    world.add(Box::new(Sphere {
+                        center,
+                        radius: 0.2,
+                        material: Rc::new(Dielectic::new(1.5)),
+                    }));
+                }
+            }
+        }
+    }
+
+    world.add(Box::new(Sphere {
+        center: Vector3::new(0., 1., 0.),
+        radius: 1.,
+        material: Rc::new(Dielectic::new(1.5)),
+    }));
+
+    // a box spinning a quarter turn around Y while the shutter is open
+    let spinning_box = Cuboid::new(
+        Vector3::new(-0.7, -0.7, -0.7),
+        Vector3::new(0.7, 0.7, 0.7),
+        Rc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1))),
+    );
+    let pose0 = Pose::new(
+        Vector3::new(-4., 1., 0.),
+        UnitQuaternion::identity(),
+        Vector3::new(1., 1., 1.),
+    );
+    let pose1 = pose0.rotated(Vector3::new(0., 1., 0.), 90.);
+    world.add(Box::new(MovingInstance::new(
+        spinning_box,
+        pose0,
+        pose1,
+        0.,
+        1.,
+    )));
+
+    let lookfrom = Vector3::new(13., 2., 3.);
+    let lookat = Vector3::new(0., 0., 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.1;
+
+    let camera = PerspectiveCamera::new(
         lookfrom,
         lookat,
         vup,
@@ -353,6 +1080,660 @@ fn image21_scene(rng: &mut ThreadRng) -> (HittableList<Sphere>, Camera) {
         ASPECT_RATIO,
         aperture,
         dist_to_focus,
+    )
+    .with_shutter(0., 1.);
+    (world, Vec::new(), Box::new(camera))
+}
+
+// image21 in a light fog, with the big glass sphere filled with white smoke
+// and a spot light shining through the fog.
+#[allow(dead_code)]
+fn smoke_scene(rng: &mut RenderRng) -> Scene<Box<dyn Hittable>> {
+    let (spheres, _, camera) = image21_scene(rng);
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+    world.add(Box::new(spheres));
+
+    // smoke inside the glass sphere at (0, 1, 0)
+    world.add(Box::new(ConstantMedium::new(
+        Sphere {
+            center: Vector3::new(0., 1., 0.),
+            radius: 0.95,
+            material: Rc::new(Lambertian::new(Color::new(1., 1., 1.))),
+        },
+        2.,
+        Color::new(0.9, 0.9, 0.9),
+    )));
+    // fog around the whole scene, camera included
+    world.add(Box::new(ConstantMedium::new(
+        Sphere {
+            center: Vector3::new(0., 0., 0.),
+            radius: 50.,
+            material: Rc::new(Lambertian::new(Color::new(1., 1., 1.))),
+        },
+        0.02,
+        Color::new(1., 1., 1.),
+    )));
+
+    let lights: Vec<Box<dyn Light>> = vec![Box::new(SpotLight::new(
+        Vector3::new(-2., 8., 2.),
+        Vector3::new(0., 0., 0.),
+        Color::new(100., 90., 80.),
+        10.,
+        15.,
+    ))];
+
+    (world, lights, camera)
+}
+
+// A procedural cloud in a voxel grid, with a glowing core, hovering over image21.
+// Load simulation data with VoxelGrid::from_text or VoxelGrid::from_raw instead.
+#[allow(dead_code)]
+fn cloud_scene(rng: &mut RenderRng) -> Scene<Box<dyn Hittable>> {
+    let (spheres, _, camera) = image21_scene(rng);
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+    world.add(Box::new(spheres));
+
+    // a few random blobs with soft edges
+    const N: usize = 48;
+    let blobs: Vec<(Vector3<f64>, f64)> = (0..12)
+        .map(|_| {
+            let center = Vector3::new(
+                random_f64(rng, 0.3, 0.7),
+                random_f64(rng, 0.35, 0.65),
+                random_f64(rng, 0.3, 0.7),
+            );
+            (center, random_f64(rng, 0.1, 0.25))
+        })
+        .collect();
+    let mut density = Vec::with_capacity(N * N * N);
+    let mut emission = Vec::with_capacity(N * N * N);
+    for z in 0..N {
+        for y in 0..N {
+            for x in 0..N {
+                let p = Vector3::new(x as f64, y as f64, z as f64) / N as f64;
+                let d: f64 = blobs
+                    .iter()
+                    .map(|(center, radius)| clamp(1. - (p - center).norm() / radius, 0., 1.))
+                    .sum();
+                density.push(d);
+                // hot where the cloud is thickest
+                emission.push(clamp(d - 1., 0., 1.));
+            }
+        }
+    }
+    let grid = VoxelGrid::new(N, N, N, density).with_emission(emission);
+
+    world.add(Box::new(GridMedium::new(
+        Rc::new(grid),
+        Aabb::new(Vector3::new(-3., 1.5, -2.), Vector3::new(1., 4.5, 2.)),
+        4.,
+        Color::new(0.9, 0.9, 0.9),
+        Color::new(4., 1.5, 0.3),
+    )));
+
+    (world, Vec::new(), camera)
+}
+
+// image20 built with CSG: the hollow glass ball is a proper difference instead
+// of a sphere with a negative radius, the center ball has a bite taken out of
+// it and the right one is the lens-shaped intersection of two spheres.
+#[allow(dead_code)]
+fn csg_scene() -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    let material_ground: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.)));
+    let material_center: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+    let material_cut: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.3, 0.3)));
+    let material_left: Rc<dyn Material> = Rc::new(Dielectic::new(1.5));
+    let material_right: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.));
+
+    world.add(Box::new(Sphere {
+        center: Vector3::new(0., -100.5, -1.),
+        radius: 100.,
+        material: material_ground,
+    }));
+    world.add(Box::new(Csg::difference(
+        Sphere {
+            center: Vector3::new(0., 0., -1.),
+            radius: 0.5,
+            material: material_center,
+        },
+        Cuboid::new(
+            Vector3::new(0., 0., -1.),
+            Vector3::new(1., 1., 0.),
+            material_cut,
+        ),
+    )));
+    world.add(Box::new(Csg::difference(
+        Sphere {
+            center: Vector3::new(-1., 0., -1.),
+            radius: 0.5,
+            material: Rc::clone(&material_left),
+        },
+        Sphere {
+            center: Vector3::new(-1., 0., -1.),
+            radius: 0.45,
+            material: material_left,
+        },
+    )));
+    world.add(Box::new(Csg::intersection(
+        Sphere {
+            center: Vector3::new(1., 0., -1.3),
+            radius: 0.6,
+            material: Rc::clone(&material_right),
+        },
+        Sphere {
+            center: Vector3::new(1., 0., -0.7),
+            radius: 0.6,
+            material: material_right,
+        },
+    )));
+
+    let lookfrom = Vector3::new(3., 3., 2.);
+    let lookat = Vector3::new(0., 0., -1.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.1;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        20.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, Vec::new(), Box::new(camera))
+}
+
+// Sphere-traced SDF shapes next to ordinary spheres.
+#[allow(dead_code)]
+fn sdf_scene() -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    world.add(Box::new(Sphere {
+        center: Vector3::new(0., -1000., 0.),
+        radius: 1000.,
+        material: Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    }));
+    world.add(Box::new(Sphere {
+        center: Vector3::new(4., 1., 0.),
+        radius: 1.,
+        material: Rc::new(Dielectic::new(1.5)),
+    }));
+
+    // Mandelbulb
+    world.add(Box::new(
+        SdfObject::new(
+            Translate {
+                sdf: Mandelbulb {
+                    power: 8.,
+                    iterations: 12,
+                },
+                offset: Vector3::new(0., 1.2, 0.),
+            },
+            Rc::new(Lambertian::new(Color::new(0.8, 0.5, 0.3))),
+        )
+        .with_max_steps(512),
+    ));
+
+    // a ball melting into a ring
+    world.add(Box::new(SdfObject::new(
+        Translate {
+            sdf: SmoothUnion {
+                a: SdfSphere { radius: 0.5 },
+                b: Torus {
+                    major_radius: 0.8,
+                    minor_radius: 0.15,
+                },
+                k: 0.3,
+            },
+            offset: Vector3::new(-4., 0.7, 0.),
+        },
+        Rc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 0.1)),
+    )));
+
+    // a rounded slab with a grid of holes carved out of it
+    world.add(Box::new(SdfObject::new(
+        Translate {
+            sdf: Subtraction {
+                a: RoundBox {
+                    half_extents: Vector3::new(1., 0.15, 1.),
+                    radius: 0.1,
+                },
+                b: Repetition {
+                    sdf: SdfSphere { radius: 0.15 },
+                    period: Vector3::new(0.5, 0., 0.5),
+                },
+                k: 0.05,
+            },
+            offset: Vector3::new(0., 0.15, 3.),
+        },
+        Rc::new(Lambertian::new(Color::new(0.2, 0.5, 0.3))),
+    )));
+
+    // a twisted column next to a capsule
+    world.add(Box::new(
+        SdfObject::new(
+            Translate {
+                sdf: Twist {
+                    sdf: SdfBox {
+                        half_extents: Vector3::new(0.3, 1., 0.3),
+                    },
+                    rate: 1.5,
+                },
+                offset: Vector3::new(0., 1., -3.),
+            },
+            Rc::new(Lambertian::new(Color::new(0.6, 0.2, 0.2))),
+        )
+        .with_step_scale(0.5),
+    ));
+    world.add(Box::new(SdfObject::new(
+        Capsule {
+            a: Vector3::new(1., 0.2, -3.),
+            b: Vector3::new(2., 1.5, -2.5),
+            radius: 0.2,
+        },
+        Rc::new(Lambertian::new(Color::new(0.9, 0.9, 0.2))),
+    )));
+
+    let lookfrom = Vector3::new(13., 2., 3.);
+    let lookat = Vector3::new(0., 0.8, 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.1;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        30.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
     );
-    (world, camera)
+    (world, Vec::new(), Box::new(camera))
+}
+
+// Machine parts: cylinders, cones, a torus and quadrics.
+#[allow(dead_code)]
+fn shapes_scene() -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    let steel: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.7, 0.7, 0.75), 0.2));
+    let brass: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.05));
+    let paint: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.7, 0.2, 0.1)));
+
+    world.add(Box::new(Plane::new(
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        1.,
+        Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+    // a bolt: shaft, head and a washer
+    world.add(Box::new(Cylinder {
+        center: Vector3::new(0., 0., 0.),
+        radius: 0.3,
+        height: 2.,
+        capped: true,
+        material: Rc::clone(&steel),
+    }));
+    world.add(Box::new(Cylinder {
+        center: Vector3::new(0., 2., 0.),
+        radius: 0.6,
+        height: 0.4,
+        capped: true,
+        material: Rc::clone(&steel),
+    }));
+    world.add(Box::new(quadric::Torus {
+        center: Vector3::new(0., 0.5, 0.),
+        major_radius: 0.6,
+        minor_radius: 0.15,
+        material: Rc::clone(&brass),
+    }));
+    // a cone tipped over on its side
+    world.add(Box::new(Instance::new(
+        Cone {
+            center: Vector3::new(0., 0., 0.),
+            radius: 0.6,
+            height: 1.5,
+            capped: true,
+            material: Rc::clone(&paint),
+        },
+        Transform::rotation(Vector3::new(0., 0., 1.), 90.)
+            .then(&Transform::translation(Vector3::new(2.5, 0.6, 1.))),
+    )));
+    // an ellipsoid and a hyperboloid of one sheet clipped to a box
+    world.add(Box::new(Quadric::ellipsoid(
+        Vector3::new(-2.5, 0.6, 0.5),
+        Vector3::new(1., 0.6, 0.6),
+        Rc::clone(&brass),
+    )));
+    world.add(Box::new(Quadric::new(
+        [1., -1., 1., 0., 0., 0., 0., 1.5, 0., -0.6],
+        Aabb::new(Vector3::new(-1.5, 0., -3.5), Vector3::new(1.5, 3., -0.5)),
+        paint,
+    )));
+
+    let lookfrom = Vector3::new(6., 4., 8.);
+    let lookat = Vector3::new(0., 1., 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.05;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        35.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, Vec::new(), Box::new(camera))
+}
+
+// Rolling hills from a noise heightfield under a low sun, replacing the giant
+// ground sphere. Heightfield::from_pgm loads real terrain instead.
+#[allow(dead_code)]
+fn terrain_scene(rng: &mut RenderRng) -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    world.add(Box::new(Heightfield::from_noise(
+        1024,
+        1024,
+        6.,
+        6,
+        Vector3::new(-50., -2., -50.),
+        Vector3::new(100., 8., 100.),
+        Rc::new(Lambertian::new(Color::new(0.4, 0.5, 0.3))),
+        rng,
+    )));
+    world.add(Box::new(Sphere {
+        center: Vector3::new(0., 4., 0.),
+        radius: 1.,
+        material: Rc::new(Dielectic::new(1.5)),
+    }));
+
+    let lights: Vec<Box<dyn Light>> = vec![Box::new(DirectionalLight::new(
+        Vector3::new(-1., -0.4, -0.3),
+        Color::new(2., 1.8, 1.5),
+        0.53,
+    ))];
+
+    let lookfrom = Vector3::new(30., 10., 20.);
+    let lookat = Vector3::new(0., 2., 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        40.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, lights, Box::new(camera))
+}
+
+// A vase turned from two Bezier profiles and a rippled sheet, all patches.
+// A teapot file loads with BezierPatch::parse(include_str!(...), material).
+#[allow(dead_code)]
+fn bezier_scene() -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    // a single patch with its middle control points pulled up and down
+    let mut sheet = [[Vector3::zeros(); 4]; 4];
+    for (i, row) in sheet.iter_mut().enumerate() {
+        for (j, p) in row.iter_mut().enumerate() {
+            let height = if (i + j) % 2 == 0 { 0. } else { 0.8 };
+            *p = Vector3::new(j as f64 * 3. - 4.5, height, i as f64 * 3. - 4.5);
+        }
+    }
+    world.add(Box::new(BezierPatch::new(
+        sheet,
+        Rc::new(Lambertian::new(Color::new(0.3, 0.4, 0.6))),
+    )));
+
+    let glaze: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.8, 0.5, 0.3), 0.1));
+    let body = [
+        Vector2::new(0.6, 0.6),
+        Vector2::new(1.8, 0.8),
+        Vector2::new(1.6, 2.2),
+        Vector2::new(0.5, 2.8),
+    ];
+    let neck = [
+        Vector2::new(0.5, 2.8),
+        Vector2::new(0.3, 3.1),
+        Vector2::new(0.4, 3.4),
+        Vector2::new(0.7, 3.6),
+    ];
+    for patch in BezierPatch::revolve(body, Rc::clone(&glaze))
+        .into_iter()
+        .chain(BezierPatch::revolve(neck, glaze))
+    {
+        world.add(Box::new(patch));
+    }
+
+    let lights: Vec<Box<dyn Light>> = vec![Box::new(PointLight::new(
+        Vector3::new(4., 8., 4.),
+        Color::new(60., 60., 60.),
+    ))];
+
+    let lookfrom = Vector3::new(7., 5., 7.);
+    let lookat = Vector3::new(0., 1.5, 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        35.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, lights, Box::new(camera))
+}
+
+// A furry ball: a few thousand B-spline strands in a BVH, drooping under
+// their own weight, with brown hair scattering.
+#[allow(dead_code)]
+fn fur_scene(rng: &mut RenderRng) -> Scene<Box<dyn Hittable>> {
+    let mut world: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+    world.add(Box::new(Plane::new(
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        1.,
+        Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+    let center = Vector3::new(0., 1., 0.);
+    let radius = 0.8;
+    world.add(Box::new(Sphere {
+        center,
+        radius,
+        material: Rc::new(Lambertian::new(Color::new(0.2, 0.1, 0.05))),
+    }));
+
+    let hair: Rc<dyn Material> = Rc::new(Hair::from_melanin(1.3, 0.2, 0.3, 0.3, 2.));
+    let mut strands = Vec::new();
+    for _ in 0..3000 {
+        let normal = random_unit_vector(rng);
+        if normal.y < -0.3 {
+            continue;
+        }
+        let length = random_f64(rng, 0.25, 0.4);
+        let root = center + normal * radius;
+        let points: Vec<Vector3<f64>> = (0..5)
+            .map(|i| {
+                let s = i as f64 / 4. * length;
+                root + normal * s - Vector3::new(0., 0.6 * s * s, 0.)
+            })
+            .collect();
+        strands.extend(Curve::strand(
+            &points,
+            CurveBasis::BSpline,
+            0.006,
+            0.001,
+            CurveType::Round,
+            Rc::clone(&hair),
+        ));
+    }
+    world.add(Box::new(BvhNode::new(strands)));
+
+    let lights: Vec<Box<dyn Light>> = vec![
+        Box::new(DirectionalLight::new(
+            Vector3::new(-1., -1., -0.5),
+            Color::new(2., 2., 2.),
+            0.53,
+        )),
+        Box::new(PointLight::new(
+            Vector3::new(-2., 2., -3.),
+            Color::new(10., 10., 12.),
+        )),
+    ];
+
+    let lookfrom = Vector3::new(0., 1.5, 5.);
+    let lookat = Vector3::new(0., 1., 0.);
+    let vup = Vector3::new(0., 1., 0.);
+    let dist_to_focus = (lookfrom - lookat).norm();
+    let aperture = 0.;
+
+    let camera = PerspectiveCamera::new(
+        lookfrom,
+        lookat,
+        vup,
+        30.,
+        ASPECT_RATIO,
+        aperture,
+        dist_to_focus,
+    );
+    (world, lights, Box::new(camera))
+}
+
+// 360 degree view from among the spheres of image 21. Set ASPECT_RATIO to 2.
+#[allow(dead_code)]
+fn panorama_scene(rng: &mut RenderRng) -> Scene<Sphere> {
+    let (world, lights, _) = image21_scene(rng);
+    let camera = EquirectangularCamera::new(
+        Vector3::new(0., 1., 2.),
+        Vector3::new(4., 1., 0.),
+        Vector3::new(0., 1., 0.),
+    );
+    (world, lights, Box::new(camera))
+}
+
+// Red-cyan anaglyph of image 21, with the glass sphere at screen depth.
+#[allow(dead_code)]
+fn stereo_scene(rng: &mut RenderRng) -> Scene<Sphere> {
+    let (world, lights, _) = image21_scene(rng);
+    let camera = StereoCamera::perspective(
+        Vector3::new(13., 2., 3.),
+        Vector3::new(0., 1., 0.),
+        Vector3::new(0., 1., 0.),
+        20.,
+        ASPECT_RATIO,
+        0.,
+        0.4,
+        Convergence::OffAxis,
+        StereoLayout::Anaglyph,
+    );
+    (world, lights, Box::new(camera))
+}
+
+// Image 21 through a fast lens: hexagonal bokeh turning into cat's eyes
+// towards the corners, and the plane in focus tilted to lie along the ground.
+#[allow(dead_code)]
+fn bokeh_scene(rng: &mut RenderRng) -> Scene<Sphere> {
+    let (world, lights, _) = image21_scene(rng);
+    let camera = PerspectiveCamera::new(
+        Vector3::new(13., 2., 3.),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        20.,
+        ASPECT_RATIO,
+        0.8,
+        10.,
+    )
+    .with_aperture(Aperture::Polygon {
+        blades: 6,
+        rotation: 15.,
+    })
+    .with_cat_eye(0.4)
+    .with_tilt(8., 0.)
+    .with_exposure(1.4, 1. / 15., 3200.);
+    (world, lights, Box::new(camera))
+}
+
+#[allow(dead_code)]
+fn lens_scene(rng: &mut RenderRng) -> Scene<Sphere> {
+    let (world, lights, _) = image21_scene(rng);
+    // the double Gauss stopped down to 8mm on a full frame film, with the
+    // scene in meters
+    let elements = parse_prescription(DOUBLE_GAUSS_50MM).unwrap();
+    let camera = RealisticCamera::new(
+        Vector3::new(13., 2., 3.),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        &elements,
+        8.,
+        10.,
+        43.3,
+        ASPECT_RATIO,
+    )
+    .unwrap();
+    (world, lights, Box::new(camera))
+}
+
+// A product spin: the vase of bezier_scene() with a ball hopping around it,
+// seen from a turntable camera. `time` runs from 0 to 1 over the loop.
+#[allow(dead_code)]
+fn animation_scene(time: f64) -> Scene<Box<dyn Hittable>> {
+    let (mut world, lights, _) = bezier_scene();
+
+    // eases in and out of each bounce, squashed on the ground
+    let around = |angle: f64, height: f64, scale: Vector3<f64>| {
+        let turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), deg_to_rad(angle));
+        Pose::new(
+            turn * Vector3::new(3., height, 0.),
+            UnitQuaternion::identity(),
+            scale,
+        )
+    };
+    // the sheet is 0.4 high where the ball lands, and the squashed ball 0.35
+    // high from its center
+    let ground = |angle: f64| around(angle, 0.75, Vector3::new(1.2, 0.7, 1.2));
+    let air = |angle: f64| around(angle, 2., Vector3::new(1., 1., 1.));
+    let mut hop = Track::new(Interpolation::Bezier);
+    for i in 0..4 {
+        let t = i as f64 / 4.;
+        hop = hop
+            .with_key(t, ground(i as f64 * 90.))
+            .with_key(t + 0.125, air(i as f64 * 90. + 45.));
+    }
+    hop = hop.with_key(1., ground(360.));
+    let ball = Sphere {
+        center: Vector3::zeros(),
+        radius: 0.5,
+        material: Rc::new(Lambertian::new(Color::new(0.8, 0.2, 0.1))),
+    };
+    world.add(Box::new(Instance::new(ball, hop.sample(time).transform())));
+
+    let camera = CameraAnimation::turntable(
+        Vector3::new(7., 5., 7.),
+        Vector3::new(0., 1.5, 0.),
+        Vector3::new(0., 1., 0.),
+        35.,
+        ASPECT_RATIO,
+        0.,
+    )
+    .camera(time);
+    (world, lights, Box::new(camera))
 }
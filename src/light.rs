@@ -0,0 +1,182 @@
+use nalgebra::Vector3;
+
+use super::Color;
+use crate::hit::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::utils::*;
+
+// Illumination arriving at a shading point from a single light.
+pub struct LightSample {
+    // unit vector from the shading point towards the light
+    pub direction: Vector3<f64>,
+    // distance to the light along `direction` (INFINITY for directional lights)
+    pub distance: f64,
+    // incident radiance, already attenuated by falloff and cone
+    pub radiance: Color,
+}
+
+// Delta lights: each one illuminates a point from exactly one direction,
+// so they are never hit by rays and are only reached via shadow rays.
+pub trait Light {
+    fn illuminate(&self, p: &Vector3<f64>, rng: &mut RenderRng) -> Option<LightSample>;
+}
+
+pub struct PointLight {
+    position: Vector3<f64>,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Vector3<f64>, intensity: Color) -> Self {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn illuminate(&self, p: &Vector3<f64>, _rng: &mut RenderRng) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.dot(&to_light);
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            // inverse-square falloff
+            radiance: self.intensity / distance_squared,
+        })
+    }
+}
+
+pub struct SpotLight {
+    position: Vector3<f64>,
+    // unit vector the spot is pointing to
+    direction: Vector3<f64>,
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vector3<f64>,
+        lookat: Vector3<f64>,
+        intensity: Color,
+        inner_angle: f64, /* half-angle of the fully lit cone in degrees */
+        outer_angle: f64, /* half-angle where the light falls off to zero in degrees */
+    ) -> Self {
+        SpotLight {
+            position,
+            direction: (lookat - position).normalize(),
+            intensity,
+            cos_inner: deg_to_rad(inner_angle).cos(),
+            cos_outer: deg_to_rad(outer_angle.max(inner_angle)).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            1.
+        } else if cos_theta <= self.cos_outer {
+            0.
+        } else {
+            // smoothstep between the outer and the inner cone
+            let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3. - 2. * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn illuminate(&self, p: &Vector3<f64>, _rng: &mut RenderRng) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.dot(&to_light);
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let falloff = self.falloff(-direction.dot(&self.direction));
+        if falloff <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * falloff / distance_squared,
+        })
+    }
+}
+
+pub struct DirectionalLight {
+    // unit vector from the scene towards the light
+    direction: Vector3<f64>,
+    irradiance: Color,
+    cos_half_angle: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(
+        direction: Vector3<f64>, /* direction the light travels in */
+        irradiance: Color,
+        angular_diameter: f64, /* apparent size of the sun in degrees, 0 for hard shadows */
+    ) -> Self {
+        DirectionalLight {
+            direction: -direction.normalize(),
+            irradiance,
+            cos_half_angle: deg_to_rad(angular_diameter / 2.).cos(),
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn illuminate(&self, _p: &Vector3<f64>, rng: &mut RenderRng) -> Option<LightSample> {
+        // A sun with a non-zero angular diameter is sampled over its disk,
+        // which softens the shadow edges.
+        let direction = if self.cos_half_angle < 1. {
+            random_in_cone(rng, &self.direction, self.cos_half_angle)
+        } else {
+            self.direction
+        };
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+        })
+    }
+}
+
+// Sum of the direct illumination from all lights at a hit point,
+// weighted by `eval` (see Material::eval) and attenuated by whatever lies
+// between the point and the light.
+pub fn direct_lighting<H, F>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    lights: &[Box<dyn Light>],
+    world: &H,
+    rng: &mut RenderRng,
+    eval: F,
+) -> Color
+where
+    H: Hittable,
+    F: Fn(&Vector3<f64>) -> Color,
+{
+    let p = &hit_record.p;
+    let mut color = Color::new(0., 0., 0.);
+    for light in lights {
+        let sample = match light.illuminate(p, rng) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let weight = eval(&sample.direction);
+        if weight == Color::new(0., 0., 0.) {
+            continue;
+        }
+        // shadow ray
+        let shadow_ray = Ray::new(*p, sample.direction, ray.time);
+        let transmittance = world.transmittance(&shadow_ray, 0.001, sample.distance - 0.001, rng);
+        if transmittance <= 0. {
+            continue;
+        }
+        color += weight.component_mul(&sample.radiance) * transmittance;
+    }
+    color
+}
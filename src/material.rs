@@ -1,5 +1,6 @@
-use js_sys::Math::sqrt;
-use rand::prelude::ThreadRng;
+use std::f64::consts::PI;
+
+use nalgebra::Vector3;
 
 use super::Color;
 use crate::hit::HitRecord;
@@ -11,8 +12,26 @@ pub trait Material {
         &self,
         _ray: &Ray,
         hit_record: &HitRecord,
-        rng: &mut ThreadRng,
+        rng: &mut RenderRng,
     ) -> Option<(Ray, Color)>;
+
+    // Fraction of the light arriving from `direction` that is scattered back along
+    // the ray, i.e. BRDF * cosine on surfaces or the phase function in media.
+    // Used to gather direct lighting from delta lights. Perfectly specular
+    // materials can never see a delta light, so they return black.
+    fn eval(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> Color {
+        Color::new(0., 0., 0.)
+    }
+
+    // Light emitted by the material itself at the hit point.
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        Color::new(0., 0., 0.)
+    }
+
+    // Surface color for the albedo buffer, white for clear materials like glass.
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        Color::new(1., 1., 1.)
+    }
 }
 
 pub struct Lambertian {
@@ -28,9 +47,9 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _ray: &Ray,
+        ray_in: &Ray,
         hit_record: &HitRecord,
-        rng: &mut ThreadRng,
+        rng: &mut RenderRng,
     ) -> Option<(Ray, Color)> {
         let mut scatter_direction = hit_record.normal + random_unit_vector(rng);
 
@@ -39,10 +58,22 @@ impl Material for Lambertian {
             scatter_direction = hit_record.normal;
         }
 
-        let scattered = Ray::new(hit_record.p, scatter_direction);
+        let scattered = Ray::new(hit_record.p, scatter_direction, ray_in.time);
         let attenuation = self.albedo;
         Some((scattered, attenuation))
     }
+
+    fn eval(&self, _ray: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Color {
+        let cos_theta = hit_record.normal.dot(direction);
+        if cos_theta <= 0. {
+            return Color::new(0., 0., 0.);
+        }
+        self.albedo / PI * cos_theta
+    }
+
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 pub struct Metal {
@@ -61,12 +92,13 @@ impl Material for Metal {
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        rng: &mut ThreadRng,
+        rng: &mut RenderRng,
     ) -> Option<(Ray, Color)> {
         let reflected = reflect(&ray_in.direction.normalize(), &hit_record.normal);
         let scattered = Ray::new(
             hit_record.p,
             reflected + self.fuzz * random_unit_vector(rng),
+            ray_in.time,
         );
         if scattered.direction.dot(&hit_record.normal) > 0. {
             let attenuation = self.albedo;
@@ -75,6 +107,10 @@ impl Material for Metal {
             None
         }
     }
+
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -95,7 +131,7 @@ impl Material for Dielectic {
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        rng: &mut ThreadRng,
+        rng: &mut RenderRng,
     ) -> Option<(Ray, Color)> {
         let attenuation = Color::new(1., 1., 1.);
         let refraction_ratio = if hit_record.front_face {
@@ -106,7 +142,7 @@ impl Material for Dielectic {
 
         let unit_direction = ray_in.direction.normalize();
         let cos_theta = (-unit_direction.dot(&hit_record.normal)).min(1.);
-        let sin_theta = sqrt(1. - cos_theta * cos_theta);
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
 
         let cannot_reflact = refraction_ratio * sin_theta > 1.;
         let direction = if cannot_reflact
@@ -117,7 +153,39 @@ impl Material for Dielectic {
             refract(&unit_direction, &hit_record.normal, refraction_ratio)
         };
 
-        let scattered = Ray::new(hit_record.p, direction);
+        let scattered = Ray::new(hit_record.p, direction, ray_in.time);
         Some((scattered, attenuation))
     }
 }
+
+// Scatters uniformly in all directions. Phase function of participating media.
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#volumes
+pub struct Isotropic {
+    albedo: Color,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut RenderRng,
+    ) -> Option<(Ray, Color)> {
+        let scattered = Ray::new(hit_record.p, random_unit_vector(rng), ray_in.time);
+        Some((scattered, self.albedo))
+    }
+
+    fn eval(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> Color {
+        self.albedo / (4. * PI)
+    }
+
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        self.albedo
+    }
+}
@@ -0,0 +1,276 @@
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+use rand::Rng;
+
+use super::Color;
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::{Isotropic, Material};
+use crate::ray::Ray;
+use crate::utils::*;
+use crate::voxel::VoxelGrid;
+
+// Fog or smoke of constant density filling a closed boundary shape.
+// Rays are scattered at exponentially distributed distances inside it.
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#volumes/constantdensitymediums
+pub struct ConstantMedium<T>
+where
+    T: Hittable,
+{
+    boundary: T,
+    neg_inv_density: f64,
+    phase_function: Rc<dyn Material>,
+}
+
+impl<T> ConstantMedium<T>
+where
+    T: Hittable,
+{
+    pub fn new(boundary: T, density: f64, albedo: Color) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function: Rc::new(Isotropic::new(albedo)),
+        }
+    }
+}
+
+impl<T> Hittable for ConstantMedium<T>
+where
+    T: Hittable,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        let (t0, t1) = inside_interval(&self.boundary, ray, t_min, t_max, rng)?;
+
+        let ray_length = ray.direction.norm();
+        let distance_inside_boundary = (t1 - t0) * ray_length;
+        let hit_distance = self.neg_inv_density * rng.gen::<f64>().ln();
+        if hit_distance > distance_inside_boundary {
+            return None;
+        }
+
+        let t = t0 + hit_distance / ray_length;
+        // The normal and the face are meaningless inside a volume.
+        let mut hit_record = HitRecord::new(
+            ray,
+            t,
+            &Vector3::new(1., 0., 0.),
+            (0., 0.),
+            Rc::clone(&self.phase_function),
+        );
+        hit_record.front_face = true;
+        Some(hit_record)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.phase_function));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    // Beer-Lambert law
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        match inside_interval(&self.boundary, ray, t_min, t_max, rng) {
+            Some((t0, t1)) => ((t1 - t0) * ray.direction.norm() / self.neg_inv_density).exp(),
+            None => 1.,
+        }
+    }
+}
+
+// Part of [t_min, t_max] (and t >= 0) where the ray is inside the boundary,
+// in the first stretch of it that reaches that far. Each way in pairs with
+// the next hit as the way out.
+fn inside_interval<T>(
+    boundary: &T,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rng: &mut RenderRng,
+) -> Option<(f64, f64)>
+where
+    T: Hittable,
+{
+    // guards against boundaries that keep reporting hits at the same t
+    const MAX_STRETCHES: usize = 32;
+    let start = t_min.max(0.);
+    let mut t = f64::NEG_INFINITY;
+    for _ in 0..MAX_STRETCHES {
+        let first = boundary.hit(ray, t, f64::INFINITY, rng)?;
+        // the ray may start inside, as from the end of an unbounded SDF's march
+        let (enter, exit) = if first.front_face {
+            let exit = boundary.hit(ray, first.t + 0.0001, f64::INFINITY, rng)?;
+            (first.t, exit)
+        } else {
+            (t, first)
+        };
+        if exit.t > start {
+            let t0 = enter.max(start);
+            let t1 = exit.t.min(t_max);
+            return (t0 < t1).then_some((t0, t1));
+        }
+        t = exit.t + 0.0001;
+    }
+    None
+}
+
+// Heterogeneous medium: a voxel density grid stretched over `bounds`.
+// Free paths are sampled with delta tracking and shadow rays are attenuated with
+// ratio tracking, both against the grid's maximum density as the majorant.
+pub struct GridMedium {
+    grid: Rc<VoxelGrid>,
+    bounds: Aabb,
+    density_scale: f64,
+    phase_function: Rc<dyn Material>,
+}
+
+impl GridMedium {
+    // `emission` is multiplied by the grid's emission channel, if it has one.
+    pub fn new(
+        grid: Rc<VoxelGrid>,
+        bounds: Aabb,
+        density_scale: f64,
+        albedo: Color,
+        emission: Color,
+    ) -> Self {
+        GridMedium {
+            phase_function: Rc::new(GridPhase {
+                grid: Rc::clone(&grid),
+                bounds,
+                albedo,
+                emission,
+            }),
+            grid,
+            bounds,
+            density_scale,
+        }
+    }
+
+    fn majorant(&self) -> f64 {
+        self.grid.max_density() * self.density_scale
+    }
+
+    fn density(&self, p: &Vector3<f64>) -> f64 {
+        self.grid.density(&grid_coordinates(&self.bounds, p)) * self.density_scale
+    }
+
+    // Next tentative collision along the ray, sampled against the majorant.
+    fn step(&self, t: f64, majorant_per_t: f64, rng: &mut RenderRng) -> f64 {
+        t - (1. - rng.gen::<f64>()).ln() / majorant_per_t
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        let (t0, t1) = self.bounds.interval(ray, t_min.max(0.), t_max)?;
+        let majorant = self.majorant();
+        if majorant <= 0. {
+            return None;
+        }
+        let majorant_per_t = majorant * ray.direction.norm();
+
+        // delta tracking
+        let mut t = t0;
+        loop {
+            t = self.step(t, majorant_per_t, rng);
+            if t >= t1 {
+                return None;
+            }
+            if rng.gen::<f64>() * majorant < self.density(&ray.at(t)) {
+                let mut hit_record = HitRecord::new(
+                    ray,
+                    t,
+                    &Vector3::new(1., 0., 0.),
+                    (0., 0.),
+                    Rc::clone(&self.phase_function),
+                );
+                hit_record.front_face = true;
+                return Some(hit_record);
+            }
+        }
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.phase_function));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> f64 {
+        let (t0, t1) = match self.bounds.interval(ray, t_min.max(0.), t_max) {
+            Some(interval) => interval,
+            None => return 1.,
+        };
+        let majorant = self.majorant();
+        if majorant <= 0. {
+            return 1.;
+        }
+        let majorant_per_t = majorant * ray.direction.norm();
+
+        // ratio tracking
+        let mut transmittance = 1.;
+        let mut t = t0;
+        loop {
+            t = self.step(t, majorant_per_t, rng);
+            if t >= t1 {
+                return transmittance;
+            }
+            transmittance *= 1. - self.density(&ray.at(t)) / majorant;
+            // Russian roulette once hardly any light gets through
+            if transmittance < 0.1 {
+                if rng.gen::<f64>() < 0.5 {
+                    return 0.;
+                }
+                transmittance *= 2.;
+            }
+        }
+    }
+}
+
+// Maps p inside `bounds` to [0, 1]^3.
+fn grid_coordinates(bounds: &Aabb, p: &Vector3<f64>) -> Vector3<f64> {
+    (p - bounds.min).component_div(&(bounds.max - bounds.min))
+}
+
+// Isotropic phase function which also emits light where the grid says so.
+struct GridPhase {
+    grid: Rc<VoxelGrid>,
+    bounds: Aabb,
+    albedo: Color,
+    emission: Color,
+}
+
+impl Material for GridPhase {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut RenderRng,
+    ) -> Option<(Ray, Color)> {
+        let scattered = Ray::new(hit_record.p, random_unit_vector(rng), ray_in.time);
+        Some((scattered, self.albedo))
+    }
+
+    fn eval(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> Color {
+        self.albedo / (4. * PI)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        if !self.grid.has_emission() {
+            return Color::new(0., 0., 0.);
+        }
+        self.emission
+            * self
+                .grid
+                .emission(&grid_coordinates(&self.bounds, &hit_record.p))
+    }
+
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        self.albedo
+    }
+}
@@ -0,0 +1,85 @@
+use nalgebra::Vector3;
+use rand::seq::SliceRandom;
+
+use crate::utils::*;
+
+const POINT_COUNT: usize = 256;
+
+// Perlin noise with random gradient vectors.
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#perlinnoise
+pub struct Perlin {
+    random_vectors: Vec<Vector3<f64>>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut RenderRng) -> Self {
+        let random_vectors = (0..POINT_COUNT)
+            .map(|_| {
+                Vector3::new(
+                    random_f64(rng, -1., 1.),
+                    random_f64(rng, -1., 1.),
+                    random_f64(rng, -1., 1.),
+                )
+                .normalize()
+            })
+            .collect();
+        Perlin {
+            random_vectors,
+            perm_x: Self::generate_perm(rng),
+            perm_y: Self::generate_perm(rng),
+            perm_z: Self::generate_perm(rng),
+        }
+    }
+
+    fn generate_perm(rng: &mut RenderRng) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        p.shuffle(rng);
+        p
+    }
+
+    // Smooth noise in about [-1, 1]
+    pub fn noise(&self, p: &Vector3<f64>) -> f64 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // Hermite cubic to round off the interpolation
+        let uu = u * u * (3. - 2. * u);
+        let vv = v * v * (3. - 2. * v);
+        let ww = w * w * (3. - 2. * w);
+
+        let mut accum = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.random_vectors[self.perm_x[((i + di) & 255) as usize]
+                        ^ self.perm_y[((j + dj) & 255) as usize]
+                        ^ self.perm_z[((k + dk) & 255) as usize]];
+                    let weight = Vector3::new(u - di as f64, v - dj as f64, w - dk as f64);
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    accum += (fi * uu + (1. - fi) * (1. - uu))
+                        * (fj * vv + (1. - fj) * (1. - vv))
+                        * (fk * ww + (1. - fk) * (1. - ww))
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+        accum
+    }
+
+    // Fractal sum of `depth` octaves of noise
+    pub fn fbm(&self, p: &Vector3<f64>, depth: u32) -> f64 {
+        let mut accum = 0.;
+        let mut temp_p = *p;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p *= 2.;
+        }
+        accum
+    }
+}
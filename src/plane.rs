@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::*;
+
+// Distance along the ray to the plane through `point` with the unit `normal`,
+// if the ray isn't parallel to it.
+fn hit_plane(ray: &Ray, point: &Vector3<f64>, normal: &Vector3<f64>) -> Option<f64> {
+    let denom = normal.dot(&ray.direction);
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+    Some((point - ray.origin).dot(normal) / denom)
+}
+
+// Infinite plane through `point`, facing `normal`.
+// u and v repeat every `uv_scale` units along the plane.
+pub struct Plane {
+    point: Vector3<f64>,
+    normal: Vector3<f64>,
+    // tangent vectors spanning the plane
+    tangent_u: Vector3<f64>,
+    tangent_v: Vector3<f64>,
+    uv_scale: f64,
+    material: Rc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        uv_scale: f64,
+        material: Rc<dyn Material>,
+    ) -> Self {
+        let normal = normal.normalize();
+        let (tangent_u, tangent_v) = orthonormal_basis(&normal);
+        Plane {
+            point,
+            normal,
+            tangent_u,
+            tangent_v,
+            uv_scale,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        let t = hit_plane(ray, &self.point, &self.normal)?;
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
+        let d = ray.at(t) - self.point;
+        let u = d.dot(&self.tangent_u) / self.uv_scale;
+        let v = d.dot(&self.tangent_v) / self.uv_scale;
+        Some(HitRecord::new(
+            ray,
+            t,
+            &self.normal,
+            (u - u.floor(), v - v.floor()),
+            Rc::clone(&self.material),
+        ))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+// Flat disk of `radius` around `center`, facing `normal`.
+// u is the angle around the center, v the distance from it.
+pub struct Disk {
+    center: Vector3<f64>,
+    normal: Vector3<f64>,
+    radius: f64,
+    tangent_u: Vector3<f64>,
+    tangent_v: Vector3<f64>,
+    material: Rc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(
+        center: Vector3<f64>,
+        normal: Vector3<f64>,
+        radius: f64,
+        material: Rc<dyn Material>,
+    ) -> Self {
+        let normal = normal.normalize();
+        let (tangent_u, tangent_v) = orthonormal_basis(&normal);
+        Disk {
+            center,
+            normal,
+            radius,
+            tangent_u,
+            tangent_v,
+            material,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        let t = hit_plane(ray, &self.center, &self.normal)?;
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
+        let d = ray.at(t) - self.center;
+        let r_squared = d.dot(&d);
+        if r_squared > self.radius * self.radius {
+            return None;
+        }
+        let phi = d.dot(&self.tangent_v).atan2(d.dot(&self.tangent_u)) + PI;
+        Some(HitRecord::new(
+            ray,
+            t,
+            &self.normal,
+            (phi / (2. * PI), r_squared.sqrt() / self.radius),
+            Rc::clone(&self.material),
+        ))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // extent of a disk along each axis is radius * sin(angle between axis and normal)
+        let n = self.normal;
+        let e = self.radius
+            * Vector3::new(
+                (1. - n.x * n.x).max(0.).sqrt(),
+                (1. - n.y * n.y).max(0.).sqrt(),
+                (1. - n.z * n.z).max(0.).sqrt(),
+            );
+        Some(Aabb::new(self.center - e, self.center + e).padded(0.0001))
+    }
+}
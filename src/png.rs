@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+// Truecolor PNG, 8 bits per channel. The image data goes into zlib in
+// stored blocks, without compression, which every decoder reads and which
+// is quick to write.
+// See https://www.w3.org/TR/png/ and https://www.rfc-editor.org/rfc/rfc1950
+pub fn write_png<W, F>(mut out: W, width: u32, height: u32, pixel: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(u32, u32) -> [u8; 3],
+{
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits, RGB, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header)?;
+
+    // every row starts with its filter, 0 for none
+    let mut rows = Vec::with_capacity((height * (1 + 3 * width)) as usize);
+    for y in 0..height {
+        rows.push(0);
+        for x in 0..width {
+            rows.extend_from_slice(&pixel(x, y));
+        }
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&rows))?;
+    write_chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(crc32(!0, kind), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 65535;
+    let mut out = Vec::with_capacity(data.len() + data.len() / BLOCK * 5 + 11);
+    // deflate with a 32K window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+    let blocks = data.len().div_ceil(BLOCK).max(1);
+    for i in 0..blocks {
+        let block = &data[i * BLOCK..((i + 1) * BLOCK).min(data.len())];
+        let last = (i + 1 == blocks) as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// CRC-32 of PNG chunks, continuing from `crc`
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1, 0);
+    // 5552 bytes are the most that can't overflow b before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
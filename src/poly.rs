@@ -0,0 +1,220 @@
+// Real roots of polynomials up to degree 4, for the analytic shapes.
+// Ported from Jochen Schwarze, "Cubic and Quartic Roots", Graphics Gems I.
+// Coefficients are given from the highest degree down; roots aren't sorted.
+
+use std::f64::consts::PI;
+
+const EPS: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPS
+}
+
+// c[0] x^2 + c[1] x + c[2] = 0
+pub fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    if is_zero(c[0]) {
+        if is_zero(c[1]) {
+            return Vec::new();
+        }
+        return vec![-c[2] / c[1]];
+    }
+    // normal form: x^2 + px + q = 0
+    let p = c[1] / (2. * c[0]);
+    let q = c[2] / c[0];
+    let d = p * p - q;
+    if is_zero(d) {
+        vec![-p]
+    } else if d < 0. {
+        Vec::new()
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![sqrt_d - p, -sqrt_d - p]
+    }
+}
+
+// c[0] x^3 + c[1] x^2 + c[2] x + c[3] = 0
+pub fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    if is_zero(c[0]) {
+        return solve_quadratic([c[1], c[2], c[3]]);
+    }
+    // normal form: x^3 + Ax^2 + Bx + C = 0
+    let a = c[1] / c[0];
+    let b = c[2] / c[0];
+    let cc = c[3] / c[0];
+
+    // substitute x = y - A/3 to eliminate the quadric term: y^3 + 3py + 2q = 0
+    let sq_a = a * a;
+    let p = 1. / 3. * (-1. / 3. * sq_a + b);
+    let q = 1. / 2. * (2. / 27. * a * sq_a - 1. / 3. * a * b + cc);
+
+    // Cardano's formula
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    let mut roots = if is_zero(d) {
+        if is_zero(q) {
+            // one triple solution
+            vec![0.]
+        } else {
+            // one single and one double solution
+            let u = (-q).cbrt();
+            vec![2. * u, -u]
+        }
+    } else if d < 0. {
+        // casus irreducibilis: three real solutions
+        let phi = 1. / 3. * (-q / (-cb_p).sqrt()).acos();
+        let t = 2. * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + PI / 3.).cos(),
+            -t * (phi - PI / 3.).cos(),
+        ]
+    } else {
+        // one real solution
+        let sqrt_d = d.sqrt();
+        let u = (sqrt_d - q).cbrt();
+        let v = -(sqrt_d + q).cbrt();
+        vec![u + v]
+    };
+
+    // resubstitute
+    let sub = 1. / 3. * a;
+    for root in roots.iter_mut() {
+        *root -= sub;
+    }
+    roots
+}
+
+// c[0] x^4 + c[1] x^3 + c[2] x^2 + c[3] x + c[4] = 0
+// Each root is polished with a few Newton steps, which removes most of the
+// error Ferrari's method picks up when the coefficients differ a lot in scale.
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    if is_zero(c[0]) {
+        return solve_cubic([c[1], c[2], c[3], c[4]]);
+    }
+    // normal form: x^4 + Ax^3 + Bx^2 + Cx + D = 0
+    let a = c[1] / c[0];
+    let b = c[2] / c[0];
+    let cc = c[3] / c[0];
+    let d = c[4] / c[0];
+
+    // substitute x = y - A/4 to eliminate the cubic term: y^4 + py^2 + qy + r = 0
+    let sq_a = a * a;
+    let p = -3. / 8. * sq_a + b;
+    let q = 1. / 8. * sq_a * a - 1. / 2. * a * b + cc;
+    let r = -3. / 256. * sq_a * sq_a + 1. / 16. * sq_a * b - 1. / 4. * a * cc + d;
+
+    let mut roots = if is_zero(r) {
+        // no absolute term: y(y^3 + py + q) = 0
+        let mut roots = solve_cubic([1., 0., p, q]);
+        roots.push(0.);
+        roots
+    } else {
+        // solve the resolvent cubic...
+        let z = solve_cubic([1., -1. / 2. * p, -r, 1. / 2. * r * p - 1. / 8. * q * q])[0];
+
+        // ...and take the one real solution to build two quadric equations
+        let mut u = z * z - r;
+        let mut v = 2. * z - p;
+        if is_zero(u) {
+            u = 0.;
+        } else if u > 0. {
+            u = u.sqrt();
+        } else {
+            return Vec::new();
+        }
+        if is_zero(v) {
+            v = 0.;
+        } else if v > 0. {
+            v = v.sqrt();
+        } else {
+            return Vec::new();
+        }
+
+        let mut roots = solve_quadratic([1., if q < 0. { -v } else { v }, z - u]);
+        roots.extend(solve_quadratic([1., if q < 0. { v } else { -v }, z + u]));
+        roots
+    };
+
+    // resubstitute and polish
+    let sub = 1. / 4. * a;
+    for root in roots.iter_mut() {
+        *root -= sub;
+        for _ in 0..2 {
+            let x = *root;
+            let f = (((c[0] * x + c[1]) * x + c[2]) * x + c[3]) * x + c[4];
+            let df = ((4. * c[0] * x + 3. * c[1]) * x + 2. * c[2]) * x + c[3];
+            if df != 0. {
+                *root = x - f / df;
+            }
+        }
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The coefficients of the monic polynomial with these roots
+    fn from_roots(roots: [f64; 4]) -> [f64; 5] {
+        let mut c = [1., 0., 0., 0., 0.];
+        for (degree, root) in roots.into_iter().enumerate() {
+            for i in (1..=degree + 1).rev() {
+                c[i] -= root * c[i - 1];
+            }
+        }
+        c
+    }
+
+    fn assert_roots(found: Vec<f64>, expected: &[f64]) {
+        let mut found = found;
+        found.sort_by(f64::total_cmp);
+        found.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+        assert_eq!(found.len(), expected.len(), "roots {:?}", found);
+        for (found, expected) in found.iter().zip(expected) {
+            assert!(
+                (found - expected).abs() < 1e-9,
+                "{} isn't {}",
+                found,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn quartic_with_four_roots() {
+        assert_eq!(from_roots([1., 2., 3., 4.]), [1., -10., 35., -50., 24.]);
+        assert_roots(
+            solve_quartic(from_roots([1., 2., 3., 4.])),
+            &[1., 2., 3., 4.],
+        );
+        assert_roots(
+            solve_quartic(from_roots([-7., -0.25, 0.5, 3.])),
+            &[-7., -0.25, 0.5, 3.],
+        );
+    }
+
+    #[test]
+    fn quartic_with_fewer_roots() {
+        // (x^2 - 1)(x^2 + 1)
+        assert_roots(solve_quartic([1., 0., 0., 0., -1.]), &[-1., 1.]);
+        // (x - 2)^2 (x^2 + 1)
+        assert_roots(solve_quartic([1., -4., 5., -4., 4.]), &[2.]);
+        assert_roots(solve_quartic([1., 0., 0., 0., 1.]), &[]);
+    }
+
+    #[test]
+    fn quartic_scaled_and_biquadratic() {
+        // 2 (x^2 - 4)(x^2 - 9)
+        assert_roots(solve_quartic([2., 0., -26., 0., 72.]), &[-3., -2., 2., 3.]);
+    }
+
+    #[test]
+    fn lower_degrees() {
+        assert_roots(solve_quadratic([1., -3., 2.]), &[1., 2.]);
+        assert_roots(solve_quadratic([1., 0., 1.]), &[]);
+        // (x + 1)(x - 2)(x - 5)
+        assert_roots(solve_cubic([1., -6., 3., 10.]), &[-1., 2., 5.]);
+    }
+}
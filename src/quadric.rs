@@ -0,0 +1,364 @@
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::aabb::Aabb;
+use crate::hit::{sphere_uv, HitRecord, Hittable};
+use crate::material::Material;
+use crate::poly::{solve_quadratic, solve_quartic};
+use crate::ray::Ray;
+use crate::utils::RenderRng;
+
+// Analytic shapes standing on the XZ plane around the Y axis.
+// Use Instance to tilt them.
+
+// (u, v) around the Y axis: u is the angle, v the height in [0, 1]
+fn cylindrical_uv(p: &Vector3<f64>, height: f64) -> (f64, f64) {
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2. * PI), p.y / height)
+}
+
+// Nearest root in [t_min, t_max] passing `accept`, if any
+fn nearest_root<F>(roots: Vec<f64>, t_min: f64, t_max: f64, accept: F) -> Option<f64>
+where
+    F: Fn(f64) -> bool,
+{
+    roots
+        .into_iter()
+        .filter(|t| (t_min..=t_max).contains(t) && accept(*t))
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+// Hit on a cap disk at local height y with the given outward normal direction (+1/-1)
+#[allow(clippy::too_many_arguments)]
+fn hit_cap(
+    ray: &Ray,
+    local_origin: &Vector3<f64>,
+    y: f64,
+    radius: f64,
+    facing: f64,
+    t_min: f64,
+    t_max: f64,
+    material: &Rc<dyn Material>,
+) -> Option<HitRecord> {
+    if ray.direction.y == 0. {
+        return None;
+    }
+    let t = (y - local_origin.y) / ray.direction.y;
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+    let p = local_origin + t * ray.direction;
+    let r_squared = p.x * p.x + p.z * p.z;
+    if r_squared > radius * radius {
+        return None;
+    }
+    let phi = (-p.z).atan2(p.x) + PI;
+    Some(HitRecord::new(
+        ray,
+        t,
+        &Vector3::new(0., facing, 0.),
+        (phi / (2. * PI), r_squared.sqrt() / radius),
+        Rc::clone(material),
+    ))
+}
+
+fn closest(a: Option<HitRecord>, b: Option<HitRecord>) -> Option<HitRecord> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.t <= b.t { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// Cylinder of `radius` from `center` (the middle of the bottom) up to `height`.
+pub struct Cylinder {
+    pub center: Vector3<f64>,
+    pub radius: f64,
+    pub height: f64,
+    // closed with disks at both ends
+    pub capped: bool,
+    pub material: Rc<dyn Material>,
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+
+        // side: x^2 + z^2 = r^2, 0 <= y <= height
+        let roots = solve_quadratic([
+            d.x * d.x + d.z * d.z,
+            2. * (o.x * d.x + o.z * d.z),
+            o.x * o.x + o.z * o.z - self.radius * self.radius,
+        ]);
+        let side = nearest_root(roots, t_min, t_max, |t| {
+            let y = o.y + t * d.y;
+            (0. ..=self.height).contains(&y)
+        })
+        .map(|t| {
+            let p = o + t * d;
+            let outward_normal = Vector3::new(p.x, 0., p.z) / self.radius;
+            HitRecord::new(
+                ray,
+                t,
+                &outward_normal,
+                cylindrical_uv(&p, self.height),
+                Rc::clone(&self.material),
+            )
+        });
+        if !self.capped {
+            return side;
+        }
+
+        let bottom = hit_cap(ray, &o, 0., self.radius, -1., t_min, t_max, &self.material);
+        let top = hit_cap(
+            ray,
+            &o,
+            self.height,
+            self.radius,
+            1.,
+            t_min,
+            t_max,
+            &self.material,
+        );
+        closest(closest(side, bottom), top)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius;
+        Some(Aabb::new(
+            self.center - Vector3::new(r, 0., r),
+            self.center + Vector3::new(r, self.height, r),
+        ))
+    }
+}
+
+// Cone with a base of `radius` at `center`, narrowing to a point `height` above it.
+pub struct Cone {
+    pub center: Vector3<f64>,
+    pub radius: f64,
+    pub height: f64,
+    // closed with a disk at the base
+    pub capped: bool,
+    pub material: Rc<dyn Material>,
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+
+        // side: x^2 + z^2 = (k (height - y))^2, 0 <= y <= height
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let h = self.height - o.y;
+        let roots = solve_quadratic([
+            d.x * d.x + d.z * d.z - k2 * d.y * d.y,
+            2. * (o.x * d.x + o.z * d.z + k2 * h * d.y),
+            o.x * o.x + o.z * o.z - k2 * h * h,
+        ]);
+        let side = nearest_root(roots, t_min, t_max, |t| {
+            let y = o.y + t * d.y;
+            (0. ..=self.height).contains(&y)
+        })
+        .map(|t| {
+            let p = o + t * d;
+            let r = (p.x * p.x + p.z * p.z).sqrt();
+            // slope of the side is radius / height, so the normal leans up by that much
+            let outward_normal = if r > 0. {
+                Vector3::new(p.x / r, k, p.z / r).normalize()
+            } else {
+                Vector3::new(0., 1., 0.)
+            };
+            HitRecord::new(
+                ray,
+                t,
+                &outward_normal,
+                cylindrical_uv(&p, self.height),
+                Rc::clone(&self.material),
+            )
+        });
+        if !self.capped {
+            return side;
+        }
+
+        let base = hit_cap(ray, &o, 0., self.radius, -1., t_min, t_max, &self.material);
+        closest(side, base)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius;
+        Some(Aabb::new(
+            self.center - Vector3::new(r, 0., r),
+            self.center + Vector3::new(r, self.height, r),
+        ))
+    }
+}
+
+// Torus around `center` in the XZ plane: a tube of `minor_radius` swept
+// along a circle of `major_radius`.
+pub struct Torus {
+    pub center: Vector3<f64>,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Rc<dyn Material>,
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        // The quartic loses precision when the ray starts far away, so move the
+        // origin up to the bounding box first and solve with a unit direction.
+        let (t_enter, _) = self.bounding_box()?.interval(ray, t_min, t_max)?;
+        let length = ray.direction.norm();
+        let d = ray.direction / length;
+        let o = ray.at(t_enter) - self.center;
+
+        let r2 = self.major_radius * self.major_radius;
+        let e = o.dot(&o) - r2 - self.minor_radius * self.minor_radius;
+        let f = o.dot(&d);
+        let roots = solve_quartic([
+            1.,
+            4. * f,
+            2. * e + 4. * f * f + 4. * r2 * d.y * d.y,
+            4. * f * e + 8. * r2 * o.y * d.y,
+            e * e - 4. * r2 * (self.minor_radius * self.minor_radius - o.y * o.y),
+        ]);
+        let t = nearest_root(
+            roots.into_iter().map(|s| t_enter + s / length).collect(),
+            t_min,
+            t_max,
+            |_| true,
+        )?;
+
+        let p = ray.at(t) - self.center;
+        let ring = (p.x * p.x + p.z * p.z).sqrt();
+        // direction from the nearest point on the center circle
+        let outward_normal = Vector3::new(
+            p.x * (1. - self.major_radius / ring),
+            p.y,
+            p.z * (1. - self.major_radius / ring),
+        )
+        .normalize();
+        let u = ((-p.z).atan2(p.x) + PI) / (2. * PI);
+        let v = (p.y.atan2(ring - self.major_radius) + PI) / (2. * PI);
+        Some(HitRecord::new(
+            ray,
+            t,
+            &outward_normal,
+            (u, v),
+            Rc::clone(&self.material),
+        ))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.major_radius + self.minor_radius;
+        let e = Vector3::new(r, self.minor_radius, r);
+        Some(Aabb::new(self.center - e, self.center + e))
+    }
+}
+
+// General quadric surface p^T Q p = 0 in homogeneous coordinates, i.e.
+//   A x^2 + B y^2 + C z^2 + 2D xy + 2E xz + 2F yz + 2G x + 2H y + 2I z + J = 0
+// with Q = [A D E G; D B F H; E F C I; G H I J].
+// Most quadrics are infinite, so only the part inside `bounds` is rendered.
+pub struct Quadric {
+    q: Matrix4<f64>,
+    bounds: Aabb,
+    material: Rc<dyn Material>,
+}
+
+impl Quadric {
+    pub fn new(
+        [a, b, c, d, e, f, g, h, i, j]: [f64; 10],
+        bounds: Aabb,
+        material: Rc<dyn Material>,
+    ) -> Self {
+        Quadric {
+            q: Matrix4::new(a, d, e, g, d, b, f, h, e, f, c, i, g, h, i, j),
+            bounds,
+            material,
+        }
+    }
+
+    // Ellipsoid with the given semi-axes around `center`
+    pub fn ellipsoid(
+        center: Vector3<f64>,
+        radii: Vector3<f64>,
+        material: Rc<dyn Material>,
+    ) -> Self {
+        let (a, b, c) = (
+            1. / (radii.x * radii.x),
+            1. / (radii.y * radii.y),
+            1. / (radii.z * radii.z),
+        );
+        Quadric::new(
+            [
+                a,
+                b,
+                c,
+                0.,
+                0.,
+                0.,
+                -a * center.x,
+                -b * center.y,
+                -c * center.z,
+                a * center.x * center.x + b * center.y * center.y + c * center.z * center.z - 1.,
+            ],
+            Aabb::new(center - radii, center + radii).padded(0.0001),
+            material,
+        )
+    }
+
+    fn gradient(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        let g = self.q * Vector4::new(p.x, p.y, p.z, 1.);
+        Vector3::new(g.x, g.y, g.z)
+    }
+}
+
+impl Hittable for Quadric {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        let (t0, t1) = self.bounds.interval(ray, t_min, t_max)?;
+        let o = Vector4::new(ray.origin.x, ray.origin.y, ray.origin.z, 1.);
+        let d = Vector4::new(ray.direction.x, ray.direction.y, ray.direction.z, 0.);
+        let qd = self.q * d;
+        let roots = solve_quadratic([d.dot(&qd), 2. * o.dot(&qd), o.dot(&(self.q * o))]);
+        let t = nearest_root(roots, t0, t1, |_| true)?;
+
+        let p = ray.at(t);
+        // Which side is "outside" depends on the sign convention of Q;
+        // the gradient points towards p^T Q p > 0.
+        let outward_normal = self.gradient(&p).normalize();
+        // spherical mapping around the middle of the bounds
+        let c = (self.bounds.min + self.bounds.max) / 2.;
+        let dir = (p - c).normalize();
+        Some(HitRecord::new(
+            ray,
+            t,
+            &outward_normal,
+            sphere_uv(&dir),
+            Rc::clone(&self.material),
+        ))
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
@@ -3,11 +3,17 @@ use nalgebra::Vector3;
 pub struct Ray {
     pub origin: Vector3<f64>,
     pub direction: Vector3<f64>,
+    // moment within the camera shutter the ray was sent at
+    pub time: f64,
 }
 
 impl Ray {
-    pub fn new(origin: Vector3<f64>, direction: Vector3<f64>) -> Self {
-        Ray { origin, direction }
+    pub fn new(origin: Vector3<f64>, direction: Vector3<f64>, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub fn at(&self, t: f64) -> Vector3<f64> {
@@ -0,0 +1,232 @@
+use std::rc::Rc;
+
+use nalgebra::Vector3;
+
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable, HittableList};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::RenderRng;
+
+// Axis-aligned rectangles
+// See https://raytracing.github.io/books/RayTracingTheNextWeek.html#rectanglesandlights/creatingrectangleobjects
+
+// Intersects the plane `axis k == k` with a ray and checks that the hit point
+// lies in [a0, a1] x [b0, b1] on the other two axes.
+fn hit_rect(
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    (a_axis, b_axis, k_axis): (usize, usize, usize),
+    (a0, a1, b0, b1, k): (f64, f64, f64, f64, f64),
+    material: &Rc<dyn Material>,
+) -> Option<HitRecord> {
+    let t = (k - ray.origin[k_axis]) / ray.direction[k_axis];
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+    let a = ray.origin[a_axis] + t * ray.direction[a_axis];
+    let b = ray.origin[b_axis] + t * ray.direction[b_axis];
+    if a < a0 || a > a1 || b < b0 || b > b1 {
+        return None;
+    }
+    let mut outward_normal = Vector3::zeros();
+    outward_normal[k_axis] = 1.;
+    Some(HitRecord::new(
+        ray,
+        t,
+        &outward_normal,
+        ((a - a0) / (a1 - a0), (b - b0) / (b1 - b0)),
+        Rc::clone(material),
+    ))
+}
+
+fn rect_box(
+    (a_axis, b_axis, k_axis): (usize, usize, usize),
+    (a0, a1, b0, b1, k): (f64, f64, f64, f64, f64),
+) -> Aabb {
+    let mut min = Vector3::zeros();
+    let mut max = Vector3::zeros();
+    min[a_axis] = a0;
+    max[a_axis] = a1;
+    min[b_axis] = b0;
+    max[b_axis] = b1;
+    min[k_axis] = k;
+    max[k_axis] = k;
+    // The bounding box must have non-zero width in each dimension.
+    Aabb::new(min, max).padded(0.0001)
+}
+
+// Rectangle [x0, x1] x [y0, y1] on the plane z = k, facing +Z
+pub struct XYRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub y0: f64,
+    pub y1: f64,
+    pub k: f64,
+    pub material: Rc<dyn Material>,
+}
+
+impl Hittable for XYRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        hit_rect(
+            ray,
+            t_min,
+            t_max,
+            (0, 1, 2),
+            (self.x0, self.x1, self.y0, self.y1, self.k),
+            &self.material,
+        )
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(rect_box(
+            (0, 1, 2),
+            (self.x0, self.x1, self.y0, self.y1, self.k),
+        ))
+    }
+}
+
+// Rectangle [x0, x1] x [z0, z1] on the plane y = k, facing +Y
+pub struct XZRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub material: Rc<dyn Material>,
+}
+
+impl Hittable for XZRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        hit_rect(
+            ray,
+            t_min,
+            t_max,
+            (0, 2, 1),
+            (self.x0, self.x1, self.z0, self.z1, self.k),
+            &self.material,
+        )
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(rect_box(
+            (0, 2, 1),
+            (self.x0, self.x1, self.z0, self.z1, self.k),
+        ))
+    }
+}
+
+// Rectangle [y0, y1] x [z0, z1] on the plane x = k, facing +X
+pub struct YZRect {
+    pub y0: f64,
+    pub y1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub material: Rc<dyn Material>,
+}
+
+impl Hittable for YZRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        hit_rect(
+            ray,
+            t_min,
+            t_max,
+            (1, 2, 0),
+            (self.y0, self.y1, self.z0, self.z1, self.k),
+            &self.material,
+        )
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(rect_box(
+            (1, 2, 0),
+            (self.y0, self.y1, self.z0, self.z1, self.k),
+        ))
+    }
+}
+
+// Six-sided axis-aligned box between the corners p0 and p1.
+// (Called `box` in the book, which is taken by `std::boxed::Box` in Rust.)
+pub struct Cuboid {
+    min: Vector3<f64>,
+    max: Vector3<f64>,
+    sides: HittableList<Box<dyn Hittable>>,
+}
+
+impl Cuboid {
+    pub fn new(p0: Vector3<f64>, p1: Vector3<f64>, material: Rc<dyn Material>) -> Self {
+        let min = p0.inf(&p1);
+        let max = p0.sup(&p1);
+        let mut sides: HittableList<Box<dyn Hittable>> = HittableList::new();
+
+        for k in [min.z, max.z] {
+            sides.add(Box::new(XYRect {
+                x0: min.x,
+                x1: max.x,
+                y0: min.y,
+                y1: max.y,
+                k,
+                material: Rc::clone(&material),
+            }));
+        }
+        for k in [min.y, max.y] {
+            sides.add(Box::new(XZRect {
+                x0: min.x,
+                x1: max.x,
+                z0: min.z,
+                z1: max.z,
+                k,
+                material: Rc::clone(&material),
+            }));
+        }
+        for k in [min.x, max.x] {
+            sides.add(Box::new(YZRect {
+                y0: min.y,
+                y1: max.y,
+                z0: min.z,
+                z1: max.z,
+                k,
+                material: Rc::clone(&material),
+            }));
+        }
+
+        Cuboid { min, max, sides }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut RenderRng) -> Option<HitRecord> {
+        let mut hit_record = self.sides.hit(ray, t_min, t_max, rng)?;
+        // The rects all face the positive axis, so flip the normals on the
+        // min sides to make every face point out of the box.
+        let center = (self.min + self.max) / 2.;
+        let mut outward_normal = hit_record.outward_normal();
+        if outward_normal.dot(&(hit_record.p - center)) < 0. {
+            outward_normal = -outward_normal;
+        }
+        hit_record.set_face_normal(ray, &outward_normal);
+        Some(hit_record)
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        self.sides.materials(materials);
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
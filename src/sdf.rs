@@ -0,0 +1,549 @@
+use std::rc::Rc;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::aabb::Aabb;
+use crate::hit::{sphere_uv, HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::RenderRng;
+
+// Signed distance field: negative inside, positive outside.
+// See https://iquilezles.org/articles/distfunctions/
+pub trait Sdf {
+    // Distance from p to the surface. Must never overestimate the true
+    // distance, or sphere tracing will step through the surface.
+    fn distance(&self, p: &Vector3<f64>) -> f64;
+
+    // Infinite along the axes the field repeats along, None if it has no
+    // bounds at all.
+    fn bounding_box(&self) -> Option<Aabb>;
+}
+
+impl<T> Sdf for Box<T>
+where
+    T: Sdf + ?Sized,
+{
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        (**self).distance(p)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+}
+
+fn centered_box(half: Vector3<f64>) -> Aabb {
+    Aabb::new(-half, half)
+}
+
+//
+// Primitives, all centered at the origin
+//
+
+pub struct SdfSphere {
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        p.norm() - self.radius
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(centered_box(Vector3::repeat(self.radius)))
+    }
+}
+
+pub struct SdfBox {
+    pub half_extents: Vector3<f64>,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let q = p.abs() - self.half_extents;
+        q.sup(&Vector3::zeros()).norm() + q.max().min(0.)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(centered_box(self.half_extents))
+    }
+}
+
+// Box with its edges rounded off by `radius`, within the same half extents.
+pub struct RoundBox {
+    pub half_extents: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl Sdf for RoundBox {
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let q = p.abs() - self.half_extents + Vector3::repeat(self.radius);
+        q.sup(&Vector3::zeros()).norm() + q.max().min(0.) - self.radius
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(centered_box(self.half_extents))
+    }
+}
+
+// Torus lying in the XZ plane
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl Sdf for Torus {
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let q = Vector2::new(Vector2::new(p.x, p.z).norm() - self.major_radius, p.y);
+        q.norm() - self.minor_radius
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.major_radius + self.minor_radius;
+        Some(centered_box(Vector3::new(r, self.minor_radius, r)))
+    }
+}
+
+// Segment from a to b, thickened by radius
+pub struct Capsule {
+    pub a: Vector3<f64>,
+    pub b: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl Sdf for Capsule {
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0., 1.);
+        (pa - ba * h).norm() - self.radius
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vector3::repeat(self.radius);
+        Some(Aabb::new(self.a.inf(&self.b) - r, self.a.sup(&self.b) + r))
+    }
+}
+
+// Distance estimator of the Mandelbulb fractal, about 1.2 units across.
+// See http://blog.hvidtfeldts.net/index.php/2011/09/distance-estimated-3d-fractals-v-the-mandelbulb-different-de-approximations/
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: u32,
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let mut z = *p;
+        let mut dr = 1.;
+        let mut r = 0.;
+        for _ in 0..self.iterations {
+            r = z.norm();
+            if r > 2. {
+                break;
+            }
+            // to polar coordinates, raise to the power, and back
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+            let zr = r.powf(self.power);
+            z =
+                zr * Vector3::new(
+                    theta.sin() * phi.cos(),
+                    phi.sin() * theta.sin(),
+                    theta.cos(),
+                ) + p;
+        }
+        0.5 * r.ln() * r / dr
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(centered_box(Vector3::repeat(1.3)))
+    }
+}
+
+//
+// Operators
+//
+
+pub struct Translate<A>
+where
+    A: Sdf,
+{
+    pub sdf: A,
+    pub offset: Vector3<f64>,
+}
+
+impl<A> Sdf for Translate<A>
+where
+    A: Sdf,
+{
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        self.sdf.distance(&(p - self.offset))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let aabb = self.sdf.bounding_box()?;
+        Some(Aabb::new(aabb.min + self.offset, aabb.max + self.offset))
+    }
+}
+
+// Union blending the two shapes together within distance k
+pub struct SmoothUnion<A, B>
+where
+    A: Sdf,
+    B: Sdf,
+{
+    pub a: A,
+    pub b: B,
+    pub k: f64,
+}
+
+impl<A, B> Sdf for SmoothUnion<A, B>
+where
+    A: Sdf,
+    B: Sdf,
+{
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        if self.k <= 0. {
+            return d1.min(d2);
+        }
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0., 1.);
+        d2 + (d1 - d2) * h - self.k * h * (1. - h)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let aabb = self.a.bounding_box()?.surrounding(&self.b.bounding_box()?);
+        Some(aabb.padded(self.k.max(0.)))
+    }
+}
+
+// `a` with `b` carved out of it, with edges rounded by k (0 for sharp ones)
+pub struct Subtraction<A, B>
+where
+    A: Sdf,
+    B: Sdf,
+{
+    pub a: A,
+    pub b: B,
+    pub k: f64,
+}
+
+impl<A, B> Sdf for Subtraction<A, B>
+where
+    A: Sdf,
+    B: Sdf,
+{
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let d1 = self.a.distance(p);
+        let d2 = -self.b.distance(p);
+        if self.k <= 0. {
+            return d1.max(d2);
+        }
+        let h = (0.5 - 0.5 * (d1 - d2) / self.k).clamp(0., 1.);
+        d1 + (d2 - d1) * h + self.k * h * (1. - h)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.a.bounding_box()
+    }
+}
+
+// Repeats the shape every `period` units along each axis.
+// A zero component leaves that axis alone.
+pub struct Repetition<A>
+where
+    A: Sdf,
+{
+    pub sdf: A,
+    pub period: Vector3<f64>,
+}
+
+impl<A> Sdf for Repetition<A>
+where
+    A: Sdf,
+{
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let mut q = *p;
+        for i in 0..3 {
+            let c = self.period[i];
+            if c > 0. {
+                q[i] -= c * (q[i] / c + 0.5).floor();
+            }
+        }
+        self.sdf.distance(&q)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut aabb = self.sdf.bounding_box()?;
+        for i in 0..3 {
+            if self.period[i] > 0. {
+                aabb.min[i] = f64::NEG_INFINITY;
+                aabb.max[i] = f64::INFINITY;
+            }
+        }
+        Some(aabb)
+    }
+}
+
+// Twists the shape around the Y axis by `rate` radians per unit of height.
+// This bends space, so distances are only estimates: render it with a
+// step scale below 1 (see SdfObject::with_step_scale).
+pub struct Twist<A>
+where
+    A: Sdf,
+{
+    pub sdf: A,
+    pub rate: f64,
+}
+
+impl<A> Sdf for Twist<A>
+where
+    A: Sdf,
+{
+    fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let angle = self.rate * p.y;
+        let (c, s) = (angle.cos(), angle.sin());
+        let q = Vector3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z);
+        self.sdf.distance(&q)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // any rotation around Y stays within the radius of the farthest corner
+        let aabb = self.sdf.bounding_box()?;
+        let rx = aabb.min.x.abs().max(aabb.max.x.abs());
+        let rz = aabb.min.z.abs().max(aabb.max.z.abs());
+        let r = (rx * rx + rz * rz).sqrt();
+        Some(Aabb::new(
+            Vector3::new(-r, aabb.min.y, -r),
+            Vector3::new(r, aabb.max.y, r),
+        ))
+    }
+}
+
+//
+// Hittable
+//
+
+// Renders an SDF by sphere tracing.
+pub struct SdfObject<S>
+where
+    S: Sdf,
+{
+    sdf: S,
+    material: Rc<dyn Material>,
+    // fraction of the distance estimate to advance by each step
+    step_scale: f64,
+    max_steps: u32,
+    // how close to the surface counts as a hit
+    epsilon: f64,
+    // how far from the ray origin unbounded fields are marched, in world units
+    max_distance: f64,
+}
+
+impl<S> SdfObject<S>
+where
+    S: Sdf,
+{
+    pub fn new(sdf: S, material: Rc<dyn Material>) -> Self {
+        SdfObject {
+            sdf,
+            material,
+            step_scale: 1.,
+            max_steps: 256,
+            epsilon: 1e-4,
+            max_distance: 100.,
+        }
+    }
+
+    pub fn with_step_scale(mut self, step_scale: f64) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    // Normal from the gradient of the field, by central differences.
+    fn normal(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        let h = self.epsilon;
+        let mut gradient = Vector3::zeros();
+        for i in 0..3 {
+            let mut d = Vector3::zeros();
+            d[i] = h;
+            gradient[i] = self.sdf.distance(&(p + d)) - self.sdf.distance(&(p - d));
+        }
+        gradient.normalize()
+    }
+}
+
+impl<S> Hittable for SdfObject<S>
+where
+    S: Sdf,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut RenderRng) -> Option<HitRecord> {
+        // Only march the part of the ray inside the bounding box, and within
+        // max_distance of the origin when that's unbounded. CSG and media ask
+        // for hits from t = -INFINITY, where the march couldn't start.
+        let ray_length = ray.direction.norm();
+        let bounds = self.sdf.bounding_box();
+        let (mut t0, mut t1) = (t_min, t_max);
+        if !bounds.is_some_and(|aabb| is_finite(&aabb)) {
+            let reach = self.max_distance / ray_length;
+            t0 = t0.max(-reach);
+            t1 = t1.min(reach);
+        }
+        let (t0, t1) = match bounds {
+            Some(aabb) => aabb.padded(self.epsilon).interval(ray, t0, t1)?,
+            None if t0 < t1 => (t0, t1),
+            None => return None,
+        };
+
+        // March from outside or from inside, whichever side the ray starts on.
+        let sign = if self.sdf.distance(&ray.at(t0)) < 0. {
+            -1.
+        } else {
+            1.
+        };
+        let mut t = t0;
+        for _ in 0..self.max_steps {
+            let d = sign * self.sdf.distance(&ray.at(t));
+            if d < self.epsilon && t > t_min {
+                let p = ray.at(t);
+                let outward_normal = self.normal(&p);
+                return Some(HitRecord::new(
+                    ray,
+                    t,
+                    &outward_normal,
+                    sphere_uv(&outward_normal),
+                    Rc::clone(&self.material),
+                ));
+            }
+            t += d.max(self.epsilon) * self.step_scale / ray_length;
+            if t > t1 {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn materials(&self, materials: &mut Vec<Rc<dyn Material>>) {
+        materials.push(Rc::clone(&self.material));
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.sdf.bounding_box().filter(is_finite)
+    }
+}
+
+fn is_finite(aabb: &Aabb) -> bool {
+    aabb.min
+        .iter()
+        .chain(aabb.max.iter())
+        .all(|x| x.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::csg::Csg;
+    use crate::hit::Sphere;
+    use crate::material::Lambertian;
+    use crate::medium::ConstantMedium;
+    use crate::Color;
+
+    fn gray() -> Rc<dyn Material> {
+        Rc::new(Lambertian::new(Color::repeat(0.5)))
+    }
+
+    // Spheres of radius 1 every 4 units along every axis
+    fn spheres() -> SdfObject<Repetition<SdfSphere>> {
+        SdfObject::new(
+            Repetition {
+                sdf: SdfSphere { radius: 1. },
+                period: Vector3::repeat(4.),
+            },
+            gray(),
+        )
+    }
+
+    fn down_z(x: f64) -> Ray {
+        Ray::new(Vector3::new(x, 0., 7.), Vector3::new(0., 0., -1.), 0.)
+    }
+
+    #[test]
+    fn repetition_keeps_the_other_axes_bounded() {
+        let rows = Repetition {
+            sdf: SdfSphere { radius: 1. },
+            period: Vector3::new(4., 0., 0.),
+        };
+        let aabb = rows.bounding_box().unwrap();
+        assert_eq!((aabb.min.x, aabb.max.x), (f64::NEG_INFINITY, f64::INFINITY));
+        assert_eq!((aabb.min.y, aabb.max.y), (-1., 1.));
+        assert!(SdfObject::new(rows, gray()).bounding_box().is_none());
+    }
+
+    #[test]
+    fn unbounded_field_from_minus_infinity() {
+        let mut rng = RenderRng::seed_from_u64(0);
+        let hit = spheres().hit(&down_z(0.), f64::NEG_INFINITY, f64::INFINITY, &mut rng);
+        // the sphere behind the ray origin, within max_distance of it
+        assert!(hit.unwrap().t < 0.);
+        assert!(!spheres().spans(&down_z(0.), &mut rng).is_empty());
+        assert!(spheres().spans(&down_z(2.), &mut rng).is_empty());
+    }
+
+    #[test]
+    fn max_distance_in_world_units() {
+        let mut rng = RenderRng::seed_from_u64(0);
+        let hit = |direction_length: f64, rng: &mut RenderRng| {
+            let ray = Ray::new(
+                Vector3::new(0., 0., 7.),
+                Vector3::new(0., 0., -direction_length),
+                0.,
+            );
+            let hit = spheres().hit(&ray, 0.001, f64::INFINITY, rng).unwrap();
+            ray.at(hit.t)
+        };
+        assert!((hit(1., &mut rng) - Vector3::new(0., 0., 5.)).norm() < 1e-3);
+        assert!((hit(100., &mut rng) - Vector3::new(0., 0., 5.)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn csg_over_unbounded_field() {
+        let mut rng = RenderRng::seed_from_u64(0);
+        let ball = Sphere {
+            center: Vector3::zeros(),
+            radius: 2.,
+            material: gray(),
+        };
+        let both = Csg::intersection(spheres(), ball);
+        let hit = both
+            .hit(&down_z(0.), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!((hit.t - 6.).abs() < 1e-3);
+        assert!(both
+            .hit(&down_z(2.), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn medium_over_unbounded_field() {
+        let mut rng = RenderRng::seed_from_u64(0);
+        let fog = ConstantMedium::new(spheres(), 100., Color::repeat(0.5));
+        // through the spheres at z = 4 and 0, and between them
+        assert!(fog.transmittance(&down_z(0.), 0.001, 8., &mut rng) < 1e-6);
+        assert_eq!(fog.transmittance(&down_z(2.), 0.001, 8., &mut rng), 1.);
+        let hit = fog
+            .hit(&down_z(0.), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!(hit.t > 2. - 1e-3 && hit.t < 4.);
+    }
+}
@@ -0,0 +1,146 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::camera::{Camera, EquirectangularCamera, PerspectiveCamera};
+use crate::ray::Ray;
+use crate::utils::*;
+use crate::Color;
+
+// Where each eye goes in the final image
+#[derive(Clone, Copy)]
+pub enum StereoLayout {
+    // left eye on the left half
+    SideBySide,
+    // left eye on the top half
+    OverUnder,
+    // red-cyan: left eye in the red channel, right eye in green and blue
+    Anaglyph,
+}
+
+// How the two eyes agree on the plane that appears at screen depth
+#[derive(Clone, Copy)]
+pub enum Convergence {
+    // both eyes turn towards lookat; simple, but the images get
+    // vertical disparity towards their corners
+    ToeIn,
+    // both eyes look straight ahead and shift their image windows
+    // to meet at the distance of lookat
+    OffAxis,
+}
+
+// Renders a left and a right eye camera into one image.
+pub struct StereoCamera {
+    left: Box<dyn Camera>,
+    right: Box<dyn Camera>,
+    layout: StereoLayout,
+}
+
+impl StereoCamera {
+    pub fn new(left: Box<dyn Camera>, right: Box<dyn Camera>, layout: StereoLayout) -> Self {
+        StereoCamera {
+            left,
+            right,
+            layout,
+        }
+    }
+
+    // Pair of perspective eyes `interocular` apart, centered on lookfrom and
+    // focused on lookat. aspect_ratio is the one of the whole image, which
+    // the eyes share according to the layout.
+    #[allow(clippy::too_many_arguments)]
+    pub fn perspective(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        interocular: f64,
+        convergence: Convergence,
+        layout: StereoLayout,
+    ) -> Self {
+        let aspect_ratio = match layout {
+            StereoLayout::SideBySide => aspect_ratio / 2.,
+            StereoLayout::OverUnder => aspect_ratio * 2.,
+            StereoLayout::Anaglyph => aspect_ratio,
+        };
+        let distance = (lookat - lookfrom).norm();
+        let right = vup.cross(&(lookfrom - lookat)).normalize();
+        // width of the image window at unit distance
+        let viewport_width = 2. * (deg_to_rad(vfov) / 2.).tan() * aspect_ratio;
+
+        // side is -1 for the left eye and 1 for the right one
+        let eye = |side: f64| -> Box<dyn Camera> {
+            let offset = side * interocular / 2. * right;
+            match convergence {
+                Convergence::ToeIn => Box::new(PerspectiveCamera::new(
+                    lookfrom + offset,
+                    lookat,
+                    vup,
+                    vfov,
+                    aspect_ratio,
+                    aperture,
+                    distance,
+                )),
+                Convergence::OffAxis => Box::new(
+                    PerspectiveCamera::new(
+                        lookfrom + offset,
+                        lookat + offset,
+                        vup,
+                        vfov,
+                        aspect_ratio,
+                        aperture,
+                        distance,
+                    )
+                    .with_shift(-side * interocular / 2. / (distance * viewport_width), 0.),
+                ),
+            }
+        };
+        StereoCamera::new(eye(-1.), eye(1.), layout)
+    }
+
+    // Omnidirectional stereo panorama: every viewing direction gets its own
+    // pair of eyes `interocular` apart, as if turning the head to look there.
+    // Use OverUnder with an aspect ratio of 1, the usual layout for headsets.
+    pub fn equirectangular(
+        lookfrom: Vector3<f64>,
+        lookat: Vector3<f64>,
+        vup: Vector3<f64>,
+        interocular: f64,
+        layout: StereoLayout,
+    ) -> Self {
+        let eye = |side: f64| {
+            Box::new(
+                EquirectangularCamera::new(lookfrom, lookat, vup)
+                    .with_eye_offset(side * interocular / 2.),
+            )
+        };
+        StereoCamera::new(eye(-1.), eye(1.), layout)
+    }
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<Ray> {
+        self.sample_ray(s, t, rng).map(|(ray, _)| ray)
+    }
+
+    fn sample_ray(&self, s: f64, t: f64, rng: &mut RenderRng) -> Option<(Ray, Color)> {
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => self.left.sample_ray(2. * s, t, rng),
+            StereoLayout::SideBySide => self.right.sample_ray(2. * s - 1., t, rng),
+            StereoLayout::OverUnder if t >= 0.5 => self.left.sample_ray(s, 2. * t - 1., rng),
+            StereoLayout::OverUnder => self.right.sample_ray(s, 2. * t, rng),
+            StereoLayout::Anaglyph => {
+                // Pick one eye per sample, doubling its channels so that the
+                // average over both comes out right.
+                let (eye, mask) = if rng.gen::<bool>() {
+                    (&self.left, Color::new(2., 0., 0.))
+                } else {
+                    (&self.right, Color::new(0., 2., 2.))
+                };
+                eye.sample_ray(s, t, rng)
+                    .map(|(ray, weight)| (ray, weight.component_mul(&mask)))
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use std::f64::consts::PI;
 
-use js_sys::Math::{cos, sin, sqrt};
 use nalgebra::Vector3;
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
+
+// Random numbers of the renderer. Unlike ThreadRng it can be seeded and its
+// state saved, so a checkpointed render resumes exactly where it stopped.
+pub type RenderRng = rand_chacha::ChaCha12Rng;
 
 // This macro is retrived from https://github.com/lykhouzov/rust-wasm-webgl/blob/master/src/utils.rs
 #[macro_export]
@@ -33,6 +36,8 @@ macro_rules! uint_16_array {
 }
 
 // This macro is retrived from https://github.com/lykhouzov/rust-wasm-webgl/blob/master/src/utils.rs
+// Outside the browser it prints to stderr instead.
+#[cfg(target_arch = "wasm32")]
 #[macro_export]
 macro_rules! log {
     ( $( $t:tt )* ) => {
@@ -40,6 +45,27 @@ macro_rules! log {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+#[macro_export]
+macro_rules! log {
+    ( $( $t:tt )* ) => {
+        eprintln!( $( $t )* );
+    }
+}
+
+// Wall-clock time in seconds, for measuring durations
+#[cfg(target_arch = "wasm32")]
+pub fn now() -> f64 {
+    js_sys::Date::now() / 1000.
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0., |duration| duration.as_secs_f64())
+}
+
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     if x < min {
         min
@@ -50,17 +76,17 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     }
 }
 
-pub fn random_f64(rng: &mut ThreadRng, min: f64, max: f64) -> f64 {
+pub fn random_f64(rng: &mut RenderRng, min: f64, max: f64) -> f64 {
     rng.gen::<f64>() * (max - min) + min
 }
 
-pub fn random_vec3(rng: &mut ThreadRng) -> Vector3<f64> {
+pub fn random_vec3(rng: &mut RenderRng) -> Vector3<f64> {
     Vector3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>())
 }
 
 // different diffuse formulation
 // 1
-pub fn random_vec3_in_unit_spehere(rng: &mut ThreadRng) -> Vector3<f64> {
+pub fn random_vec3_in_unit_spehere(rng: &mut RenderRng) -> Vector3<f64> {
     loop {
         let v = random_vec3(rng);
         if sqnorm(v) < 1. {
@@ -70,11 +96,11 @@ pub fn random_vec3_in_unit_spehere(rng: &mut ThreadRng) -> Vector3<f64> {
 }
 
 // 2
-pub fn random_unit_vector(rng: &mut ThreadRng) -> Vector3<f64> {
+pub fn random_unit_vector(rng: &mut RenderRng) -> Vector3<f64> {
     let a = random_f64(rng, 0., 2. * PI);
     let z = random_f64(rng, -1., 1.);
-    let r = sqrt(1. - z * z);
-    Vector3::new(r * cos(a), r * sin(a), z)
+    let r = (1. - z * z).sqrt();
+    Vector3::new(r * a.cos(), r * a.sin(), z)
 }
 
 pub fn reflect(v: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64> {
@@ -89,7 +115,7 @@ pub fn near_zero(v: &Vector3<f64>) -> bool {
 pub fn refract(uv: &Vector3<f64>, n: &Vector3<f64>, etai_over_etat: f64) -> Vector3<f64> {
     let cos_theta = (-uv.dot(n)).min(1.);
     let r_out_parallel = etai_over_etat * (uv + cos_theta * n);
-    let r_out_perp = -sqrt((1.0 - sqnorm(r_out_parallel)).abs()) * n;
+    let r_out_perp = -(1.0 - sqnorm(r_out_parallel)).abs().sqrt() * n;
     r_out_parallel + r_out_perp
 }
 
@@ -104,6 +130,11 @@ pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1. - r0) * (1. - cosine).powf(5.)
 }
 
+// Rec. 709 luminance of a linear color
+pub fn luminance(c: &Vector3<f64>) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
 pub fn deg_to_rad(deg: f64) -> f64 {
     deg / 360. * 2. * PI
 }
@@ -112,7 +143,7 @@ pub fn rad_to_deg(rad: f64) -> f64 {
     rad * 180. / PI
 }
 
-pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Vector3<f64> {
+pub fn random_in_unit_disk(rng: &mut RenderRng) -> Vector3<f64> {
     loop {
         let p = Vector3::new(random_f64(rng, -1., 1.), random_f64(rng, -1., 1.), 0.);
         if sqnorm(p) < 1. {
@@ -120,3 +151,155 @@ pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Vector3<f64> {
         }
     }
 }
+
+// Orthonormal basis (u, v) perpendicular to the unit vector w.
+pub fn orthonormal_basis(w: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let a = if w.x.abs() > 0.9 {
+        Vector3::new(0., 1., 0.)
+    } else {
+        Vector3::new(1., 0., 0.)
+    };
+    let v = w.cross(&a).normalize();
+    let u = w.cross(&v);
+    (u, v)
+}
+
+// Uniformly distributed direction within the cone around the unit vector `axis`
+// whose half-angle has the cosine `cos_max`.
+pub fn random_in_cone(rng: &mut RenderRng, axis: &Vector3<f64>, cos_max: f64) -> Vector3<f64> {
+    let cos_theta = random_f64(rng, cos_max, 1.);
+    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+    let phi = random_f64(rng, 0., 2. * PI);
+    let (u, v) = orthonormal_basis(axis);
+    (u * phi.cos() + v * phi.sin()) * sin_theta + axis * cos_theta
+}
+
+// Möller–Trumbore ray/triangle intersection.
+// Returns t and the barycentric coordinates (b1, b2) of v1 and v2.
+pub fn intersect_triangle(
+    origin: &Vector3<f64>,
+    direction: &Vector3<f64>,
+    v0: &Vector3<f64>,
+    v1: &Vector3<f64>,
+    v2: &Vector3<f64>,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let pvec = direction.cross(&edge2);
+    let det = edge1.dot(&pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1. / det;
+    let tvec = origin - v0;
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(&edge1);
+    let b2 = direction.dot(&qvec) * inv_det;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+    Some((edge2.dot(&qvec) * inv_det, b1, b2))
+}
+
+// Width, height and samples in [0, 1] of a P2 or P5 PGM image.
+// See http://netpbm.sourceforge.net/doc/pgm.html
+pub fn parse_pgm(bytes: &[u8]) -> Result<(usize, usize, Vec<f32>), String> {
+    let mut pos = 0;
+    // header fields are whitespace separated and may be interleaved with # comments
+    let next_token = |pos: &mut usize| -> Result<String, String> {
+        loop {
+            while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+                *pos += 1;
+            }
+            if *pos < bytes.len() && bytes[*pos] == b'#' {
+                while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                    *pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = *pos;
+        while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if start == *pos {
+            return Err("unexpected end of PGM header".to_string());
+        }
+        Ok(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+    };
+    let number = |pos: &mut usize, name: &str| -> Result<usize, String> {
+        let token = next_token(pos)?;
+        token
+            .parse::<usize>()
+            .map_err(|_| format!("invalid PGM {}: {}", name, token))
+    };
+
+    let magic = next_token(&mut pos)?;
+    if magic != "P2" && magic != "P5" {
+        return Err(format!("not a PGM image: {}", magic));
+    }
+    let width = number(&mut pos, "width")?;
+    let height = number(&mut pos, "height")?;
+    let max_value = number(&mut pos, "maxval")?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(format!("invalid PGM maxval: {}", max_value));
+    }
+    let count = width
+        .checked_mul(height)
+        .ok_or(format!("PGM image too big: {}x{}", width, height))?;
+
+    let samples: Vec<usize> = if magic == "P2" {
+        (0..count)
+            .map(|_| number(&mut pos, "sample"))
+            .collect::<Result<_, _>>()?
+    } else {
+        // exactly one whitespace byte separates the header from the raster
+        let start = pos + 1;
+        let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+        let raster = count
+            .checked_mul(bytes_per_sample)
+            .and_then(|len| bytes.get(start..start.checked_add(len)?))
+            .ok_or("PGM raster is truncated")?;
+        if bytes_per_sample == 1 {
+            raster.iter().map(|&b| b as usize).collect()
+        } else {
+            raster
+                .chunks_exact(2)
+                .map(|b| ((b[0] as usize) << 8) | b[1] as usize)
+                .collect()
+        }
+    };
+
+    let heights = samples
+        .into_iter()
+        .map(|s| s as f32 / max_value as f32)
+        .collect();
+    Ok((width, height, heights))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pgm() {
+        let (width, height, samples) = parse_pgm(b"P2 # ascii\n2 1\n10\n0 5").unwrap();
+        assert_eq!((width, height, samples), (2, 1, vec![0., 0.5]));
+        let (_, _, samples) = parse_pgm(b"P5 2 1 65535\n\x00\x00\xff\xff").unwrap();
+        assert_eq!(samples, [0., 1.]);
+        for bytes in [
+            &b"P6 1 1 255 0"[..],
+            b"P2 1 1 0 0",
+            b"P2 2 1 255 0",
+            b"P5 2 1 255\n\x00",
+            b"P2 4294967296 4294967296 255 0",
+            b"P5 18446744073709551615 1 65535\n\x00",
+        ] {
+            assert!(parse_pgm(bytes).is_err(), "{:?}", bytes);
+        }
+    }
+}
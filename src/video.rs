@@ -0,0 +1,83 @@
+use std::io::{self, Write};
+
+// Uncompressed video in the YUV4MPEG2 format that ffmpeg and mpv read,
+// streamed frame by frame in full range 4:2:0.
+// See https://wiki.multimedia.cx/index.php/YUV4MPEG2
+pub struct Y4mWriter<W>
+where
+    W: Write,
+{
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W> Y4mWriter<W>
+where
+    W: Write,
+{
+    pub fn new(mut out: W, width: u32, height: u32, fps: u32) -> io::Result<Self> {
+        // players assume limited range unless the header says otherwise
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg XCOLORRANGE=FULL",
+            width, height, fps
+        )?;
+        Ok(Y4mWriter {
+            out,
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    // `rgb` holds the frame as 8-bit RGB triples, row by row from the top.
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let (width, height) = (self.width, self.height);
+        let pixel = |x: usize, y: usize| {
+            let i = 3 * (y * width + x);
+            [rgb[i] as f64, rgb[i + 1] as f64, rgb[i + 2] as f64]
+        };
+
+        // BT.601 with full range, as in JPEG
+        let luma = |[r, g, b]: [f64; 3]| 0.299 * r + 0.587 * g + 0.114 * b;
+        let mut y_plane = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                y_plane.push(luma(pixel(x, y)).round() as u8);
+            }
+        }
+
+        // chroma of the average of each 2x2 block
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let mut u_plane = Vec::with_capacity(chroma_width * chroma_height);
+        let mut v_plane = Vec::with_capacity(chroma_width * chroma_height);
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                let mut sum = [0.; 3];
+                let mut count = 0.;
+                for y in 2 * cy..(2 * cy + 2).min(height) {
+                    for x in 2 * cx..(2 * cx + 2).min(width) {
+                        for (s, c) in sum.iter_mut().zip(pixel(x, y)) {
+                            *s += c;
+                        }
+                        count += 1.;
+                    }
+                }
+                let [r, g, b] = sum.map(|s| s / count);
+                let y = luma([r, g, b]);
+                u_plane.push((128. + (b - y) / 1.772).round().clamp(0., 255.) as u8);
+                v_plane.push((128. + (r - y) / 1.402).round().clamp(0., 255.) as u8);
+            }
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&y_plane)?;
+        self.out.write_all(&u_plane)?;
+        self.out.write_all(&v_plane)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
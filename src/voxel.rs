@@ -0,0 +1,236 @@
+use nalgebra::Vector3;
+
+// 3D grid of density values with an optional emission channel.
+// Values are stored x fastest, then y, then z, and sampled at voxel centers.
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    density: Vec<f64>,
+    emission: Option<Vec<f64>>,
+    max_density: f64,
+}
+
+impl VoxelGrid {
+    // Panics if a dimension is 0 or `density` doesn't hold exactly
+    // nx * ny * nz values.
+    pub fn new(nx: usize, ny: usize, nz: usize, density: Vec<f64>) -> Self {
+        assert!(nx > 0 && ny > 0 && nz > 0, "empty voxel grid");
+        assert_eq!(density.len(), nx * ny * nz, "voxel count mismatch");
+        let max_density = density.iter().cloned().fold(0., f64::max);
+        VoxelGrid {
+            nx,
+            ny,
+            nz,
+            density,
+            emission: None,
+            max_density,
+        }
+    }
+
+    // Adds a per-voxel emission channel (e.g. temperature already mapped to intensity).
+    pub fn with_emission(mut self, emission: Vec<f64>) -> Self {
+        assert_eq!(emission.len(), self.density.len(), "voxel count mismatch");
+        self.emission = Some(emission);
+        self
+    }
+
+    // Parses the text format:
+    //
+    //     # comments start with '#'
+    //     nx ny nz channels
+    //     v v v ...
+    //
+    // followed by nx * ny * nz whitespace-separated voxels. With 2 channels every
+    // voxel is a `density emission` pair, with 1 it's just the density.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+
+        let mut header = |name: &str| -> Result<usize, String> {
+            let token = tokens
+                .next()
+                .ok_or(format!("missing header field {}", name))?;
+            token
+                .parse::<usize>()
+                .map_err(|_| format!("invalid header field {}: {}", name, token))
+        };
+        let nx = header("nx")?;
+        let ny = header("ny")?;
+        let nz = header("nz")?;
+        let channels = header("channels")?;
+        if channels != 1 && channels != 2 {
+            return Err(format!("unsupported channel count: {}", channels));
+        }
+
+        let values = tokens
+            .map(|token| {
+                token
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid voxel value: {}", token))
+            })
+            .collect::<Result<Vec<f64>, String>>()?;
+        let count = value_count([nx, ny, nz, channels])?;
+        if values.len() != count {
+            return Err(format!(
+                "expected {} voxel values, found {}",
+                count,
+                values.len()
+            ));
+        }
+
+        if channels == 2 {
+            let density = values.iter().step_by(2).cloned().collect();
+            let emission = values.iter().skip(1).step_by(2).cloned().collect();
+            Ok(VoxelGrid::new(nx, ny, nz, density).with_emission(emission))
+        } else {
+            Ok(VoxelGrid::new(nx, ny, nz, values))
+        }
+    }
+
+    // Parses raw little-endian f32 densities, as dumped by most simulation tools.
+    pub fn from_raw(nx: usize, ny: usize, nz: usize, bytes: &[u8]) -> Result<Self, String> {
+        let count = value_count([nx, ny, nz, 4])?;
+        if bytes.len() != count {
+            return Err(format!(
+                "expected {} bytes of f32 voxels, found {}",
+                count,
+                bytes.len()
+            ));
+        }
+        let density = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Ok(VoxelGrid::new(nx, ny, nz, density))
+    }
+
+    pub fn max_density(&self) -> f64 {
+        self.max_density
+    }
+
+    pub fn has_emission(&self) -> bool {
+        self.emission.is_some()
+    }
+
+    // Trilinearly interpolated density at p in [0, 1]^3 grid coordinates.
+    pub fn density(&self, p: &Vector3<f64>) -> f64 {
+        self.sample(&self.density, p)
+    }
+
+    pub fn emission(&self, p: &Vector3<f64>) -> f64 {
+        match &self.emission {
+            Some(emission) => self.sample(emission, p),
+            None => 0.,
+        }
+    }
+
+    fn voxel(&self, values: &[f64], x: isize, y: isize, z: isize) -> f64 {
+        // clamp to the edge voxels
+        let x = x.clamp(0, self.nx as isize - 1) as usize;
+        let y = y.clamp(0, self.ny as isize - 1) as usize;
+        let z = z.clamp(0, self.nz as isize - 1) as usize;
+        values[(z * self.ny + y) * self.nx + x]
+    }
+
+    fn sample(&self, values: &[f64], p: &Vector3<f64>) -> f64 {
+        // voxel centers sit at (i + 0.5) / n
+        let gx = p.x * self.nx as f64 - 0.5;
+        let gy = p.y * self.ny as f64 - 0.5;
+        let gz = p.z * self.nz as f64 - 0.5;
+        let (x0, y0, z0) = (gx.floor(), gy.floor(), gz.floor());
+        let (fx, fy, fz) = (gx - x0, gy - y0, gz - z0);
+        let (x0, y0, z0) = (x0 as isize, y0 as isize, z0 as isize);
+
+        let mut value = 0.;
+        for dz in 0..2 {
+            for dy in 0..2 {
+                for dx in 0..2 {
+                    let w = (if dx == 0 { 1. - fx } else { fx })
+                        * (if dy == 0 { 1. - fy } else { fy })
+                        * (if dz == 0 { 1. - fz } else { fz });
+                    value += w * self.voxel(values, x0 + dx, y0 + dy, z0 + dz);
+                }
+            }
+        }
+        value
+    }
+}
+
+// The product of the grid dimensions and the values per voxel, which the
+// files can't be trusted with
+fn value_count(factors: [usize; 4]) -> Result<usize, String> {
+    let [nx, ny, nz, _] = factors;
+    if nx == 0 || ny == 0 || nz == 0 {
+        return Err(format!("empty voxel grid: {}x{}x{}", nx, ny, nz));
+    }
+    factors
+        .into_iter()
+        .try_fold(1usize, usize::checked_mul)
+        .ok_or(format!("voxel grid too big: {}x{}x{}", nx, ny, nz))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_text() {
+        let grid = VoxelGrid::from_text("# a grid\n2 1 1 2 # size\n0.5 3\n1 4\n").unwrap();
+        assert_eq!((grid.nx, grid.ny, grid.nz), (2, 1, 1));
+        assert_eq!(grid.density, [0.5, 1.]);
+        assert_eq!(grid.emission, Some(vec![3., 4.]));
+        assert_eq!(grid.max_density(), 1.);
+        assert!(!VoxelGrid::from_text("1 1 1 1 2").unwrap().has_emission());
+    }
+
+    #[test]
+    fn parse_errors() {
+        for text in [
+            "",
+            "2 2",
+            "1 1 1 3 0 0 0",
+            "1 1 x 1 0",
+            "2 1 1 1 0",
+            "1 1 1 1 0 1",
+            "1 1 1 1 nan?",
+            "0 0 0 1",
+            "4294967296 4294967296 2 1",
+            "18446744073709551615 18446744073709551615 1 1",
+        ] {
+            assert!(VoxelGrid::from_text(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_raw() {
+        let bytes: Vec<u8> = [0.25f32, 2.].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let grid = VoxelGrid::from_raw(1, 2, 1, &bytes).unwrap();
+        assert_eq!(grid.density, [0.25, 2.]);
+        assert!(VoxelGrid::from_raw(1, 1, 1, &bytes).is_err());
+        assert!(VoxelGrid::from_raw(0, 2, 1, &[]).is_err());
+        assert!(VoxelGrid::from_raw(usize::MAX, 2, 1, &bytes).is_err());
+    }
+
+    #[test]
+    fn trilinear_sampling() {
+        // 0 and 1 along x, 0 and 2 along z
+        let grid = VoxelGrid::new(2, 1, 2, vec![0., 1., 2., 3.]);
+        let density = |x, y, z| grid.density(&Vector3::new(x, y, z));
+        // voxel centers
+        assert_eq!(density(0.25, 0.5, 0.25), 0.);
+        assert_eq!(density(0.75, 0.5, 0.25), 1.);
+        assert_eq!(density(0.25, 0.5, 0.75), 2.);
+        assert_eq!(density(0.75, 0.2, 0.75), 3.);
+        // halfway between centers
+        assert_eq!(density(0.5, 0.5, 0.25), 0.5);
+        assert_eq!(density(0.5, 0.5, 0.5), 1.5);
+        // the edges and beyond hold the values of the edge voxels
+        assert_eq!(density(0., 0., 0.), 0.);
+        assert_eq!(density(1., 1., 1.), 3.);
+        assert_eq!(density(-1., 0.5, 2.), 2.);
+        assert_eq!(grid.emission(&Vector3::repeat(0.5)), 0.);
+    }
+}